@@ -1,9 +1,11 @@
-use std::{fmt, io::{Error, ErrorKind}};
+use std::{cmp::Ordering, fmt, io::{Error, ErrorKind}};
 
 use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeDelta};
+use rand::{rngs::OsRng, RngCore};
 use serde::{Deserialize, Serialize};
 
-use crate::{database::MAX_STR_LEN, lexer_functions::Token};
+use crate::lexer_functions::Token;
 
 #[derive( Clone, PartialEq, Deserialize, Serialize)]
 pub enum AlbaTypes{
@@ -23,6 +25,12 @@ pub enum AlbaTypes{
     MediumBytes(Vec<u8>),
     BigSBytes(Vec<u8>),
     LargeBytes(Vec<u8>),
+    Timestamp(i64), // microseconds since the Unix epoch, UTC
+    Date(i32), // days since the Unix epoch
+    Uuid(u128),
+    Decimal(i128, u8, u8), // value scaled by 10^scale, precision, scale
+    Json(String),
+    Array(Vec<AlbaTypes>, u8), // elements, type id of the elements
     NONE
 }
 fn format_bytes_debug(
@@ -60,6 +68,12 @@ impl fmt::Debug for AlbaTypes {
             AlbaTypes::MediumBytes(bytes) => format_bytes_debug(f, "MediumBytes", bytes, 10),
             AlbaTypes::BigSBytes(bytes) => format_bytes_debug(f, "BigSBytes", bytes, 10),
             AlbaTypes::LargeBytes(bytes) => format_bytes_debug(f, "LargeBytes", bytes, 10),
+            AlbaTypes::Timestamp(t) => f.debug_tuple("Timestamp").field(&format_timestamp(*t)).finish(),
+            AlbaTypes::Date(d) => f.debug_tuple("Date").field(&format_date(*d)).finish(),
+            AlbaTypes::Uuid(u) => f.debug_tuple("Uuid").field(&format_uuid(*u)).finish(),
+            AlbaTypes::Decimal(v, _, s) => f.debug_tuple("Decimal").field(&format_decimal(*v, *s)).finish(),
+            AlbaTypes::Json(s) => f.debug_tuple("Json").field(s).finish(),
+            AlbaTypes::Array(items, _) => f.debug_tuple("Array").field(items).finish(),
             AlbaTypes::NONE => write!(f, "NONE"),
         }
     }
@@ -131,6 +145,24 @@ impl AlbaTypes {
             AlbaTypes::LargeBytes(_) => AlbaTypes::LargeBytes(Vec::new())
                 .try_from_existing(x.clone())
                 .unwrap_or(AlbaTypes::NONE),
+            AlbaTypes::Timestamp(_) => AlbaTypes::Timestamp(0)
+                .try_from_existing(x.clone())
+                .unwrap_or(AlbaTypes::NONE),
+            AlbaTypes::Date(_) => AlbaTypes::Date(0)
+                .try_from_existing(x.clone())
+                .unwrap_or(AlbaTypes::NONE),
+            AlbaTypes::Uuid(_) => AlbaTypes::Uuid(0)
+                .try_from_existing(x.clone())
+                .unwrap_or(AlbaTypes::NONE),
+            AlbaTypes::Decimal(_, p, s) => AlbaTypes::Decimal(0, *p, *s)
+                .try_from_existing(x.clone())
+                .unwrap_or(AlbaTypes::NONE),
+            AlbaTypes::Json(_) => AlbaTypes::Json(String::new())
+                .try_from_existing(x.clone())
+                .unwrap_or(AlbaTypes::NONE),
+            AlbaTypes::Array(_, element) => AlbaTypes::Array(Vec::new(), *element)
+                .try_from_existing(x.clone())
+                .unwrap_or(AlbaTypes::NONE),
             AlbaTypes::NONE => AlbaTypes::NONE,
         }
     }
@@ -155,6 +187,14 @@ impl AlbaTypes {
             14 => Ok(AlbaTypes::MediumBytes(Vec::new())),
             15 => Ok(AlbaTypes::BigSBytes(Vec::new())),
             16 => Ok(AlbaTypes::LargeBytes(Vec::new())),
+            17 => Ok(AlbaTypes::Timestamp(0)),
+            18 => Ok(AlbaTypes::Date(0)),
+            19 => Ok(AlbaTypes::Uuid(0)),
+            // the precision and the scale follow the id in the container header
+            20 => Ok(AlbaTypes::Decimal(0, 18, 0)),
+            21 => Ok(AlbaTypes::Json(String::new())),
+            // the type id of the elements follows the id in the container header
+            22 => Ok(AlbaTypes::Array(Vec::new(), 2)),
             x  => Err(Error::new(
                       ErrorKind::InvalidData,
                       format!("Unknown AlbaTypes code: {}", x)
@@ -180,6 +220,12 @@ impl AlbaTypes {
             AlbaTypes::MediumBytes(_)  => 14,
             AlbaTypes::BigSBytes(_)    => 15,
             AlbaTypes::LargeBytes(_)   => 16,
+            AlbaTypes::Timestamp(_)    => 17,
+            AlbaTypes::Date(_)         => 18,
+            AlbaTypes::Uuid(_)         => 19,
+            AlbaTypes::Decimal(..)     => 20,
+            AlbaTypes::Json(_)         => 21,
+            AlbaTypes::Array(..)       => 22,
         }
     }
     // pub fn get_id_from_text(keyword: &str) -> Result<u8, Error> {
@@ -214,7 +260,8 @@ impl AlbaTypes {
             AlbaTypes::Text(_) => {
                 let text = match i {
                     AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
-                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s,
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) |
+                    AlbaTypes::Json(s) => s,
                     AlbaTypes::Int(n) => n.to_string(),
                     AlbaTypes::Bigint(n) => n.to_string(),
                     AlbaTypes::Float(f) => f.to_string(),
@@ -224,6 +271,11 @@ impl AlbaTypes {
                     AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => {
                         general_purpose::STANDARD.encode(&b)
                     }
+                    AlbaTypes::Timestamp(t) => format_timestamp(t),
+                    AlbaTypes::Date(d) => format_date(d),
+                    AlbaTypes::Uuid(u) => format_uuid(u),
+                    AlbaTypes::Decimal(v, _, s) => format_decimal(v, s),
+                    AlbaTypes::Array(items, _) => format_array(&items),
                     AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Text")),
                 };
                 Ok(AlbaTypes::Text(text))
@@ -245,6 +297,9 @@ impl AlbaTypes {
                         f as i32
                     }
                     AlbaTypes::Bool(b) => if b { 1 } else { 0 },
+                    AlbaTypes::Decimal(v, _, s) => {
+                        i32::try_from(v / 10i128.pow(s as u32)).map_err(|_| Error::new(ErrorKind::InvalidData, "Decimal out of range for i32"))?
+                    }
                     AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
                     AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
                         s.parse::<i32>().map_err(|_| Error::new(ErrorKind::InvalidData, "Failed to parse string as i32"))?
@@ -265,6 +320,9 @@ impl AlbaTypes {
                         f as i64
                     }
                     AlbaTypes::Bool(b) => if b { 1 } else { 0 },
+                    AlbaTypes::Decimal(v, _, s) => {
+                        i64::try_from(v / 10i128.pow(s as u32)).map_err(|_| Error::new(ErrorKind::InvalidData, "Decimal out of range for i64"))?
+                    }
                     AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
                     AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
                         s.parse::<i64>().map_err(|_| Error::new(ErrorKind::InvalidData, "Failed to parse string as i64"))?
@@ -280,6 +338,7 @@ impl AlbaTypes {
                     AlbaTypes::Int(n) => n as f64,
                     AlbaTypes::Bigint(n) => n as f64,
                     AlbaTypes::Bool(b) => if b { 1.0 } else { 0.0 },
+                    AlbaTypes::Decimal(v, _, s) => decimal_to_float(v, s),
                     AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
                     AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
                         s.parse::<f64>().map_err(|_| Error::new(ErrorKind::InvalidData, "Failed to parse string as f64"))?
@@ -365,16 +424,146 @@ impl AlbaTypes {
                 let bytes = get_bytes_from_alba_type(i)?;
                 Ok(AlbaTypes::LargeBytes(truncate_or_pad_bytes(bytes, 1_000_000)))
             }
+            AlbaTypes::Timestamp(_) => {
+                let timestamp = match i {
+                    AlbaTypes::Timestamp(t) => t,
+                    AlbaTypes::Date(d) => d as i64 * MICROS_PER_DAY,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
+                        parse_timestamp(s.trim_end()).ok_or(Error::new(ErrorKind::InvalidData, "Failed to parse string as an ISO-8601 timestamp"))?
+                    }
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Timestamp")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Timestamp")),
+                };
+                Ok(AlbaTypes::Timestamp(timestamp))
+            }
+            AlbaTypes::Date(_) => {
+                let date = match i {
+                    AlbaTypes::Date(d) => d,
+                    // the time of the day is dropped
+                    AlbaTypes::Timestamp(t) => t.div_euclid(MICROS_PER_DAY) as i32,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
+                        let s = s.trim_end();
+                        match parse_date(s) {
+                            Some(d) => d,
+                            None => parse_timestamp(s).ok_or(Error::new(ErrorKind::InvalidData, "Failed to parse string as an ISO-8601 date"))?.div_euclid(MICROS_PER_DAY) as i32,
+                        }
+                    }
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Date")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Date")),
+                };
+                Ok(AlbaTypes::Date(date))
+            }
+            AlbaTypes::Uuid(_) => {
+                let uuid = match i {
+                    AlbaTypes::Uuid(u) => u,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
+                        parse_uuid(s.trim_end()).ok_or(Error::new(ErrorKind::InvalidData, "Failed to parse string as a UUID"))?
+                    }
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Uuid")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Uuid")),
+                };
+                Ok(AlbaTypes::Uuid(uuid))
+            }
+            AlbaTypes::Json(_) => {
+                let document = match i {
+                    AlbaTypes::Json(s) => return Ok(AlbaTypes::Json(s)),
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
+                        serde_json::from_str::<serde_json::Value>(s.trim_end())
+                            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid JSON: {}", e)))?
+                    }
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Json")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Json")),
+                };
+                // kept compact and with sorted keys, so equal documents have equal text
+                Ok(AlbaTypes::Json(document.to_string()))
+            }
+            AlbaTypes::Array(_, element) => {
+                let items = match i {
+                    AlbaTypes::Array(items, _) => items,
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) |
+                    AlbaTypes::Json(s) => parse_array(s.trim_end())?,
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Array")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Array")),
+                };
+                let element_type = AlbaTypes::from_id(*element)?;
+                let items = items.into_iter()
+                    .map(|item| match item {
+                        AlbaTypes::NONE => Err(Error::new(ErrorKind::InvalidData, "An array can't hold NULL")),
+                        item => element_type.try_from_existing(item),
+                    })
+                    .collect::<Result<Vec<AlbaTypes>, Error>>()?;
+                Ok(AlbaTypes::Array(items, *element))
+            }
+            AlbaTypes::Decimal(_, precision, scale) => {
+                let (value, from) = match i {
+                    AlbaTypes::Decimal(v, _, s) => (v, s),
+                    AlbaTypes::Int(n) => (n as i128, 0),
+                    AlbaTypes::Bigint(n) => (n as i128, 0),
+                    AlbaTypes::Float(f) => {
+                        if f.is_nan() || f.is_infinite() {
+                            return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NaN or infinite float to Decimal"));
+                        }
+                        // the shortest text that reads back as the float, so 0.1 becomes 0.1 and not 0.1000000000000000055
+                        parse_decimal(&f.to_string()).ok_or(Error::new(ErrorKind::InvalidData, "Float has too many digits for Decimal"))?
+                    }
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
+                    AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => {
+                        parse_decimal(s.trim()).ok_or(Error::new(ErrorKind::InvalidData, "Failed to parse string as a decimal"))?
+                    }
+                    AlbaTypes::NONE => return Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to Decimal")),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, "Unsupported conversion to Decimal")),
+                };
+                match rescale_decimal(value, from, *scale).filter(|v| v.unsigned_abs() < 10u128.pow(*precision as u32)) {
+                    Some(value) => Ok(AlbaTypes::Decimal(value, *precision, *scale)),
+                    None => Err(Error::new(ErrorKind::InvalidData, format!("The value {} does not fit in DECIMAL({},{})", format_decimal(value, from), precision, scale))),
+                }
+            }
             AlbaTypes::NONE => Ok(AlbaTypes::NONE),
         }
     }
+    /// Values kept in reference files, the row only holds the u64 id of the file.
+    pub fn is_referenced(&self) -> bool{
+        matches!(self, AlbaTypes::Text(_) | AlbaTypes::Json(_) | AlbaTypes::Array(..))
+    }
+    /// Scaled value and scale of a Decimal or of an integer.
+    pub fn as_decimal(&self) -> Option<(i128, u8)>{
+        match self {
+            AlbaTypes::Decimal(v, _, s) => Some((*v, *s)),
+            AlbaTypes::Int(i) => Some((*i as i128, 0)),
+            AlbaTypes::Bigint(i) => Some((*i as i128, 0)),
+            _ => None,
+        }
+    }
+    /// Value of a number of any type as a float, decimals through their text.
+    pub fn as_float(&self) -> Option<f64>{
+        match self {
+            AlbaTypes::Float(f) => Some(*f),
+            AlbaTypes::Int(i) => Some(*i as f64),
+            AlbaTypes::Bigint(i) => Some(*i as f64),
+            AlbaTypes::Decimal(v, _, s) => Some(decimal_to_float(*v, *s)),
+            _ => None,
+        }
+    }
+    /// Microseconds since the Unix epoch of a Timestamp or of the start of a Date.
+    pub fn as_timestamp(&self) -> Option<i64>{
+        match self {
+            AlbaTypes::Timestamp(t) => Some(*t),
+            AlbaTypes::Date(d) => Some(*d as i64 * MICROS_PER_DAY),
+            _ => None,
+        }
+    }
     pub fn size(&self) -> usize{
         match self {
             AlbaTypes::Bigint(_) => size_of::<i64>(),
             AlbaTypes::Int(_) => size_of::<i32>(),
             AlbaTypes::Float(_) => size_of::<f64>(),
             AlbaTypes::Bool(_) => size_of::<bool>(),
-            AlbaTypes::Text(_) => MAX_STR_LEN,
+            AlbaTypes::Text(_) => size_of::<u64>(), // reference id of the file in rf/
             AlbaTypes::NONE => 0,
             AlbaTypes::Char(_) => size_of::<char>(),
             AlbaTypes::NanoString(_) => 10 + size_of::<usize>(),
@@ -387,6 +576,12 @@ impl AlbaTypes {
             AlbaTypes::MediumBytes(_) => 10_000 + size_of::<usize>(),
             AlbaTypes::BigSBytes(_) => 100_000 + size_of::<usize>(),
             AlbaTypes::LargeBytes(_) => 1_000_000 + size_of::<usize>(),
+            AlbaTypes::Timestamp(_) => size_of::<i64>(),
+            AlbaTypes::Date(_) => size_of::<i32>(),
+            AlbaTypes::Uuid(_) => size_of::<u128>(),
+            AlbaTypes::Decimal(..) => size_of::<i128>(),
+            AlbaTypes::Json(_) => size_of::<u64>(), // reference id of the file in rf/
+            AlbaTypes::Array(..) => size_of::<u64>(), // reference id of the file in rf/
         }
     }
 
@@ -395,7 +590,8 @@ impl AlbaTypes {
 fn get_string_from_alba_type(i: AlbaTypes) -> Result<String, Error> {
     match i {
         AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) |
-        AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => Ok(s),
+        AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) |
+        AlbaTypes::Json(s) => Ok(s),
         AlbaTypes::Int(n) => Ok(n.to_string()),
         AlbaTypes::Bigint(n) => Ok(n.to_string()),
         AlbaTypes::Float(f) => Ok(f.to_string()),
@@ -405,10 +601,156 @@ fn get_string_from_alba_type(i: AlbaTypes) -> Result<String, Error> {
         AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b) => {
             Ok(general_purpose::STANDARD.encode(&b))
         }
+        AlbaTypes::Timestamp(t) => Ok(format_timestamp(t)),
+        AlbaTypes::Date(d) => Ok(format_date(d)),
+        AlbaTypes::Uuid(u) => Ok(format_uuid(u)),
+        AlbaTypes::Decimal(v, _, s) => Ok(format_decimal(v, s)),
+        AlbaTypes::Array(items, _) => Ok(format_array(&items)),
         AlbaTypes::NONE => Err(Error::new(ErrorKind::InvalidData, "Cannot convert NONE to string")),
     }
 }
 
+pub const MICROS_PER_DAY : i64 = 86_400_000_000;
+
+/// Microseconds since the Unix epoch of an ISO-8601 date-time like 2024-05-01T10:20:30.5+02:00,
+/// a date-time without an offset is taken as UTC.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Some(datetime.timestamp_micros())
+    }
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M"))
+        .ok()
+        .map(|datetime| datetime.and_utc().timestamp_micros())
+}
+
+/// Days since the Unix epoch of an ISO-8601 date like 2024-05-01.
+pub fn parse_date(text: &str) -> Option<i32> {
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    i32::try_from((date - DateTime::UNIX_EPOCH.date_naive()).num_days()).ok()
+}
+
+pub fn format_timestamp(timestamp: i64) -> String {
+    match DateTime::from_timestamp_micros(timestamp) {
+        Some(datetime) => datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        None => timestamp.to_string(),
+    }
+}
+
+pub fn format_date(date: i32) -> String {
+    match DateTime::UNIX_EPOCH.date_naive().checked_add_signed(TimeDelta::days(date as i64)) {
+        Some(date) => date.format("%Y-%m-%d").to_string(),
+        None => date.to_string(),
+    }
+}
+
+/// A UUID written as 32 hexadecimal digits, with or without the hyphens of 550e8400-e29b-41d4-a716-446655440000.
+pub fn parse_uuid(text: &str) -> Option<u128> {
+    let digits : String = text.chars().filter(|c| *c != '-').collect();
+    let hyphens = text.len() - digits.len();
+    if digits.len() != 32 || !(hyphens == 0 || (hyphens == 4 && [8, 13, 18, 23].iter().all(|i| text.as_bytes()[*i] == b'-'))) {
+        return None
+    }
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None
+    }
+    u128::from_str_radix(&digits, 16).ok()
+}
+
+pub fn format_uuid(uuid: u128) -> String {
+    let hex = format!("{:032x}", uuid);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Random version 4 UUID.
+pub fn generate_uuid() -> u128 {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    u128::from_be_bytes(bytes)
+}
+
+/// Most digits of a DECIMAL, every value of them fits in an i128.
+pub const DECIMAL_MAX_PRECISION : u8 = 38;
+
+/// Scaled value and scale of a number like -12.50, which is (-1250, 2).
+pub fn parse_decimal(text: &str) -> Option<(i128, u8)> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) || whole.len() + fraction.len() > DECIMAL_MAX_PRECISION as usize {
+        return None
+    }
+    let value = format!("{}{}", whole, fraction).parse::<i128>().unwrap_or(0);
+    Some((if negative { -value } else { value }, fraction.len() as u8))
+}
+
+pub fn format_decimal(value: i128, scale: u8) -> String {
+    let digits = format!("{:0>width$}", value.unsigned_abs(), width = scale as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - scale as usize);
+    let sign = if value < 0 { "-" } else { "" };
+    if fraction.is_empty() { format!("{}{}", sign, whole) } else { format!("{}{}.{}", sign, whole, fraction) }
+}
+
+/// The scaled value with `to` digits after the point instead of `from`, rounded half away from zero.
+pub fn rescale_decimal(value: i128, from: u8, to: u8) -> Option<i128> {
+    if to >= from {
+        return value.checked_mul(10i128.checked_pow((to - from) as u32)?)
+    }
+    let divisor = 10i128.checked_pow((from - to) as u32)?;
+    let (quotient, remainder) = (value / divisor, value % divisor);
+    Some(if remainder.unsigned_abs() * 2 >= divisor.unsigned_abs() { quotient + value.signum() } else { quotient })
+}
+
+/// Orders two scaled values of any scale.
+pub fn compare_decimals(a: (i128, u8), b: (i128, u8)) -> Ordering {
+    let scale = a.1.max(b.1);
+    match (rescale_decimal(a.0, a.1, scale), rescale_decimal(b.0, b.1, scale)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        // one of them is too large to be scaled, so it is the one further from zero
+        _ => decimal_to_float(a.0, a.1).total_cmp(&decimal_to_float(b.0, b.1)),
+    }
+}
+
+pub fn decimal_to_float(value: i128, scale: u8) -> f64 {
+    format_decimal(value, scale).parse::<f64>().unwrap_or(0.0)
+}
+
+/// Type ids of the elements an ARRAY can hold: INT, BIGINT, FLOAT and NANO-STRING.
+pub const ARRAY_ELEMENT_IDS : [u8; 4] = [2, 3, 5, 7];
+
+/// Elements of a JSON array of numbers and strings like [1, 2.5, "a"], as Bigint, Float and Text.
+pub fn parse_array(text: &str) -> Result<Vec<AlbaTypes>, Error> {
+    let items = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Array(items)) => items,
+        _ => return Err(Error::new(ErrorKind::InvalidData, format!("Expected an array like [1, 2, 3], found {}", text))),
+    };
+    items.into_iter().map(|item| match item {
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Ok(AlbaTypes::Bigint(i)),
+            None => Ok(AlbaTypes::Float(n.as_f64().unwrap_or(0.0))),
+        },
+        serde_json::Value::String(s) => Ok(AlbaTypes::Text(s)),
+        other => Err(Error::new(ErrorKind::InvalidData, format!("An array can only hold numbers and strings, found {}", other))),
+    }).collect()
+}
+
+/// The elements as a JSON array, the padding of the strings is left out.
+pub fn format_array(items: &[AlbaTypes]) -> String {
+    serde_json::Value::Array(items.iter().map(|item| match item {
+        AlbaTypes::Int(i) => serde_json::Value::from(*i),
+        AlbaTypes::Bigint(i) => serde_json::Value::from(*i),
+        AlbaTypes::Float(f) => serde_json::Value::from(*f),
+        other => serde_json::Value::from(get_string_from_alba_type(other.clone()).unwrap_or_default().trim_end()),
+    }).collect()).to_string()
+}
+
 fn truncate_or_pad_string(s: String, max_len: usize) -> String {
     if s.len() > max_len {
         s[..max_len].to_string()
@@ -483,6 +825,22 @@ impl TryFrom<Token> for AlbaTypes {
 
             Token::Bool(b) =>
                 Ok(AlbaTypes::Bool(b)),
+
+            Token::Timestamp(t) =>
+                Ok(AlbaTypes::Timestamp(t)),
+
+            Token::Date(d) =>
+                Ok(AlbaTypes::Date(d)),
+
+            Token::Uuid(u) =>
+                Ok(AlbaTypes::Uuid(u)),
+
+            Token::Decimal(v, s) =>
+                Ok(AlbaTypes::Decimal(v, DECIMAL_MAX_PRECISION, s)),
+
+            // the elements take the type of the column they are written to
+            Token::Group(items) =>
+                Ok(AlbaTypes::Array(items.into_iter().map(AlbaTypes::try_from).collect::<Result<_, _>>()?, 0)),
             Token::Keyword(s) => match s.to_uppercase().as_str().trim() {
                 "INT" => Ok(AlbaTypes::Int(0)),        // default dummy values
                 "BIGINT" => Ok(AlbaTypes::Bigint(0)),
@@ -499,10 +857,16 @@ impl TryFrom<Token> for AlbaTypes {
                 "MEDIUM-BYTES" => Ok(AlbaTypes::MediumBytes(Vec::new())),
                 "BIG-BYTES" => Ok(AlbaTypes::BigSBytes(Vec::new())),
                 "LARGE-BYTES" => Ok(AlbaTypes::LargeBytes(Vec::new())),
+                "TIMESTAMP" => Ok(AlbaTypes::Timestamp(0)),
+                "DATE" => Ok(AlbaTypes::Date(0)),
+                "UUID" => Ok(AlbaTypes::Uuid(0)),
+                "DECIMAL" => Ok(AlbaTypes::Decimal(0, 18, 0)),
+                "JSON" => Ok(AlbaTypes::Json(String::new())),
+                "NULL" => Ok(AlbaTypes::NONE),
                 _ => return Err(format!("Unknown type keyword: {}", s).leak()),
             },
             _ => {
-                let va = format!("Cannot convert token to AlbaTypes: unsupported token type {:#?}. Expected one of: String, Int, Float, Bool, or Keyword (for type definitions).", token);
+                let va = format!("Cannot convert token to AlbaTypes: unsupported token type {:#?}. Expected one of: String, Int, Float, Bool, Timestamp, Date, Uuid, Decimal, Group, or Keyword (for type definitions).", token);
                 return Err(va.leak());
             }
         }
@@ -0,0 +1,249 @@
+use std::{collections::HashMap, io::Error};
+
+use crate::{alba_types::{decimal_to_float, parse_decimal, rescale_decimal, AlbaTypes, DECIMAL_MAX_PRECISION}, gerr};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl ArithmeticOperator {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        Some(match symbol {
+            "+" => ArithmeticOperator::Add,
+            "-" => ArithmeticOperator::Subtract,
+            "*" => ArithmeticOperator::Multiply,
+            "/" => ArithmeticOperator::Divide,
+            "%" => ArithmeticOperator::Remainder,
+            _ => return None,
+        })
+    }
+    /// `*`, `/` and `%` bind tighter than `+` and `-`
+    pub fn precedence(&self) -> u8 {
+        match self {
+            ArithmeticOperator::Add | ArithmeticOperator::Subtract => 1,
+            _ => 2,
+        }
+    }
+}
+
+/// Step of a JSON path, the key of an object or the position in an array.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonKey {
+    Field(String),
+    Index(usize),
+}
+
+/// Arithmetic over the values of a row, like `'price' * 'qty'` or `'counter' + 1`.
+/// Inside an expression a quoted name is a column, not a text value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Column(String),
+    Value(AlbaTypes),
+    Operation(Box<Expression>, ArithmeticOperator, Box<Expression>),
+    // `'doc'->'user'->>'id'`, the flag is set when the path ends with ->> and yields a plain value
+    JsonPath(Box<Expression>, Vec<JsonKey>, bool),
+}
+
+impl Expression {
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Expression::Column(name) => vec![name.as_str()],
+            Expression::Value(_) => Vec::new(),
+            Expression::JsonPath(document, ..) => document.columns(),
+            Expression::Operation(left, _, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+        }
+    }
+
+    /// Columns read as JSON documents through -> and ->>.
+    pub fn json_documents(&self) -> Vec<&str> {
+        match self {
+            Expression::JsonPath(document, ..) => match document.as_ref() {
+                Expression::Column(name) => vec![name.as_str()],
+                other => other.json_documents(),
+            },
+            Expression::Column(_) | Expression::Value(_) => Vec::new(),
+            Expression::Operation(left, _, right) => {
+                let mut columns = left.json_documents();
+                columns.extend(right.json_documents());
+                columns
+            }
+        }
+    }
+
+    pub fn columns_mut(&mut self, f: &mut impl FnMut(&mut String) -> Result<(), Error>) -> Result<(), Error> {
+        match self {
+            Expression::Column(name) => f(name),
+            Expression::Value(_) => Ok(()),
+            Expression::JsonPath(document, ..) => document.columns_mut(f),
+            Expression::Operation(left, _, right) => {
+                left.columns_mut(f)?;
+                right.columns_mut(f)
+            }
+        }
+    }
+
+    /// Computes the expression over `row`, NULL when any of the values it uses is NULL.
+    pub fn evaluate(&self, row: &HashMap<String, AlbaTypes>) -> Result<AlbaTypes, Error> {
+        match self {
+            Expression::Column(name) => match row.get(name) {
+                Some(value) => Ok(value.clone()),
+                None => Err(gerr(&format!("There is no column named {}", name))),
+            },
+            Expression::Value(value) => Ok(value.clone()),
+            Expression::Operation(left, operator, right) => {
+                apply(*operator, left.evaluate(row)?, right.evaluate(row)?)
+            }
+            Expression::JsonPath(document, path, plain) => {
+                let document = match document.evaluate(row)? {
+                    AlbaTypes::NONE => return Ok(AlbaTypes::NONE),
+                    AlbaTypes::Json(text) => text,
+                    other => return Err(gerr(&format!("-> and ->> can only be used on JSON values, found {:?}", other))),
+                };
+                let document : serde_json::Value = serde_json::from_str(&document).map_err(|e| gerr(&format!("Invalid JSON: {}", e)))?;
+                let mut value = &document;
+                for key in path {
+                    let next = match (key, value) {
+                        (JsonKey::Field(field), serde_json::Value::Object(object)) => object.get(field),
+                        (JsonKey::Index(index), serde_json::Value::Array(array)) => array.get(*index),
+                        _ => None,
+                    };
+                    // a missing key is NULL, like a JSON null
+                    value = match next {
+                        Some(next) => next,
+                        None => return Ok(AlbaTypes::NONE),
+                    };
+                }
+                Ok(if *plain { json_value(value) } else { AlbaTypes::Json(value.to_string()) })
+            }
+        }
+    }
+}
+
+/// A JSON value as a plain one: strings become Text, numbers Bigint or an exact Decimal, objects and arrays their text.
+fn json_value(value: &serde_json::Value) -> AlbaTypes {
+    match value {
+        serde_json::Value::Null => AlbaTypes::NONE,
+        serde_json::Value::Bool(b) => AlbaTypes::Bool(*b),
+        serde_json::Value::String(s) => AlbaTypes::Text(s.clone()),
+        serde_json::Value::Number(n) => match (n.as_i64(), parse_decimal(&n.to_string())) {
+            (Some(i), _) => AlbaTypes::Bigint(i),
+            (None, Some((v, s))) => AlbaTypes::Decimal(v, DECIMAL_MAX_PRECISION, s),
+            (None, None) => AlbaTypes::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        other => AlbaTypes::Text(other.to_string()),
+    }
+}
+
+/// Int with Int stays Int, Int with Bigint becomes Bigint, a Decimal with an integer or a Decimal stays exact
+/// and anything with a Float becomes Float.
+pub fn apply(operator: ArithmeticOperator, left: AlbaTypes, right: AlbaTypes) -> Result<AlbaTypes, Error> {
+    match (left, right) {
+        (AlbaTypes::NONE, _) | (_, AlbaTypes::NONE) => Ok(AlbaTypes::NONE),
+        (AlbaTypes::Int(a), AlbaTypes::Int(b)) => {
+            let result = match operator {
+                ArithmeticOperator::Add => a.checked_add(b),
+                ArithmeticOperator::Subtract => a.checked_sub(b),
+                ArithmeticOperator::Multiply => a.checked_mul(b),
+                ArithmeticOperator::Divide => a.checked_div(b),
+                ArithmeticOperator::Remainder => a.checked_rem(b),
+            };
+            integer_result(operator, b == 0, result).map(AlbaTypes::Int)
+        }
+        (a @ (AlbaTypes::Int(_) | AlbaTypes::Bigint(_)), b @ (AlbaTypes::Int(_) | AlbaTypes::Bigint(_))) => {
+            let (a, b) = (as_bigint(&a), as_bigint(&b));
+            let result = match operator {
+                ArithmeticOperator::Add => a.checked_add(b),
+                ArithmeticOperator::Subtract => a.checked_sub(b),
+                ArithmeticOperator::Multiply => a.checked_mul(b),
+                ArithmeticOperator::Divide => a.checked_div(b),
+                ArithmeticOperator::Remainder => a.checked_rem(b),
+            };
+            integer_result(operator, b == 0, result).map(AlbaTypes::Bigint)
+        }
+        (a @ (AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Decimal(..)), b @ (AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Decimal(..))) => {
+            decimal_result(operator, a.as_decimal().unwrap_or_default(), b.as_decimal().unwrap_or_default())
+        }
+        (a @ (AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Float(_) | AlbaTypes::Decimal(..)), b @ (AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Float(_) | AlbaTypes::Decimal(..))) => {
+            let (a, b) = (as_float(&a), as_float(&b));
+            Ok(AlbaTypes::Float(match operator {
+                ArithmeticOperator::Add => a + b,
+                ArithmeticOperator::Subtract => a - b,
+                ArithmeticOperator::Multiply => a * b,
+                ArithmeticOperator::Divide => a / b,
+                ArithmeticOperator::Remainder => a % b,
+            }))
+        }
+        (a, b) => Err(gerr(&format!("Arithmetic needs INT, BIGINT, FLOAT or DECIMAL values, found {:?} and {:?}", a, b))),
+    }
+}
+
+/// Sums keep the larger scale of the two values, products the sum of both scales and quotients six more digits.
+fn decimal_result(operator: ArithmeticOperator, (a, a_scale): (i128, u8), (b, b_scale): (i128, u8)) -> Result<AlbaTypes, Error> {
+    if b == 0 && matches!(operator, ArithmeticOperator::Divide | ArithmeticOperator::Remainder) {
+        return Err(gerr("Division by zero"))
+    }
+    let (value, scale) = match operator {
+        ArithmeticOperator::Multiply => (a.checked_mul(b), a_scale + b_scale),
+        ArithmeticOperator::Divide => {
+            let scale = (a_scale.max(b_scale) + 6).min(DECIMAL_MAX_PRECISION);
+            // a / b with `scale` digits is a * 10^(scale - a_scale + b_scale) / b, rounded half away from zero
+            let quotient = rescale_decimal(a, a_scale, scale + b_scale).map(|n| {
+                let (quotient, remainder) = (n / b, n % b);
+                if remainder.unsigned_abs() * 2 >= b.unsigned_abs() { quotient + n.signum() * b.signum() } else { quotient }
+            });
+            (quotient, scale)
+        }
+        _ => {
+            let scale = a_scale.max(b_scale);
+            let value = rescale_decimal(a, a_scale, scale).zip(rescale_decimal(b, b_scale, scale)).and_then(|(x, y)| match operator {
+                ArithmeticOperator::Add => x.checked_add(y),
+                ArithmeticOperator::Subtract => x.checked_sub(y),
+                _ => x.checked_rem(y),
+            });
+            (value, scale)
+        }
+    };
+    let value = match value {
+        Some(value) if scale > DECIMAL_MAX_PRECISION => rescale_decimal(value, scale, DECIMAL_MAX_PRECISION),
+        value => value,
+    };
+    match value {
+        Some(value) => Ok(AlbaTypes::Decimal(value, DECIMAL_MAX_PRECISION, scale.min(DECIMAL_MAX_PRECISION))),
+        None => Err(gerr("Arithmetic overflow")),
+    }
+}
+
+fn integer_result<T>(operator: ArithmeticOperator, zero_divisor: bool, result: Option<T>) -> Result<T, Error> {
+    match result {
+        Some(value) => Ok(value),
+        None if zero_divisor && matches!(operator, ArithmeticOperator::Divide | ArithmeticOperator::Remainder) => Err(gerr("Division by zero")),
+        None => Err(gerr("Arithmetic overflow")),
+    }
+}
+
+fn as_bigint(value: &AlbaTypes) -> i64 {
+    match value {
+        AlbaTypes::Int(v) => *v as i64,
+        AlbaTypes::Bigint(v) => *v,
+        _ => unreachable!(),
+    }
+}
+
+fn as_float(value: &AlbaTypes) -> f64 {
+    match value {
+        AlbaTypes::Int(v) => *v as f64,
+        AlbaTypes::Bigint(v) => *v as f64,
+        AlbaTypes::Float(v) => *v,
+        AlbaTypes::Decimal(v, _, s) => decimal_to_float(*v, *s),
+        _ => unreachable!(),
+    }
+}
@@ -1,24 +1,74 @@
 
-use std::{collections::{BTreeSet, HashMap}, ffi::CString, io::{self, Error, ErrorKind, Write}, mem::discriminant, os::unix::fs::FileExt, sync::Arc};
+use std::{collections::{BTreeSet, HashMap}, ffi::CString, io::{self, Error, ErrorKind}, os::unix::fs::FileExt, path::Path, sync::Arc};
 use ahash::AHashMap;
 use tokio::{io::AsyncReadExt, sync::Mutex};
 use tokio::fs::{File,self};
-use crate::{alba_types::AlbaTypes, database::write_data, gerr, indexing::{Add, GetIndex, Indexing, Remove}, logerr, loginfo};
+use rand::{rngs::OsRng, RngCore};
+use crate::{alba_types::{format_array, AlbaTypes}, database::write_data, gerr, indexing::{Add, GetIndex, Indexing, Remove, Search}, wal::{WalEntry, WalOperation}};
 
 
-type MvccType = Arc<Mutex<(AHashMap<u64,(bool,Vec<AlbaTypes>)>,HashMap<String,(bool,String)>)>>;
+/// Id of the client session a transaction belongs to
+pub type SessionId = [u8; 32];
+/// Session the database is in before a client runs a statement
+pub const LOCAL_SESSION : SessionId = [0u8; 32];
+/// Rows staged by index, true when staged for delete
+pub type StagedRows = AHashMap<u64,(bool,Vec<AlbaTypes>)>;
+/// Changes of a session that aren't committed yet: the staged rows, the reference files of their TEXT values
+/// and the indexes of the rows it inserted into free slots
+pub type WriteSet = (StagedRows,HashMap<String,(bool,String)>,BTreeSet<u64>);
+type MvccType = Arc<Mutex<AHashMap<SessionId,WriteSet>>>;
+
+/// Committed versions of the rows by row index, as (commit timestamp, row before that commit) oldest first.
+/// The row is None when the slot was empty. A snapshot older than a commit reads the row from before it.
+type VersionChains = Arc<Mutex<AHashMap<u64,Vec<(u64,Option<Vec<AlbaTypes>>)>>>>;
+
+/// Write-ahead log entry built from the write set of a session, with the row slots it fills and frees
+/// and the rows they held before.
+pub struct PreparedCommit{
+    pub entry : WalEntry,
+    inserted : Vec<u64>,
+    deleted : Vec<u64>,
+    file_rows : u64,
+    previous : Vec<(u64,Option<Vec<AlbaTypes>>)>,
+}
 #[derive(Debug)]
 pub struct Container{
     pub file : Arc<Mutex<std::fs::File>>,
     pub element_size : usize,
     pub headers : Vec<(String,AlbaTypes)>,
-    pub str_size : usize,
     pub mvcc : MvccType,
+    pub versions : VersionChains,
     pub headers_offset : u64,
     pub location : String,
     pub graveyard : Arc<Mutex<BTreeSet<u64>>>,
-    pub indexing : Arc<Indexing>
+    pub indexing : Arc<Indexing>,
+    pub name : String,
+    pub nullable : Vec<bool>,
+    pub unique : Vec<bool>,
+    pub secondary_indexes : Vec<SecondaryIndex>,
+}
+
+/// An index created with CREATE INDEX, or kept by the database over a UNIQUE column, over a column other than the primary key
+#[derive(Debug)]
+pub struct SecondaryIndex{
+    pub name : String,
+    pub column : usize,
+    pub indexing : Arc<Indexing>,
+}
+
+/// Bit set on the type id of a column header when the column accepts NULL
+pub const NULLABLE_FLAG : u8 = 0x80;
+/// Bit set on the type id of a column header when the column was declared UNIQUE
+pub const UNIQUE_FLAG : u8 = 0x40;
 
+/// Size of the null bitmap that prefixes every row, bit `i` is set when column `i` is NULL.
+/// Containers without nullable columns have no bitmap at all.
+pub fn null_bitmap_size(nullable : &[bool]) -> usize {
+    if nullable.iter().any(|n| *n) {
+        nullable.len().div_ceil(8)
+    } else {
+        0
+    }
 }
 fn serialize_closed_string(item : &AlbaTypes,s : &String,buffer : &mut Vec<u8>){
     let mut bytes = Vec::with_capacity(item.size());
@@ -42,8 +92,10 @@ fn serialize_closed_blob(item : &AlbaTypes,blob : &mut Vec<u8>,buffer : &mut Vec
 
 
 impl Container {
-    pub async fn new(container_name : String,path : &str,location : String,element_size : usize, columns : Vec<AlbaTypes>,str_size : usize,headers_offset : u64,column_names : Vec<String>) -> Result<Arc<Mutex<Self>>,Error> {
+    pub async fn new(container_name : String,path : &str,location : String,element_size : usize, columns : Vec<AlbaTypes>,headers_offset : u64,column_names : Vec<String>,nullable : Vec<bool>,unique : Vec<bool>) -> Result<Arc<Mutex<Self>>,Error> {
         let mut  headers = Vec::new();
+        let mut nullable_headers = Vec::new();
+        let mut unique_headers = Vec::new();
         for index in 0..((columns.len()+column_names.len())/2){
             let name = match column_names.get(index){
                 Some(nm) => nm,
@@ -64,6 +116,8 @@ impl Container {
                 continue
             }
             headers.push((name.to_owned(), value.to_owned()));
+            nullable_headers.push(nullable.get(index).copied().unwrap_or(false));
+            unique_headers.push(unique.get(index).copied().unwrap_or(false));
         }
         let file = Arc::new(Mutex::new(std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap()));
         let mut hash_header = HashMap::new();
@@ -73,13 +127,17 @@ impl Container {
         let container = Arc::new(Mutex::new(Container{
             file:file.clone(),
             element_size: element_size.clone(),
-            str_size,
-            mvcc: Arc::new(Mutex::new((AHashMap::new(),HashMap::new()))),
+            mvcc: Arc::new(Mutex::new(AHashMap::new())),
+            versions: Arc::new(Mutex::new(AHashMap::new())),
             headers_offset: headers_offset.clone() ,
             headers,
             location,
             graveyard: Arc::new(Mutex::new(BTreeSet::new())),
-            indexing:Indexing::load_index(&container_name).await.unwrap()
+            indexing:Indexing::load_index(&container_name).await.unwrap(),
+            name:container_name,
+            nullable: nullable_headers,
+            unique: unique_headers,
+            secondary_indexes: Vec::new(),
         }));
         Ok(container)
     }
@@ -89,6 +147,51 @@ impl Container{
     pub fn column_names(&self) -> Vec<String>{
         self.headers.iter().map(|v|v.0.to_string()).collect()
     }
+    /// Every index of the container as (column name, index), the primary key index comes first.
+    pub fn indexes(&self) -> Vec<(String, Arc<Indexing>)>{
+        let mut indexes = Vec::with_capacity(self.secondary_indexes.len() + 1);
+        if let Some(pk) = self.headers.first(){
+            indexes.push((pk.0.clone(), self.indexing.clone()));
+        }
+        for index in self.secondary_indexes.iter(){
+            indexes.push((self.headers[index.column].0.clone(), index.indexing.clone()));
+        }
+        indexes
+    }
+    /// Index operations that add (or remove) the row stored at `offset` to every index of the container.
+    fn index_operations(&self, row : &[AlbaTypes], offset : u64, add : bool) -> Vec<WalOperation>{
+        let mut operations = Vec::new();
+        if let Some(arg) = row.first().filter(|v| **v != AlbaTypes::NONE){
+            let key = arg.get_index();
+            operations.push(if add { WalOperation::IndexAdd { key, offset } } else { WalOperation::IndexRemove { key, offset } });
+        }
+        for index in self.secondary_indexes.iter(){
+            if let Some(arg) = row.get(index.column).filter(|v| **v != AlbaTypes::NONE){
+                let (index, key) = (index.name.clone(), arg.get_index());
+                operations.push(if add { WalOperation::SecondaryIndexAdd { index, key, offset } } else { WalOperation::SecondaryIndexRemove { index, key, offset } });
+            }
+        }
+        operations
+    }
+    /// Row currently stored at `offset`, None when the slot is past the end of the file or empty.
+    async fn stored_row(&self, offset : u64) -> Result<Option<Vec<AlbaTypes>>, Error>{
+        let mut previous = vec![0u8; self.element_size];
+        if offset + self.element_size as u64 > self.len().await? {
+            return Ok(None)
+        }
+        self.file.lock().await.read_exact_at(&mut previous, offset)?;
+        if previous.iter().all(|b| *b == 0) {
+            return Ok(None)
+        }
+        Ok(Some(self.deserialize_row(&previous).await?))
+    }
+    /// Index operations that remove the row currently stored at `offset`, if there is one.
+    async fn stored_index_removals(&self, offset : u64) -> Result<Vec<WalOperation>, Error>{
+        Ok(match self.stored_row(offset).await? {
+            Some(previous) => self.index_operations(&previous, offset, false),
+            None => Vec::new(),
+        })
+    }
 }
 
 async fn try_open_file(path: &str) -> io::Result<Option<File>> {
@@ -116,6 +219,7 @@ fn handle_fixed_string(buf: &[u8],index: &mut usize,instance_size: usize,values:
         
     }else{
         
+        *index += instance_size;
         let s = String::new();
         match instance_size {
             18 => values.push(AlbaTypes::NanoString(s)),
@@ -160,6 +264,7 @@ fn handle_bytes(buf: &[u8],index: &mut usize,size: usize,values: &mut Vec<AlbaTy
         
     }else{
         
+        *index += size;
         let blob = Vec::new();
         match size {
             18 => values.push(AlbaTypes::NanoBytes(blob)),
@@ -189,16 +294,21 @@ impl Container{
     pub async fn len(&self) -> Result<u64,Error>{
         Ok(self.file.lock().await.metadata()?.len())
     }
-    pub async fn arrlen(&self) -> Result<u64, Error> {
+    /// Count of the row slots written to the file.
+    async fn stored_rows(&self) -> Result<u64, Error> {
         let file_len = self.len().await?;
-        let file_rows = if file_len > self.headers_offset {
+        Ok(if file_len > self.headers_offset {
             (file_len - self.headers_offset) / self.element_size as u64
         } else {
             0
-        };
+        })
+    }
+    pub async fn arrlen(&self) -> Result<u64, Error> {
+        let file_rows = self.stored_rows().await?;
+        // rows staged by any session hold their index already
         let mvcc_max = {
             let mvcc = self.mvcc.lock().await;
-            mvcc.0.keys().copied().max().map_or(0, |max_index| max_index + 1)
+            mvcc.values().flat_map(|w| w.0.keys()).copied().max().map_or(0, |max_index| max_index + 1)
         };
         Ok(file_rows.max(mvcc_max))
     }
@@ -222,38 +332,176 @@ impl Container{
     //     None
     // }
 
-    pub async fn get_next_addr(&self) -> Result<u64, Error> {
-        let mut graveyard = self.graveyard.lock().await;
-        if graveyard.len() > 0{
-            if let Some(id) = graveyard.pop_first(){
-                return Ok(id)
-            }
-        }
+    pub async fn get_next_addr(&self, session : &SessionId) -> Result<u64, Error> {
+        let graveyard = self.graveyard.lock().await;
         let current_rows = self.arrlen().await?;
         let mvcc = self.mvcc.lock().await;
-        for (&key, (deleted, _)) in mvcc.0.iter() {
-            if *deleted {
-                return Ok(key);
+        // a free slot leaves the graveyard on commit, so a rollback doesn't lose it
+        if let Some(id) = graveyard.iter().find(|id| !mvcc.values().any(|w| w.0.contains_key(id))){
+            return Ok(*id)
+        }
+        if let Some(writes) = mvcc.get(session){
+            for (&key, (deleted, _)) in writes.0.iter() {
+                if *deleted {
+                    return Ok(key);
+                }
             }
         }
         Ok(current_rows)
     } 
-    pub async fn push_row(&mut self, data : &Vec<AlbaTypes>) -> Result<(),Error>{
-        let ind = self.get_next_addr().await?;
+    pub async fn push_row(&mut self, session : &SessionId, data : &[AlbaTypes]) -> Result<(),Error>{
+        let ind = self.get_next_addr(session).await?;
+        let saved = self.write_set(session).await;
         let mut mvcc_guard = self.mvcc.lock().await;
-        mvcc_guard.0.insert(ind, (false,data.clone()));
+        let writes = mvcc_guard.entry(*session).or_default();
+        // a slot the session staged for delete still holds a row
+        if writes.0.insert(ind, (false,data.to_vec())).is_none(){
+            writes.2.insert(ind);
+        }
+        drop(mvcc_guard);
+        if let Err(e) = self.check_unique(session, &[ind]).await{
+            self.restore_write_set(session, saved).await?;
+            return Err(e)
+        }
+        Ok(())
+    }
+    /// Columns whose values can't repeat: the primary key and the UNIQUE columns.
+    pub fn unique_columns(&self) -> Vec<usize>{
+        (0..self.headers.len()).filter(|column| *column == 0 || self.unique.get(*column).copied().unwrap_or(false)).collect()
+    }
+    /// Checks the rows `session` staged at `indexes` against the unique columns: no other row the session
+    /// staged and no committed row it leaves as it is may hold the same value. NULL never conflicts.
+    pub async fn check_unique(&self, session : &SessionId, indexes : &[u64]) -> Result<(),Error>{
+        let staged = self.staged(session).await;
+        for column in self.unique_columns(){
+            // staged rows and, once needed, committed rows by the index key of their value
+            let mut staged_keys : AHashMap<u64,Vec<u64>> = AHashMap::new();
+            for (index, (deleted, row)) in staged.iter(){
+                if let Some(value) = row.get(column).filter(|v| !*deleted && **v != AlbaTypes::NONE){
+                    staged_keys.entry(value.get_index()).or_default().push(*index);
+                }
+            }
+            for index in indexes{
+                let value = match staged.get(index){
+                    Some((false, row)) => match row.get(column){
+                        Some(AlbaTypes::NONE) | None => continue,
+                        Some(value) => value,
+                    },
+                    _ => continue,
+                };
+                let key = value.get_index();
+                let mut conflict = staged_keys.get(&key).is_some_and(|others| others.iter().any(|other| other != index && staged[other].1[column] == *value));
+                if !conflict{
+                    let offsets = match self.column_index(column){
+                        Some(indexing) => indexing.search(key).await?,
+                        None => return Err(gerr(&format!("The UNIQUE column {} of the container {} has no index", self.headers[column].0, self.name)))
+                    };
+                    for offset in offsets{
+                        let other = (offset - self.headers_offset) / self.element_size as u64;
+                        if staged.contains_key(&other){
+                            continue;
+                        }
+                        if self.stored_row(offset).await?.is_some_and(|row| row[column] == *value){
+                            conflict = true;
+                            break;
+                        }
+                    }
+                }
+                if conflict{
+                    return Err(gerr(&format!("Unique constraint violation: the container {} already has a row with {} = {:?}", self.name, self.headers[column].0, value)))
+                }
+            }
+        }
         Ok(())
     }
-    pub async fn rollback(&mut self) -> Result<(),Error> {
+    /// Index over `column`, the primary index for the first column.
+    fn column_index(&self, column : usize) -> Option<Arc<Indexing>>{
+        if column == 0{
+            return Some(self.indexing.clone())
+        }
+        self.secondary_indexes.iter().find(|i| i.column == column).map(|i| i.indexing.clone())
+    }
+    /// (index value, offset) of every stored row with a value in `column`, what an index of the column holds.
+    pub async fn index_elements(&self, column : usize) -> Result<Vec<(u64,u64)>,Error>{
+        let mut elements = Vec::new();
+        for index in 0..self.stored_rows().await?{
+            let offset = self.headers_offset + index * self.element_size as u64;
+            if let Some(value) = self.stored_row(offset).await?.and_then(|row| row.get(column).cloned()).filter(|v| *v != AlbaTypes::NONE){
+                elements.push((value.get_index(), offset));
+            }
+        }
+        Ok(elements)
+    }
+    /// Stages a new version of the row at `index` in the write set of `session`.
+    pub async fn stage(&self, session : &SessionId, index : u64, deleted : bool, data : Vec<AlbaTypes>){
         let mut mvcc_guard = self.mvcc.lock().await;
-        mvcc_guard.0.clear();
-        mvcc_guard.1.clear();
-        drop(mvcc_guard);
+        mvcc_guard.entry(*session).or_default().0.insert(index, (deleted,data));
+    }
+    /// Rows staged by `session`, which its reads see in place of the stored ones.
+    pub async fn staged(&self, session : &SessionId) -> StagedRows{
+        self.mvcc.lock().await.get(session).map(|w| w.0.clone()).unwrap_or_default()
+    }
+    /// Whether any session has changes on this container that aren't committed yet.
+    pub async fn has_staged(&self) -> bool{
+        self.mvcc.lock().await.values().any(|w| !w.0.is_empty() || !w.1.is_empty())
+    }
+    /// Copy of the changes `session` staged so far, kept by a savepoint.
+    pub async fn write_set(&self, session : &SessionId) -> WriteSet{
+        self.mvcc.lock().await.get(session).cloned().unwrap_or_default()
+    }
+    /// Puts back the changes `session` had staged at a savepoint, the ones staged after it are dropped.
+    pub async fn restore_write_set(&self, session : &SessionId, saved : WriteSet) -> Result<(),Error>{
+        let mut mvcc_guard = self.mvcc.lock().await;
+        let current = mvcc_guard.remove(session).unwrap_or_default();
+        let file_rows = self.stored_rows().await?;
+        let mut graveyard = self.graveyard.lock().await;
+        for index in current.2.range(..file_rows).filter(|index| !saved.2.contains(index)){
+            graveyard.insert(*index);
+        }
+        mvcc_guard.insert(*session, saved);
         Ok(())
     }
-    pub async fn commit(&mut self) -> Result<(), Error> {
+    pub async fn rollback(&mut self, session : &SessionId) -> Result<(),Error> {
+        let mut mvcc_guard = self.mvcc.lock().await;
+        let writes = match mvcc_guard.remove(session){
+            Some(writes) => writes,
+            None => return Ok(()),
+        };
+        // slots of rows inserted here that another session's commit wrote past are holes in the file now
+        let file_rows = self.stored_rows().await?;
+        let mut graveyard = self.graveyard.lock().await;
+        for index in writes.2.range(..file_rows){
+            graveyard.insert(*index);
+        }
+        Ok(())
+    }
+    /// First phase of a commit, turns the write set of `session` into the operations of a write-ahead
+    /// log entry without touching any file. None when the session changed nothing on this container.
+    /// With the `snapshot` of a transaction, a row another session committed after it fails the commit,
+    /// the first committer wins.
+    pub async fn prepare_commit(&self, session : &SessionId, snapshot : Option<u64>) -> Result<Option<PreparedCommit>, Error> {
         //let mut virtual_ward : AHashMap<usize, DataReference> = AHashMap::new();
-        let mut mvcc = self.mvcc.lock().await;
+        let mvcc_guard = self.mvcc.lock().await;
+        let (mut mvcc, fresh) = match mvcc_guard.get(session){
+            Some(writes) if !writes.0.is_empty() => ((writes.0.clone(), writes.1.clone()), writes.2.clone()),
+            _ => return Ok(None),
+        };
+        drop(mvcc_guard);
+        if let Some(snapshot) = snapshot {
+            let versions = self.versions.lock().await;
+            for index in mvcc.0.keys().filter(|index| !fresh.contains(index)) {
+                if versions.get(index).is_some_and(|chain| chain.iter().any(|(timestamp, _)| *timestamp > snapshot)) {
+                    return Err(gerr(&format!("A row of the container {} was changed by another session during this transaction, rollback and try again", self.name)))
+                }
+            }
+        }
+        // another session may have committed the same values since the rows were staged
+        let written : Vec<u64> = mvcc.0.iter().filter(|(_, (deleted, _))| !deleted).map(|(index, _)| *index).collect();
+        self.check_unique(session, &written).await?;
+        let mut previous = Vec::with_capacity(mvcc.0.len());
+        for index in mvcc.0.keys() {
+            previous.push((*index, self.stored_row((index * self.element_size as u64) + self.headers_offset).await?));
+        }
         let mut insertions: Vec<(u64, Vec<AlbaTypes>)> = Vec::new();
         let mut deletes: Vec<(u64, Vec<AlbaTypes>)> = Vec::new();
         for (index, value) in mvcc.0.iter() {
@@ -266,85 +514,224 @@ impl Container{
                 insertions.push(v);
             }
         }
-        mvcc.0.clear();
         insertions.sort_by_key(|(index, _)| *index);
         deletes.sort_by_key(|(index, _)| *index);
-        let buf = vec![0u8; self.element_size];
-        let fi = self.file.lock().await;
-        for (row_index, row_data) in insertions {
-            let serialized = self.serialize_row(&row_data)?;
-            let offset = row_index;
-            if let Some(arg) = row_data.first(){
-                let i = self.indexing.clone();
-                let j = offset.clone();
-                let idx = arg.get_index();
-                i.add(idx, j).await.unwrap();
+
+        let file_len = self.len().await?;
+        let file_rows = self.stored_rows().await?;
+        let mut operations : Vec<WalOperation> = Vec::with_capacity((insertions.len() + deletes.len()) * 2);
+        let inserted : Vec<u64> = insertions.iter().map(|(offset, _)| (offset - self.headers_offset) / self.element_size as u64).collect();
+        for (offset, row_data) in insertions {
+            if offset + self.element_size as u64 <= file_len {
+                for reference in self.stored_references(offset).await? {
+                    mvcc.1.insert(reference, (true, String::new()));
+                }
+                // an edited row leaves the indexes under its previous values
+                operations.extend(self.stored_index_removals(offset).await?);
             }
-            
-            fi.write_all_at(serialized.as_slice(), offset).unwrap();
-            //virtual_ward.insert(offset as usize, (const_xxh3::xxh3_64(serialized.as_slice()),serialized));
+            operations.push(WalOperation::Write { offset, bytes: self.serialize_row(&row_data, &mut mvcc.1)? });
+            operations.extend(self.index_operations(&row_data, offset, true));
         }
-        
-        let mut graveyard = self.graveyard.lock().await;
-        
-        for del in &deletes {
-            let offset = del.0;
-            let row_index = (offset - self.headers_offset) / self.element_size as u64;
-            fi.write_all_at(&buf, offset).unwrap();
-            graveyard.insert(row_index);  // Store row index
-            if let Some(arg) = del.1.first(){
-                let i = self.indexing.clone();
-                let j = offset.clone();
-                let idx = arg.get_index();
-                i.remove(idx, j).await.unwrap();
+        for (offset, _) in deletes.iter() {
+            // a row inserted and deleted in the same transaction was never written, its slot is still freed
+            if offset + self.element_size as u64 > file_len {
+                operations.push(WalOperation::Write { offset: *offset, bytes: vec![0u8; self.element_size] });
+                continue;
             }
-            loginfo!("offset: {}",offset);
-            loginfo!("row_index: {}",row_index);
-            
+            for reference in self.stored_references(*offset).await? {
+                mvcc.1.insert(reference, (true, String::new()));
+            }
+            operations.push(WalOperation::Write { offset: *offset, bytes: vec![0u8; self.element_size] });
+            operations.extend(self.stored_index_removals(*offset).await?);
         }
-        
+        // reference files are written before the rows pointing to them and removed after
+        let mut reference_writes : Vec<WalOperation> = Vec::new();
+        for (name, (deleted, content)) in mvcc.1.drain() {
+            if deleted {
+                operations.push(WalOperation::ReferenceRemove { name });
+            } else {
+                reference_writes.push(WalOperation::ReferenceWrite { name, bytes: content.into_bytes() });
+            }
+        }
+        reference_writes.append(&mut operations);
+        let operations = reference_writes;
 
-        for (i, txt) in  mvcc.1.iter(){
-            let path = format!("{}/rf/{}", self.location, i); 
-            if !txt.0 {
-                let mut file: std::fs::File = std::fs::File::create_new(&path)?;
-                if let Err(e) = file.write_all(txt.1.as_bytes()){
-                    return Err(gerr(&format!("Failed to write in text file: {}",e)))
-                };
-                
-                let buffer = txt.1.as_bytes();
-                let c_path = match CString::new(path).map_err(|e| e.to_string()){Ok(a) => a, Err(e) => return Err(gerr(&e))};
-                    let result = unsafe {
-                        write_data(buffer.as_ptr(), buffer.len(), c_path.as_ptr())
-                    };
+        Ok(Some(PreparedCommit{
+            entry: WalEntry{ container: self.name.clone(), operations },
+            inserted,
+            deleted: deletes.iter().map(|(offset, _)| (offset - self.headers_offset) / self.element_size as u64).collect(),
+            file_rows,
+            previous,
+        }))
+    }
+    /// Second phase of a commit, once the entry of `prepared` is in the write-ahead log it is applied
+    /// and the write set of `session` is dropped. The replaced rows are kept as versions of `timestamp`
+    /// before the file changes, so a snapshot reading meanwhile still finds them.
+    pub async fn finish_commit(&self, session : &SessionId, prepared : PreparedCommit, timestamp : u64) -> Result<(), Error> {
+        let mut mvcc_guard = self.mvcc.lock().await;
+        let mvcc = mvcc_guard.remove(session).unwrap_or_default();
+        {
+            let mut versions = self.versions.lock().await;
+            for (index, row) in prepared.previous {
+                versions.entry(index).or_default().push((timestamp, row));
+            }
+        }
+        self.apply_wal_operations(&prepared.entry.operations).await?;
 
-                    if result != 1 {
-                        logerr!("C write_data failed")
-                    }
-            } else if std::fs::exists(&path)?{
-                fs::remove_file(&path).await?
+        let mut graveyard = self.graveyard.lock().await;
+        for index in &prepared.inserted {
+            graveyard.remove(index);
+        }
+        for row_index in &prepared.deleted {
+            graveyard.insert(*row_index);  // Store row index
+        }
+        // slots skipped when the file grew belong to rows other sessions staged, or to none when they rolled back
+        for index in prepared.file_rows..self.stored_rows().await?{
+            if !mvcc.0.contains_key(&index) && !mvcc_guard.values().any(|w| w.0.contains_key(&index)){
+                graveyard.insert(index);
             }
-        
         }
+        drop(graveyard);
 
-        mvcc.1.clear();
-        mvcc.1.shrink_to_fit();
         // if let Some(s) = STRIX.get(){
         //     let mut l = s.lock().await;
         //     l.wards.push(Mutex::new((std::fs::OpenOptions::new().read(true).write(true).open(&self.file_path)?,virtual_ward)));
         // }
-        fi.sync_all().unwrap();
         Ok(())
     }
+    /// Row at `index` as a snapshot taken at `snapshot` saw it, None when the stored row is the one it sees.
+    pub async fn version_at(&self, index : u64, snapshot : u64) -> Option<Option<Vec<AlbaTypes>>> {
+        let versions = self.versions.lock().await;
+        versions.get(&index)?.iter().find(|(timestamp, _)| *timestamp > snapshot).map(|(_, row)| row.clone())
+    }
+    /// Indexes of the rows changed by a commit after `snapshot`.
+    pub async fn versioned_since(&self, snapshot : u64) -> Vec<u64> {
+        let versions = self.versions.lock().await;
+        versions.iter().filter(|(_, chain)| chain.iter().any(|(timestamp, _)| *timestamp > snapshot)).map(|(index, _)| *index).collect()
+    }
+    /// Drops the versions no snapshot at or after `horizon` reads.
+    pub async fn collect_versions(&self, horizon : u64) {
+        let mut versions = self.versions.lock().await;
+        versions.retain(|_, chain| {
+            chain.retain(|(timestamp, _)| *timestamp > horizon);
+            !chain.is_empty()
+        });
+    }
+    /// Applies the operations of a write-ahead log entry to the container and its index.
+    /// Row and reference operations are idempotent, so an entry can be applied again when it is replayed after a crash.
+    /// Index pages are not, an index is synced once per entry and one left dirty by a crash is rebuilt from the rows
+    /// instead, so operations on a stale index are skipped.
+    pub async fn apply_wal_operations(&self, operations : &[WalOperation]) -> Result<(), Error> {
+        let fi = self.file.lock().await;
+        for op in operations{
+            match op{
+                WalOperation::Write { offset, bytes } => {
+                    fi.write_all_at(bytes, *offset)?;
+                },
+                WalOperation::IndexAdd { key, offset } => {
+                    if !self.indexing.is_stale(){
+                        self.indexing.add(*key, *offset).await?;
+                    }
+                },
+                WalOperation::IndexRemove { key, offset } => {
+                    if !self.indexing.is_stale(){
+                        self.indexing.remove(*key, *offset).await?;
+                    }
+                },
+                WalOperation::SecondaryIndexAdd { index, key, offset } => {
+                    if let Some(i) = self.secondary_indexes.iter().find(|i| i.name == *index) && !i.indexing.is_stale(){
+                        i.indexing.add(*key, *offset).await?;
+                    }
+                },
+                WalOperation::SecondaryIndexRemove { index, key, offset } => {
+                    if let Some(i) = self.secondary_indexes.iter().find(|i| i.name == *index) && !i.indexing.is_stale(){
+                        i.indexing.remove(*key, *offset).await?;
+                    }
+                },
+                WalOperation::ReferenceWrite { name, bytes } => {
+                    let path = self.reference_path(name);
+                    let c_path = match CString::new(path.clone()).map_err(|e| e.to_string()){Ok(a) => a, Err(e) => return Err(gerr(&e))};
+                    let result = unsafe {
+                        write_data(bytes.as_ptr(), bytes.len(), c_path.as_ptr())
+                    };
+                    if result != 1 {
+                        return Err(gerr(&format!("Failed to write the reference file {}",name)))
+                    }
+                    std::fs::File::open(&path)?.sync_all()?;
+                },
+                WalOperation::ReferenceRemove { name } => {
+                    let path = self.reference_path(name);
+                    if std::fs::exists(&path)? {
+                        fs::remove_file(&path).await?
+                    }
+                }
+            }
+        }
+        fi.sync_all()?;
+        self.indexing.flush().await?;
+        for i in self.secondary_indexes.iter(){
+            i.indexing.flush().await?;
+        }
+        Ok(())
+    }
+    pub fn reference_path(&self, name : &str) -> String {
+        format!("{}/rf/{}", self.location, name)
+    }
+    fn new_reference(&self, pending : &HashMap<String,(bool,String)>) -> u64 {
+        loop {
+            let id = OsRng.next_u64();
+            if id != 0 && !pending.contains_key(&id.to_string()) && !Path::new(&self.reference_path(&id.to_string())).exists() {
+                return id
+            }
+        }
+    }
+    /// Names of the reference files used by the TEXT, JSON and ARRAY columns of the row stored at `offset`.
+    pub async fn stored_references(&self, offset : u64) -> Result<Vec<String>, Error> {
+        let mut buf = vec![0u8; self.element_size];
+        self.file.lock().await.read_exact_at(&mut buf, offset)?;
+        let mut references = Vec::new();
+        let mut index = null_bitmap_size(&self.nullable);
+        for column_type in self.columns().iter() {
+            if column_type.is_referenced() {
+                let mut id = [0u8; 8];
+                id.copy_from_slice(&buf[index..index+8]);
+                let id = u64::from_be_bytes(id);
+                if id != 0 {
+                    references.push(id.to_string());
+                }
+            }
+            index += column_type.size();
+        }
+        Ok(references)
+    }
     
     pub fn columns(&self) -> Vec<AlbaTypes>{
         self.headers.iter().map(|v|v.1.clone()).collect()
     }
-    pub fn serialize_row(&self, row: &[AlbaTypes]) -> Result<Vec<u8>, Error> {
-        let mut buffer = Vec::with_capacity(self.element_size);
+    /// Converts a value to the type of the column, NULL is only accepted by nullable columns.
+    pub fn convert_value(&self, column : usize, value : AlbaTypes) -> Result<AlbaTypes, Error> {
+        if let AlbaTypes::NONE = value {
+            if self.nullable[column] {
+                return Ok(AlbaTypes::NONE)
+            }
+            return Err(gerr(&format!("The column {} does not accept NULL values", self.headers[column].0)))
+        }
+        self.headers[column].1.try_from_existing(value)
+    }
+    /// Serializes a row into its fixed size layout.
+    /// The content of TEXT, JSON and ARRAY columns goes to new reference files, which are registered in `references` to be written on commit.
+    pub fn serialize_row(&self, row: &[AlbaTypes], references: &mut HashMap<String,(bool,String)>) -> Result<Vec<u8>, Error> {
+        let mut buffer = vec![0u8; null_bitmap_size(&self.nullable)];
     
-        for (item, ty) in row.iter().zip(self.columns().iter()) {
-            
+        for (column, (item, ty)) in row.iter().zip(self.columns().iter()).enumerate() {
+            if let AlbaTypes::NONE = item {
+                if !self.nullable[column] {
+                    return Err(gerr(&format!("The column {} does not accept NULL values", self.headers[column].0)))
+                }
+                buffer[column / 8] |= 1 << (column % 8);
+                buffer.extend(vec![0u8; ty.size()]);
+                continue;
+            }
             let item = &AlbaTypes::to_another(item, ty);
             match (item, ty) {
                 (AlbaTypes::Bigint(v), AlbaTypes::Bigint(_)) => {
@@ -363,10 +750,26 @@ impl Container{
                     let code = *c as u32;
                     buffer.extend_from_slice(&code.to_le_bytes());
                 },
-                (AlbaTypes::Text(s), AlbaTypes::Text(_)) => {
-                    let mut bytes = s.as_bytes().to_vec();
-                    bytes.resize(self.str_size, 0);
-                    buffer.extend_from_slice(&bytes);
+                (AlbaTypes::Text(s), AlbaTypes::Text(_)) | (AlbaTypes::Json(s), AlbaTypes::Json(_)) => {
+                    let id = if s.is_empty() {
+                        0
+                    } else {
+                        let id = self.new_reference(references);
+                        references.insert(id.to_string(), (false, s.clone()));
+                        id
+                    };
+                    buffer.extend_from_slice(&id.to_be_bytes());
+                },
+                // kept as a JSON array, an empty one has no reference
+                (AlbaTypes::Array(items, _), AlbaTypes::Array(..)) => {
+                    let id = if items.is_empty() {
+                        0
+                    } else {
+                        let id = self.new_reference(references);
+                        references.insert(id.to_string(), (false, format_array(items)));
+                        id
+                    };
+                    buffer.extend_from_slice(&id.to_be_bytes());
                 },
                 (AlbaTypes::NanoString(s), AlbaTypes::NanoString(_)) => {
                     serialize_closed_string(item,s,&mut buffer);
@@ -403,6 +806,18 @@ impl Container{
                     let mut blob: Vec<u8> = v.to_owned();
                     serialize_closed_blob(item, &mut blob, &mut buffer);
                 },
+                (AlbaTypes::Timestamp(v), AlbaTypes::Timestamp(_)) => {
+                    buffer.extend_from_slice(&v.to_be_bytes());
+                },
+                (AlbaTypes::Date(v), AlbaTypes::Date(_)) => {
+                    buffer.extend_from_slice(&v.to_be_bytes());
+                },
+                (AlbaTypes::Uuid(v), AlbaTypes::Uuid(_)) => {
+                    buffer.extend_from_slice(&v.to_be_bytes());
+                },
+                (AlbaTypes::Decimal(v, ..), AlbaTypes::Decimal(..)) => {
+                    buffer.extend_from_slice(&v.to_be_bytes());
+                },
                 (AlbaTypes::NONE, AlbaTypes::NONE) => {
                     let size = item.size();
                     buffer.extend(vec![0u8; size]);
@@ -429,10 +844,16 @@ impl Container{
         Ok(buffer)
     }
     pub async fn deserialize_row(&self, buf: &[u8]) -> Result<Vec<AlbaTypes>, Error> {
-        let mut index = 0;
+        let bitmap_size = null_bitmap_size(&self.nullable);
+        let mut index = bitmap_size;
         let mut values = Vec::new();
     
-        for column_type in &self.columns() {
+        for (column, column_type) in self.columns().iter().enumerate() {
+            if bitmap_size > 0 && buf[column / 8] & (1 << (column % 8)) != 0 {
+                index += column_type.size();
+                values.push(AlbaTypes::NONE);
+                continue;
+            }
             match column_type {
                 // Primitive types
                 AlbaTypes::Bigint(_) => {
@@ -481,8 +902,26 @@ impl Container{
                 },
     
                 // Text types
-                AlbaTypes::Text(_) => {
-                    values.push(AlbaTypes::Text(String::new()));
+                AlbaTypes::Text(_) | AlbaTypes::Json(_) | AlbaTypes::Array(..) => {
+                    let size = std::mem::size_of::<u64>();
+                    let bytes: [u8; 8] = buf[index..index+size].try_into()
+                        .map_err(|e| gerr(&format!("Failed to read text reference: {}", e)))?;
+                    index += size;
+                    let id = u64::from_be_bytes(bytes);
+                    let content = if id == 0 {
+                        String::new()
+                    } else {
+                        std::fs::read_to_string(self.reference_path(&id.to_string()))
+                            .map_err(|e| gerr(&format!("Failed to read the text reference {}: {}", id, e)))?
+                    };
+                    values.push(match column_type {
+                        // the zero value of a JSON column is null
+                        AlbaTypes::Json(_) if content.is_empty() => AlbaTypes::Json("null".to_string()),
+                        AlbaTypes::Json(_) => AlbaTypes::Json(content),
+                        AlbaTypes::Array(_, element) if content.is_empty() => AlbaTypes::Array(Vec::new(), *element),
+                        AlbaTypes::Array(..) => column_type.try_from_existing(AlbaTypes::Text(content))?,
+                        _ => AlbaTypes::Text(content),
+                    });
                 },
     
                 // Fixed-size string types
@@ -499,6 +938,38 @@ impl Container{
                 AlbaTypes::BigSBytes(_) => handle_bytes(&buf, &mut index, column_type.size(), &mut values)?,
                 AlbaTypes::LargeBytes(_) => handle_bytes(&buf, &mut index, column_type.size(), &mut values)?,
     
+                AlbaTypes::Timestamp(_) => {
+                    let size = std::mem::size_of::<i64>();
+                    let bytes: [u8; 8] = buf[index..index+size].try_into()
+                        .map_err(|e| gerr(&format!("Failed to read timestamp: {}", e)))?;
+                    index += size;
+                    values.push(AlbaTypes::Timestamp(i64::from_be_bytes(bytes)));
+                },
+
+                AlbaTypes::Date(_) => {
+                    let size = std::mem::size_of::<i32>();
+                    let bytes: [u8; 4] = buf[index..index+size].try_into()
+                        .map_err(|e| gerr(&format!("Failed to read date: {}", e)))?;
+                    index += size;
+                    values.push(AlbaTypes::Date(i32::from_be_bytes(bytes)));
+                },
+
+                AlbaTypes::Uuid(_) => {
+                    let size = std::mem::size_of::<u128>();
+                    let bytes: [u8; 16] = buf[index..index+size].try_into()
+                        .map_err(|e| gerr(&format!("Failed to read uuid: {}", e)))?;
+                    index += size;
+                    values.push(AlbaTypes::Uuid(u128::from_be_bytes(bytes)));
+                },
+
+                AlbaTypes::Decimal(_, precision, scale) => {
+                    let size = std::mem::size_of::<i128>();
+                    let bytes: [u8; 16] = buf[index..index+size].try_into()
+                        .map_err(|e| gerr(&format!("Failed to read decimal: {}", e)))?;
+                    index += size;
+                    values.push(AlbaTypes::Decimal(i128::from_be_bytes(bytes), *precision, *scale));
+                },
+    
                 // Null handling
                 AlbaTypes::NONE => {
                     values.push(AlbaTypes::NONE);
@@ -509,4 +980,55 @@ impl Container{
         Ok(values)
     }
     
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::database::{tests::test_database, Database};
+
+    async fn run(db : &mut Database, session : SessionId, statement : &str){
+        crate::database::tests::run(db, session, statement).await.unwrap();
+    }
+
+    async fn commits(container : &Container, index : u64) -> Vec<u64>{
+        container.versions.lock().await.get(&index).map(|chain| chain.iter().map(|(timestamp, _)| *timestamp).collect()).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn version_at_reads_the_row_a_snapshot_saw(){
+        let (_guard, mut db) = test_database("versions").await;
+        let reader = [1u8; 32];
+        run(&mut db, LOCAL_SESSION, "CREATE CONTAINER 'c' ['id', 'x'] ['INT', 'INT']").await;
+        // the open transaction keeps the versions of the commits made after it
+        run(&mut db, reader, "BEGIN").await;
+        let c = db.container.get("c").unwrap().clone();
+        let row = |x : i32| Some(vec![AlbaTypes::Int(1), AlbaTypes::Int(x)]);
+
+        run(&mut db, LOCAL_SESSION, "CREATE ROW ['id', 'x'] [1, 10] ON 'c'").await;
+        let inserted = commits(&*c.lock().await, 0).await[0];
+        assert_eq!(c.lock().await.version_at(0, inserted - 1).await, Some(None));
+        assert_eq!(c.lock().await.version_at(0, inserted).await, None);
+
+        run(&mut db, LOCAL_SESSION, "EDIT ROW ['x'][20] ON 'c' WHERE 'id' = 1").await;
+        let edited = commits(&*c.lock().await, 0).await[1];
+        assert!(edited > inserted);
+        // a snapshot taken before the edit doesn't see it
+        assert_eq!(c.lock().await.version_at(0, inserted).await, Some(row(10)));
+        assert_eq!(c.lock().await.version_at(0, inserted - 1).await, Some(None));
+        assert_eq!(c.lock().await.version_at(0, edited).await, None);
+
+        run(&mut db, LOCAL_SESSION, "DELETE ROW ON 'c' WHERE 'id' = 1").await;
+        let deleted = commits(&*c.lock().await, 0).await[2];
+        // the deleted row is still there for the snapshots taken before the delete
+        assert_eq!(c.lock().await.version_at(0, edited).await, Some(row(20)));
+        assert_eq!(c.lock().await.version_at(0, inserted).await, Some(row(10)));
+        assert_eq!(c.lock().await.version_at(0, deleted).await, None);
+        let container = c.lock().await;
+        assert_eq!(container.stored_row(container.headers_offset).await.unwrap(), None);
+        drop(container);
+
+        // once the transaction ends no snapshot reads the versions anymore
+        run(&mut db, reader, "ROLLBACK").await;
+        assert!(commits(&*c.lock().await, 0).await.is_empty());
+    }
+}
@@ -1,21 +1,20 @@
-use std::{collections::HashMap, fs, io::{Error, ErrorKind, Read, Write}, os::unix::fs::FileExt, path::PathBuf, str::FromStr, sync::Arc};
+use std::{collections::HashMap, fs, io::{Error, ErrorKind, Read, Write}, os::unix::fs::FileExt, path::PathBuf, str::FromStr, sync::Arc, time::{Duration, Instant}};
 use ahash::AHashMap;
 use base64::{alphabet, engine::{self, GeneralPurpose}, Engine};
 use lazy_static::lazy_static;
 use serde::{Serialize,Deserialize};
 use serde_yaml;
-use crate::{alba_types::AlbaTypes, container::Container, gerr, indexing::Search, logerr, loginfo, parser::{debug_tokens, parse}, query::{indexed_search, indexed_search_direct, search, search_direct, Query, SearchArguments}, query_conditions::{QueryConditions, QueryType}, AlbaContainer, AST};
-use rand::{Rng, distributions::Alphanumeric};
+use crate::{alba_types::{generate_uuid, AlbaTypes, ARRAY_ELEMENT_IDS}, container::{null_bitmap_size, Container, SecondaryIndex, SessionId, WriteSet, LOCAL_SESSION, NULLABLE_FLAG, UNIQUE_FLAG}, gerr, indexing::{Add, GetIndex, Indexing, Search}, logerr, loginfo, parser::{debug_tokens, parse}, query::{aggregate, compare_values, indexed_search, indexed_search_direct, project, qualify, search, search_direct, PrimitiveQueryConditions, Query, ResultOrder, SearchArguments}, row::Row, snapshot::SnapshotClock, arithmetic::Expression, query_conditions::{QueryConditions, QueryType}, wal::{WalEntry, WriteAheadLog}, AlbaContainer, AlterColumn, AstAlterContainer, AstCreateIndex, AstJoin, AstSearch, Projection, AST};
+use rand::{Rng, RngCore, distributions::Alphanumeric};
 use tokio::{net::TcpListener, sync::Mutex};
 /////////////////////////////////////////////////
 /////////     DEFAULT_SETTINGS    ///////////////
 /////////////////////////////////////////////////
 
-pub const MAX_STR_LEN : usize = 128;
 const DEFAULT_SETTINGS : &str = r#"
 max_columns: 50
 min_columns: 1
-auto_commit: false            
+auto_commit: true
 memory_limit: 1048576000
 ip: 127.0.0.1
 connections_port: 1515
@@ -27,6 +26,7 @@ on_insecure_rejection_delay_ms: 100
 safety_level: strict # strict | permissive
 request_handling: sync # sync | asynchronous
 secret_key_count: 10
+transaction_timeout_seconds: 600 # a transaction idle for longer is rolled back, 0 never
 "#;
 #[derive(Serialize, Deserialize, Debug, Default)]
 enum SafetyLevel {
@@ -60,7 +60,13 @@ struct Settings{
     on_insecure_rejection_delay_ms: u64,
     safety_level: SafetyLevel,
     request_handling: RequestHandling,
-    secret_key_count: u64
+    secret_key_count: u64,
+    #[serde(default = "default_transaction_timeout")]
+    transaction_timeout_seconds: u64,
+}
+
+fn default_transaction_timeout() -> u64{
+    600
 }
 
 const SECRET_KEY_PATH : &str = "TytoDB/.tytodb-keys";
@@ -94,14 +100,41 @@ pub fn generate_secure_code(len: usize) -> String {
     code
 }
 
-#[derive(Default,Debug)]
+/// A secondary index created with CREATE INDEX, the list of them is kept in indexes.yaml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexDefinition{
+    name : String,
+    container : String,
+    column : String,
+}
+
+/// Column names, types, nullable flags and unique flags read from the header of a container file
+type ContainerHeaders = (Vec<String>,Vec<AlbaTypes>,Vec<bool>,Vec<bool>);
+
+/// Name of a savepoint with the write set the session had on every container
+type Savepoint = (String, Vec<(String, WriteSet)>);
+
+#[derive(Debug)]
 pub struct Database{
     location : String,
     settings : Settings,
     containers : Vec<String>,
-    headers : Vec<(Vec<String>,Vec<AlbaTypes>)>,
+    headers : Vec<ContainerHeaders>,
+    indexes : Vec<IndexDefinition>,
     pub container : HashMap<String,Arc<Mutex<Container>>>,
     secret_keys : Arc<Mutex<HashMap<[u8;32],Vec<u8>>>>,
+    wal : Arc<Mutex<WriteAheadLog>>,
+    // session and snapshot of the running statement, and the sessions inside a BEGIN with their snapshot
+    session : SessionId,
+    snapshot : u64,
+    transactions : AHashMap<SessionId, u64>,
+    // a commit in the write-ahead log failed to apply, see `commit_containers`
+    unapplied : bool,
+    // when each open transaction last ran a statement, see `expire_transactions`
+    activity : AHashMap<SessionId, Instant>,
+    // savepoints of the open transactions, oldest first
+    savepoints : AHashMap<SessionId, Vec<Savepoint>>,
+    clock : SnapshotClock,
 }
 
 fn check_for_reference_folder(location : &String) -> Result<(), Error>{
@@ -117,7 +150,135 @@ fn check_for_reference_folder(location : &String) -> Result<(), Error>{
 
 
 
+/// Version of the layout of the container files, kept in the top byte of the u64 size of the header.
+/// 0: TEXT values stored in the row in 128 bytes, 1: TEXT values stored in reference files under rf/.
+const CONTAINER_FORMAT : u8 = 1;
+const HEADER_SIZE_MASK : u64 = (1 << 56) - 1;
+// size of a TEXT column in the rows of a container of format 0
+const LEGACY_TEXT_SIZE : usize = 128;
+
+/// Builds the header of a container file: the u64 size of the header, with CONTAINER_FORMAT in its top byte, followed by
+/// (u16 name length, u8 type id, name) for every column, a DECIMAL has its u8 precision and u8 scale after the type id
+/// and an ARRAY the u8 type id of its elements.
+fn encode_container_headers(names : &[String], types : &[AlbaTypes], nullable : &[bool], unique : &[bool]) -> Result<Vec<u8>, Error>{
+    let mut buffer : Vec<u8> = Vec::new();
+    for (((name, ty), nullable), unique) in names.iter().zip(types.iter()).zip(nullable.iter()).zip(unique.iter()){
+        let n = name.as_bytes();
+        if n.len() > u16::MAX as usize{
+            return Err(gerr(&format!("The maximum size in bytes of the column name is {}, and the current size is {}",u16::MAX,n.len())))
+        }
+        buffer.extend_from_slice(&(n.len() as u16).to_be_bytes());
+        buffer.push(ty.get_id() | if *nullable { NULLABLE_FLAG } else { 0 } | if *unique { UNIQUE_FLAG } else { 0 });
+        if let AlbaTypes::Decimal(_, precision, scale) = ty{
+            buffer.extend_from_slice(&[*precision, *scale]);
+        }
+        if let AlbaTypes::Array(_, element) = ty{
+            buffer.push(*element);
+        }
+        buffer.extend_from_slice(n);
+    }
+    let mut buff = (buffer.len() as u64 | (CONTAINER_FORMAT as u64) << 56).to_be_bytes().to_vec();
+    buff.extend_from_slice(&buffer);
+    Ok(buff)
+}
+
+/// Temporary file an ALTER CONTAINER writes the new layout of a container to, its indexes are built next to it.
+fn alter_file_name(container : &str) -> String{
+    format!(".{}.alter", container)
+}
+
+/// Key of a value in the hash table of a join. Index keys are order-preserving prefixes, values that
+/// `compare_values` finds equal can still have different ones, like a date and the timestamp of its midnight
+/// or an INT and a FLOAT, so they are brought to the same form first. Values sharing a key are
+/// compared again.
+fn join_key(value : &AlbaTypes) -> u64{
+    match value{
+        AlbaTypes::Timestamp(_) | AlbaTypes::Date(_) => value.as_timestamp().unwrap_or_default().get_index(),
+        // numbers of any type share the key of their float, values it rounds together are told apart by the comparison
+        AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Float(_) | AlbaTypes::Decimal(..) => value.as_float().unwrap_or_default().get_index(),
+        AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.trim_end().as_bytes().get_index(),
+        _ => value.get_index(),
+    }
+}
+
+/// Arguments of a search that matches every row of the container.
+async fn scan_arguments(container : &Arc<Mutex<Container>>, session : SessionId, snapshot : u64) -> Result<SearchArguments, Error>{
+    let book = container.lock().await;
+    let mut headers = HashMap::new();
+    for (name, column_type) in book.headers.iter().cloned(){
+        headers.insert(name, column_type);
+    }
+    Ok(SearchArguments{
+        element_size: book.element_size,
+        header_offset: book.headers_offset as usize,
+        file: book.file.clone(),
+        container_values: book.headers.clone(),
+        conditions: QueryConditions::from_primitive_conditions(None, &headers, Vec::new())?,
+        session,
+        snapshot,
+    })
+}
+
+const SECONDARY_INDEXES_FOLDER : &str = "indexes";
+// start of the names of the indexes the database keeps over UNIQUE columns, CREATE INDEX names can't start with '.'
+const UNIQUE_INDEX_PREFIX : &str = ".unique.";
+
+/// Name given to `Indexing::load_index` for the primary index of a container (None) or one of its secondary indexes,
+/// which have a folder per container so they never share a file name with a primary index.
+fn index_file_id(container : &str, index : Option<&str>) -> String{
+    match index{
+        Some(name) => format!("{}/{}/{}", SECONDARY_INDEXES_FOLDER, container, name),
+        None => container.to_string(),
+    }
+}
+
+/// Index an ALTER CONTAINER builds next to the temporary container file in place of `index_file_id(container, index)`.
+fn alter_index_id(container : &str, index : Option<&str>) -> String{
+    match index{
+        Some(name) => index_file_id(container, Some(&alter_file_name(name))),
+        None => alter_file_name(container),
+    }
+}
+
+/// Writes the file aside and renames it over the old one, so it is never left half written.
+fn write_atomically(location : &str, path : &std::path::Path, content : &[u8]) -> Result<(), Error>{
+    let temporary = path.with_extension("tmp");
+    let mut file = fs::File::create(&temporary)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    fs::File::open(location)?.sync_all()?;
+    Ok(())
+}
+
+/// Finishes or discards an ALTER CONTAINER interrupted by a crash.
+/// Replacing the container file is the commit point: while the temporary container file still exists the old
+/// container is intact and the temporary files are dropped, once it is gone only the index is left to be moved.
+fn recover_alter(location : &str, container : &str, secondary_indexes : &[&str]) -> Result<(), Error>{
+    let data = format!("{}/{}", location, alter_file_name(container));
+    let unfinished = fs::exists(&data)?;
+    if unfinished{
+        logerr!("Discarding the unfinished schema change of the container {}", container);
+        fs::remove_file(&data)?;
+    }
+    for index in std::iter::once(None).chain(secondary_indexes.iter().map(|i| Some(*i))){
+        let temporary = format!("{}/{}.index", location, alter_index_id(container, index));
+        if !fs::exists(&temporary)?{
+            continue;
+        }
+        if unfinished{
+            fs::remove_file(&temporary)?;
+        }else{
+            loginfo!("Finishing the schema change of the container {}", container);
+            fs::rename(&temporary, format!("{}/{}.index", location, index_file_id(container, index)))?;
+        }
+    }
+    fs::File::open(location)?.sync_all()?;
+    Ok(())
+}
+
 const SETTINGS_FILE : &str = "settings.yaml";
+const INDEXES_FILE : &str = "indexes.yaml";
 
 lazy_static!{
     static ref B64_ENGINE : GeneralPurpose = new_b64_engine();
@@ -171,13 +332,22 @@ impl Database{
         
         self.headers.clear();
         
+        let indexes_path = format!("{}/{}", &self.location, INDEXES_FILE);
+        if fs::exists(&indexes_path)? {
+            self.indexes = serde_yaml::from_str(&fs::read_to_string(&indexes_path)?)
+                .map_err(|e| Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        }
+        
         for contain in self.containers.iter() {
             
+            let secondary : Vec<&str> = self.indexes.iter().filter(|i| i.container == *contain).map(|i| i.name.as_str()).collect();
+            recover_alter(&self.location, contain, &secondary)?;
+            self.migrate_container(contain, &secondary)?;
             let (he,header_offset) = self.get_container_headers(&contain).unwrap();
             
             self.headers.push(he.clone());
             
-            let mut element_size: usize = 0;
+            let mut element_size: usize = null_bitmap_size(&he.2);
             for el in he.1.iter() {
                 element_size += el.size();
                 
@@ -191,13 +361,30 @@ impl Database{
                     self.location.clone(),
                     element_size,
                     he.1,
-                    MAX_STR_LEN,
                     header_offset,
                     he.0.clone(),
+                    he.2,
+                    he.3,
                 ).await.unwrap(),
             );
             
         }
+        for definition in self.indexes.iter() {
+            match self.container.get(&definition.container) {
+                Some(c) => {
+                    let mut c = c.lock().await;
+                    match c.column_names().iter().position(|n| *n == definition.column) {
+                        Some(column) => {
+                            let indexing = Indexing::load_index(&index_file_id(&definition.container, Some(&definition.name))).await?;
+                            c.secondary_indexes.push(SecondaryIndex { name: definition.name.clone(), column, indexing });
+                        },
+                        None => logerr!("The index {} refers to the unknown column {}", definition.name, definition.column),
+                    }
+                },
+                None => logerr!("The index {} refers to the unknown container {}", definition.name, definition.container),
+            }
+        }
+        self.replay_wal().await?;
         for (_, wedfygt) in self.container.iter() {
             let wedfygt = wedfygt.lock().await;
             let count = (wedfygt.len().await? - wedfygt.headers_offset) / wedfygt.element_size as u64;
@@ -225,44 +412,269 @@ impl Database{
                 }
             }
         }
-        
+        self.rebuild_stale_indexes().await?;
+        for name in self.containers.clone() {
+            self.create_unique_indexes(&name).await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites a container written before TEXT values moved to reference files the way ALTER CONTAINER does:
+    /// the rows go to the temporary alter file with every TEXT value moved to a new reference file, which is then
+    /// renamed over the container. The offsets of the rows change so its indexes are rebuilt.
+    fn migrate_container(&self, container : &str, secondary_indexes : &[&str]) -> Result<(), Error> {
+        let path = format!("{}/{}", self.location, container);
+        let file = fs::File::open(&path)?;
+        let mut format = [0u8;1];
+        file.read_exact_at(&mut format, 0)?;
+        if format[0] == CONTAINER_FORMAT{
+            return Ok(())
+        }
+        loginfo!("Converting the container {} to the current file format", container);
+        let ((names, types, nullable, unique), headers_offset) = self.get_container_headers(container)?;
+        let sizes : Vec<usize> = types.iter().map(|t| if let AlbaTypes::Text(_) = t { LEGACY_TEXT_SIZE } else { t.size() }).collect();
+        let bitmap = null_bitmap_size(&nullable);
+        let element_size = bitmap + sizes.iter().sum::<usize>();
+
+        let temporary = format!("{}/{}", self.location, alter_file_name(container));
+        let mut output = fs::File::create(&temporary)?;
+        output.write_all(&encode_container_headers(&names, &types, &nullable, &unique)?)?;
+        let count = file.metadata()?.len().saturating_sub(headers_offset) / element_size as u64;
+        let mut row = vec![0u8; element_size];
+        for i in 0..count{
+            file.read_exact_at(&mut row, headers_offset + i * element_size as u64)?;
+            let mut buffer = row[..bitmap].to_vec();
+            let mut at = bitmap;
+            for (column_type, size) in types.iter().zip(sizes.iter()){
+                let value = &row[at..at + size];
+                at += size;
+                if let AlbaTypes::Text(_) = column_type{
+                    let text = &value[..value.iter().rposition(|b| *b != 0).map_or(0, |p| p + 1)];
+                    let mut id = 0;
+                    if !text.is_empty(){
+                        while id == 0 || fs::exists(format!("{}/rf/{}", self.location, id))?{
+                            id = rand::rngs::OsRng.next_u64();
+                        }
+                        let mut reference = fs::File::create(format!("{}/rf/{}", self.location, id))?;
+                        reference.write_all(text)?;
+                        reference.sync_all()?;
+                    }
+                    buffer.extend_from_slice(&id.to_be_bytes());
+                }else{
+                    buffer.extend_from_slice(value);
+                }
+            }
+            output.write_all(&buffer)?;
+        }
+        output.sync_all()?;
+        for index in std::iter::once(None).chain(secondary_indexes.iter().map(|i| Some(*i))){
+            Indexing::invalidate(&index_file_id(container, index))?;
+        }
+        // a crash before the rename leaks the reference files written so far, the conversion starts over
+        fs::rename(&temporary, &path)?;
+        fs::File::open(&self.location)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Rebuilds from the rows every index whose values were computed by an older version of `GetIndex`
+    /// or that a crash left dirty.
+    async fn rebuild_stale_indexes(&self) -> Result<(), Error> {
+        for (name, c) in self.container.iter() {
+            let mut c = c.lock().await;
+            if c.indexing.is_stale() {
+                loginfo!("Rebuilding the index of the container {}", name);
+                let elements = c.index_elements(0).await?;
+                c.indexing = Indexing::build(&index_file_id(name, None), elements).await?;
+            }
+            for i in 0..c.secondary_indexes.len() {
+                if c.secondary_indexes[i].indexing.is_stale() {
+                    loginfo!("Rebuilding the index {}", c.secondary_indexes[i].name);
+                    let elements = c.index_elements(c.secondary_indexes[i].column).await?;
+                    c.secondary_indexes[i].indexing = Indexing::build(&index_file_id(name, Some(&c.secondary_indexes[i].name)), elements).await?;
+                }
+            }
+        }
         Ok(())
     }
     
+    async fn replay_wal(&self) -> Result<(), Error> {
+        let mut wal = self.wal.lock().await;
+        let records = wal.recover()?;
+        for entries in records.iter() {
+            for entry in entries {
+                match self.container.get(&entry.container) {
+                    Some(c) => c.lock().await.apply_wal_operations(&entry.operations).await?,
+                    None => logerr!("Skipping write-ahead log entry of the unknown container {}", entry.container),
+                }
+            }
+        }
+        if !records.is_empty() {
+            loginfo!("Replayed {} commits from the write-ahead log", records.len());
+        }
+        wal.checkpoint()?;
+        Ok(())
+    }
+
     fn save_containers(&self) -> Result<(), Error> {
         let path = std::path::PathBuf::from(&self.location).join("containers.yaml");
         
         let yaml = serde_yaml::to_string(&self.containers)
             .map_err(|e| Error::new(std::io::ErrorKind::Other, e.to_string()))?;
         
-        fs::remove_file(&path)?;
+        write_atomically(&self.location, &path, yaml.as_bytes())
+    }
+
+    fn save_indexes(&self) -> Result<(), Error> {
+        let path = std::path::PathBuf::from(&self.location).join(INDEXES_FILE);
         
-        fs::write(&path, yaml.as_bytes())?;
+        let yaml = serde_yaml::to_string(&self.indexes)
+            .map_err(|e| Error::new(std::io::ErrorKind::Other, e.to_string()))?;
         
-        Ok(())
+        write_atomically(&self.location, &path, yaml.as_bytes())
     }
     
+    /// Commits the changes of the current session on every container and ends its transaction.
     pub async fn commit(&mut self) -> Result<(), Error> {
-        
-        for (_, c) in self.container.iter_mut() {
+        self.commit_containers(None).await
+    }
+
+    /// Commits the changes of the current session on one container, or on every one with None.
+    /// The write sets of every container are prepared first and logged as a single write-ahead log
+    /// record, which is the commit decision: when preparing fails nothing is written, and once the
+    /// record is on disk a crash while applying it is finished by the replay on the next start.
+    /// The commit gets one timestamp, so a snapshot sees all of its containers changed or none.
+    /// When applying the record fails it stays in the log and nothing else is written until a restart replays it.
+    async fn commit_containers(&mut self, only : Option<&str>) -> Result<(), Error> {
+        self.check_applied()?;
+        let snapshot = self.transactions.get(&self.session).copied();
+        let mut prepared = Vec::new();
+        for (name, c) in self.container.iter() {
+            if only.is_some_and(|only| only != name) {
+                continue;
+            }
             
-            c.lock().await.commit().await?;
+            if let Some(p) = c.lock().await.prepare_commit(&self.session, snapshot).await? {
+                prepared.push((c.clone(), p));
+            }
             
         }
+        if !prepared.is_empty() {
+            let timestamp = self.clock.next();
+            let mut wal = self.wal.lock().await;
+            let entries : Vec<WalEntry> = prepared.iter().map(|(_, p)| p.entry.clone()).collect();
+            wal.append(&entries)?;
+            for (c, p) in prepared {
+                if let Err(e) = c.lock().await.finish_commit(&self.session, p, timestamp).await {
+                    // the containers applied so far keep the versions of this timestamp, the next commit gets its own
+                    self.clock.publish(timestamp);
+                    self.unapplied = true;
+                    logerr!("Failed to apply a commit, it is finished from the write-ahead log on the next start: {}", e);
+                    return Err(e)
+                }
+            }
+            wal.checkpoint()?;
+            self.clock.publish(timestamp);
+        }
+        self.forget_savepoints(only);
+        if !self.has_staged().await {
+            self.end_transaction();
+        }
         
         Ok(())
     }
     
+    /// Discards the changes of the current session on every container and ends its transaction.
     pub async fn rollback(&mut self) -> Result<(), Error> {
+        self.rollback_containers(None).await
+    }
+
+    async fn rollback_containers(&mut self, only : Option<&str>) -> Result<(), Error> {
         
-        for (_, c) in self.container.iter_mut() {
+        for (name, c) in self.container.iter_mut() {
+            if only.is_some_and(|only| only != name) {
+                continue;
+            }
             
-            c.lock().await.rollback().await?;
+            c.lock().await.rollback(&self.session).await?;
             
         }
+        self.forget_savepoints(only);
+        if !self.has_staged().await {
+            self.end_transaction();
+        }
         
         Ok(())
     }
+
+    /// Fails while a commit that couldn't be applied waits in the write-ahead log, writing over the files
+    /// it changes or checkpointing the log would lose it.
+    fn check_applied(&self) -> Result<(), Error> {
+        if self.unapplied {
+            return Err(gerr("A commit failed to apply, restart the database to finish it from the write-ahead log"))
+        }
+        Ok(())
+    }
+
+    fn end_transaction(&mut self) {
+        if let Some(snapshot) = self.transactions.remove(&self.session) {
+            self.clock.release(snapshot);
+        }
+        self.activity.remove(&self.session);
+        self.savepoints.remove(&self.session);
+    }
+
+    /// Rolls back the transactions that ran no statement for `transaction_timeout_seconds`, so a client that
+    /// never ends its BEGIN doesn't keep its snapshot, and the row versions it still sees, forever.
+    pub async fn expire_transactions(&mut self) {
+        let timeout = self.settings.transaction_timeout_seconds;
+        if timeout == 0 {
+            return
+        }
+        let expired : Vec<SessionId> = self.activity.iter()
+            .filter(|(_, last)| last.elapsed() >= Duration::from_secs(timeout))
+            .map(|(session, _)| *session)
+            .collect();
+        if expired.is_empty() {
+            return
+        }
+        let running = self.session;
+        for session in expired {
+            self.session = session;
+            if let Err(e) = self.rollback().await {
+                logerr!("Failed to roll back an idle transaction: {}", e);
+            }
+            // the snapshot is released even when a container failed to roll back
+            self.end_transaction();
+            loginfo!("Rolled back a transaction idle for more than {} seconds", timeout);
+        }
+        self.session = running;
+        self.collect_versions().await;
+    }
+
+    /// Drops what the savepoints of the current session kept for a container committed or rolled back on its own,
+    /// so ROLLBACK TO doesn't stage its changes again.
+    fn forget_savepoints(&mut self, only : Option<&str>) {
+        if let (Some(only), Some(savepoints)) = (only, self.savepoints.get_mut(&self.session)) {
+            for (_, write_sets) in savepoints.iter_mut() {
+                write_sets.retain(|(name, _)| name != only);
+            }
+        }
+    }
+
+    /// Position of the last savepoint of the current session named `name`.
+    fn savepoint_position(&self, name : &str) -> Result<usize, Error> {
+        self.savepoints.get(&self.session)
+            .and_then(|savepoints| savepoints.iter().rposition(|(savepoint, _)| savepoint == name))
+            .ok_or_else(|| gerr(&format!("There is no savepoint named {}", name)))
+    }
+
+    /// Drops the row versions that no open snapshot reads anymore.
+    async fn collect_versions(&self) {
+        let horizon = self.clock.horizon();
+        for (_, c) in self.container.iter() {
+            c.lock().await.collect_versions(horizon).await;
+        }
+    }
     
     pub async fn setup(&self) -> Result<(), Error> {
         let db_path = database_path();
@@ -336,7 +748,7 @@ impl Database{
         Ok(())
     }
     
-    fn get_container_headers(&self, container_name: &str) -> Result<((Vec<String>, Vec<AlbaTypes>),u64), Error> {
+    fn get_container_headers(&self, container_name: &str) -> Result<(ContainerHeaders,u64), Error> {
         let path = format!("{}/{}", self.location, container_name);
         let exists = fs::exists(&path)?;
         
@@ -344,13 +756,15 @@ impl Database{
             let file = fs::File::open(&path)?;
             let mut num_buffer = [0u8;8];
             file.read_exact_at(&mut num_buffer, 0)?;
-            let header_size = u64::from_be_bytes(num_buffer);
+            let header_size = u64::from_be_bytes(num_buffer) & HEADER_SIZE_MASK;
             let mut buffer = vec![0u8;header_size as usize];
             file.read_exact_at(&mut buffer, 8)?;
 
             let mut read = 0;
             let mut column_names = Vec::new();
             let mut column_values = Vec::new();
+            let mut column_nullable = Vec::new();
+            let mut column_unique = Vec::new();
             while read < buffer.len(){
                 let mut cnb = [0u8;2];
                 let mut atb = [0u8;1];
@@ -361,16 +775,30 @@ impl Database{
 
                 let column_name_size = u16::from_be_bytes(cnb);
                 let alba_type_id = u8::from_be_bytes(atb);
-                let column_name = match String::from_utf8(buffer[..(column_name_size as usize)].to_vec()){
+                let mut alba_type = AlbaTypes::from_id(alba_type_id & !(NULLABLE_FLAG | UNIQUE_FLAG))?;
+                if let AlbaTypes::Decimal(_, precision, scale) = &mut alba_type{
+                    *precision = buffer[read];
+                    *scale = buffer[read+1];
+                    read += 2;
+                }
+                if let AlbaTypes::Array(_, element) = &mut alba_type{
+                    if !ARRAY_ELEMENT_IDS.contains(&buffer[read]){
+                        return Err(gerr(&format!("Unknown type id {} for the elements of an ARRAY", buffer[read])))
+                    }
+                    *element = buffer[read];
+                    read += 1;
+                }
+                let column_name = match String::from_utf8(buffer[read..(read+column_name_size as usize)].to_vec()){
                     Ok(a) => a.to_string(),
                     Err(e) => {return Err(gerr(&e.to_string()))}
                 };
                 read += column_name_size as usize;
-                let alba_type = AlbaTypes::from_id(alba_type_id)?;
                 column_names.push(column_name);
                 column_values.push(alba_type);
+                column_nullable.push(alba_type_id & NULLABLE_FLAG != 0);
+                column_unique.push(alba_type_id & UNIQUE_FLAG != 0);
             }
-            return Ok(((column_names,column_values),header_size+8))
+            return Ok(((column_names,column_values,column_nullable,column_unique),header_size+8))
         }
         
         Err(gerr("Container not found"))
@@ -398,29 +826,15 @@ impl Database{
                 if self.container.get(&structure.name).is_some() || fs::exists(&path).unwrap(){
                     return Err(gerr("Failed to create container, there is already a container with this name or a file with this name on the container directory."))
                 }
-                let mut file = fs::File::create_new(&path).unwrap();
-                let mut buffer : Vec<u8> = Vec::new();
-                for i in structure.col_nam.iter().zip(structure.col_val.iter()){
-                    let n = i.0.as_bytes();
-                    let m = i.1.get_id().to_be_bytes();
-                    if n.len() > u16::MAX as usize || m.len() > u16::MAX as usize{
-                        return Err(gerr(&format!("The maximum size in bytes of the column name is {}, and the current size is {}",u16::MAX,n.len())))
-                    }
-
-
-                    let column_name_size : u16 = n.len() as u16;
-                    let mut curr = Vec::new();
-                    curr.extend_from_slice(&column_name_size.to_be_bytes());
-                    curr.extend_from_slice(&m);
-                    curr.extend_from_slice(&n);
-                    buffer.extend_from_slice(&curr);
+                if structure.nullable.len() != structure.col_val.len() || structure.unique.len() != structure.col_val.len(){
+                    return Err(gerr("Failed to create container, the count of column modifiers does not match to the count of values"))
                 }
-                let header_size : u64 = buffer.len() as u64;
-                let mut buff = header_size.to_be_bytes().to_vec();
-                buff.extend_from_slice(&buffer); 
+                let buff = encode_container_headers(&structure.col_nam, &structure.col_val, &structure.nullable, &structure.unique)?;
+                let header_size : u64 = buff.len() as u64 - 8;
+                let mut file = fs::File::create_new(&path).unwrap();
                 file.write_all(&buff).unwrap();
                 self.containers.push(structure.name.clone());
-                let mut el : usize = 0;
+                let mut el : usize = null_bitmap_size(&structure.nullable);
                 for i in structure.col_val.iter(){
                     el += i.size()
                 }
@@ -429,12 +843,14 @@ impl Database{
                     database_path(),
                     el,
                     structure.col_val.clone(), 
-                    MAX_STR_LEN,
                     header_size + 8,
-                    structure.col_nam.clone()
+                    structure.col_nam.clone(),
+                    structure.nullable.clone(),
+                    structure.unique.clone(),
                 ).await.unwrap();
-                self.container.insert(structure.name, c);
+                self.container.insert(structure.name.clone(), c);
                 self.save_containers().unwrap();
+                self.create_unique_indexes(&structure.name).await?;
             },
             AST::CreateRow(structure) => {
                 
@@ -454,8 +870,14 @@ impl Database{
                         structure.col_val.len()
                     )));
                 }
-                let mut val : Vec<AlbaTypes> = container.columns();
-                let cols = container.columns();
+                // omitted columns are NULL when nullable, otherwise they take the zero value of their type,
+                // but a UUID column gets a new UUID so the rows don't share the nil one
+                let mut val : Vec<AlbaTypes> = container.columns().into_iter().zip(container.nullable.iter())
+                    .map(|(c, nullable)| match c {
+                        _ if *nullable => AlbaTypes::NONE,
+                        AlbaTypes::Uuid(_) => AlbaTypes::Uuid(generate_uuid()),
+                        c => c,
+                    }).collect();
                 let mut hm = AHashMap::new();
                 for i in container.headers.iter().enumerate(){
                     hm.insert(i.1.0.clone(),i.0);
@@ -464,100 +886,18 @@ impl Database{
                 for i in structure.col_nam.iter().enumerate(){
                     let j = hm.get(i.1);
                     if let Some(a) = j{
-                        val.insert(*a,structure.col_val[i.0].clone().try_from_existing(cols[*a].clone())?);
+                        val[*a] = container.convert_value(*a, structure.col_val[i.0].clone())?;
+                    }else{
+                        return Err(gerr(&format!("In CREATE ROW, there is no column named {} in the container {}", i.1, structure.container)));
                     }
                 }
 
 
-                container.push_row(&val).await?;
-                if self.settings.auto_commit {
-                    
-                    container.commit().await?;
-                }
+                container.push_row(&self.session, &val).await?;
                 
             },
             AST::Search(structure) => {
-                let mut query : Option<Query> = None;
-                for i in structure.container{
-                    
-                    if let AlbaContainer::Virtual(virt) = i{
-                        let ast = debug_tokens(&virt)?;
-                        let result_query = Box::pin(self.run(ast)).await?;
-                        if let Some(ref mut q) = query{
-                            q.join(result_query);
-                        }else{
-                            query = Some(result_query)
-                        }
-                        continue;
-                    }
-                    
-                    if let AlbaContainer::Real(container_name) = i{
-                        let container = match self.container.get(&container_name){
-                            Some(a) => a,
-                            None => {return Err(gerr(&format!("Failed to perform the query, there is no container named {}",container_name)))}
-                        };
-                        let container_book = container.lock().await;
-                        let header_types = container_book.headers.clone();
-
-                        
-                        let mut headers_hash_map = HashMap::new();
-                        for i in header_types.iter().cloned(){
-                            headers_hash_map.insert(i.0,i.1);
-                        }
-                        let qc = QueryConditions::from_primitive_conditions( structure.conditions.clone(), &headers_hash_map,if let Some(a) = header_types.first(){a.0.clone()}else{return Err(gerr("Error, no primary key found"))}).unwrap();
-                        let qt = qc.query_type().unwrap();
-                        let element_size = container_book.element_size.clone();
-                        let headers_offset = container_book.headers_offset.clone();
-                        let file = container_book.file.clone();
-                        let indexing = container_book.indexing.clone();
-                        drop(container_book);
-                        let result = match qt{
-                            QueryType::Scan => { 
-                                
-                                let r = search(container.to_owned(), SearchArguments{
-                                    element_size,
-                                    header_offset: headers_offset as usize,
-                                    file,
-                                    container_values: header_types,
-                                    conditions: qc,
-                                }).await.unwrap();
-                                
-                                r
-                            },
-                            QueryType::Indexed(query_index_type) => {
-
-                                
-                                let values = match query_index_type{
-                                    crate::query_conditions::QueryIndexType::Strict(t) => indexing.search(t).await,
-                                    crate::query_conditions::QueryIndexType::Range(t) => indexing.search(t).await,
-                                    crate::query_conditions::QueryIndexType::InclusiveRange(t) => indexing.search(t).await,
-                                }?;
-                                loginfo!("values: {:?}",values);
-                                let r = indexed_search(container.to_owned(), SearchArguments{
-                                    element_size,
-                                    header_offset: headers_offset as usize,
-                                    file,
-                                    container_values: header_types,
-                                    conditions: qc,
-                                },&values).await.unwrap();
-
-                                
-                                r
-                            }
-                        };
-
-                        
-                        match query{
-                            Some(ref mut b) => b.join(result),
-                            None => {query = Some(result)}
-                        }
-                    };
-                }
-                if let Some(q) = query{
-                    return Ok(q)
-                }else{
-                    return Err(gerr("Error, no query result found"))
-                }
+                return self.reader().search(structure).await
             },
             AST::EditRow(structure) => {
                 
@@ -589,25 +929,22 @@ impl Database{
             
                 
                 
+                let container_book = container.lock().await;
+                let indexes = container_book.indexes();
                 let qc = QueryConditions::from_primitive_conditions(
                     structure.conditions.clone(),
                     &headers_hash_map,
-                    if let Some(a) = header_types.first() {
-                        a.0.clone()
-                    } else {
-                        logerr!("No primary key found");
-                        return Err(gerr("Error, no primary key found"))
-                    }
-                ).unwrap();
+                    indexes.iter().map(|i| i.0.clone()).collect()
+                )?;
             
                 
-                let container_book = container.lock().await;
-                
                 let qt = qc.query_type().unwrap();
             
                 
                 let mut column_name_idx: AHashMap<String, usize> = AHashMap::new();
                 let mut changes: AHashMap<usize, AlbaTypes> = AHashMap::new();
+                // values computed from each row, like 'counter' + 1
+                let mut computed: Vec<(usize, Expression)> = Vec::new();
                 for i in container_book.headers.iter().enumerate() {
                     column_name_idx.insert(i.1.0.clone(), i.0);
                 }
@@ -618,20 +955,31 @@ impl Database{
                         logerr!("Missing value for column: {}", i.1);
                         return Err(gerr("Failed to execute edit because there is a value missing for one of the columns entered"))
                     };
-                    let id = column_name_idx.get(i.1).unwrap();
-                    changes.insert(*id, val.to_owned());
+                    let id = match column_name_idx.get(i.1) {
+                        Some(a) => *a,
+                        None => return Err(gerr(&format!("In EDIT ROW, there is no column named {} in the container {}", i.1, structure.container)))
+                    };
+                    match val {
+                        Expression::Value(v) => { changes.insert(id, container_book.convert_value(id, v.to_owned())?); },
+                        expression => {
+                            if let Some(c) = expression.columns().into_iter().find(|c| !column_name_idx.contains_key(*c)) {
+                                return Err(gerr(&format!("In EDIT ROW, there is no column named {} in the container {}", c, structure.container)))
+                            }
+                            computed.push((id, expression.to_owned()));
+                        }
+                    }
                 }
                 
             
                 let element_size = container_book.element_size.clone();
                 let headers_offset = container_book.headers_offset.clone();
                 let file = container_book.file.clone();
-                let indexing = container_book.indexing.clone();
+                let column_names: Vec<String> = header_types.iter().map(|h| h.0.clone()).collect();
                 
                 drop(container_book);
                 
             
-                let result: Vec<(Vec<AlbaTypes>, u64)> = {
+                let mut result: Vec<(Vec<AlbaTypes>, u64)> = {
                     match qt {
                         QueryType::Scan => {
                             
@@ -641,24 +989,13 @@ impl Database{
                                 file,
                                 container_values: header_types,
                                 conditions: qc,
-                            }).await.unwrap()
+                                session: self.session,
+                                snapshot: self.snapshot,
+                            }).await?
                         }
-                        QueryType::Indexed(query_index_type) => {
+                        QueryType::Indexed(index, query_index_type) => {
                             
-                            let values = match query_index_type {
-                                crate::query_conditions::QueryIndexType::Strict(t) => {
-                                    
-                                    indexing.search(t).await
-                                }
-                                crate::query_conditions::QueryIndexType::Range(t) => {
-                                    
-                                    indexing.search(t).await
-                                }
-                                crate::query_conditions::QueryIndexType::InclusiveRange(t) => {
-                                    
-                                    indexing.search(t).await
-                                }
-                            }.unwrap();
+                            let values = query_index_type.search(&indexes[index].1).await?;
                             
                             indexed_search_direct(container.clone(), SearchArguments {
                                 element_size,
@@ -666,34 +1003,44 @@ impl Database{
                                 file,
                                 container_values: header_types,
                                 conditions: qc,
-                            }, &values).await.unwrap()
-                        }
-                    }.iter_mut().map(|f| {
-                        
-                        for (index, new_value) in &changes {
-                            f.0[*index] = new_value.clone();
+                                session: self.session,
+                                snapshot: self.snapshot,
+                            }, &values).await?
                         }
-                        f.to_owned()
-                    }).collect()
+                    }
                 };
                 
             
                 
                 let container_book = container.lock().await;
+                let unique_columns = container_book.unique_columns();
+                let touches_unique = changes.keys().chain(computed.iter().map(|(index, _)| index)).any(|index| unique_columns.contains(index));
+                let saved = container_book.write_set(&self.session).await;
+                for f in result.iter_mut() {
+                    // computed values see the row as it was before this edit
+                    if !computed.is_empty() {
+                        let current: HashMap<String, AlbaTypes> = column_names.iter().cloned().zip(f.0.iter().cloned()).collect();
+                        for (index, expression) in &computed {
+                            f.0[*index] = container_book.convert_value(*index, expression.evaluate(&current)?)?;
+                        }
+                    }
+                    for (index, new_value) in &changes {
+                        f.0[*index] = new_value.clone();
+                    }
+                }
                 
-                let mvcc = container_book.mvcc.clone();
-                drop(container_book);
-                
-            
-                
-                let mut mvcc = mvcc.lock().await;
-                
-                
+                let mut edited = Vec::with_capacity(result.len());
                 for i in result {
                     let ind = i.1.saturating_sub(headers_offset).saturating_div(element_size as u64);
                     
                     
-                    mvcc.0.insert(ind, (false, i.0));
+                    container_book.stage(&self.session, ind, false, i.0).await;
+                    edited.push(ind);
+                }
+                // checked once every row is edited, so values can move between the rows of the same edit
+                if touches_unique && let Err(e) = container_book.check_unique(&self.session, &edited).await {
+                    container_book.restore_write_set(&self.session, saved).await?;
+                    return Err(e);
                 }
             
                 
@@ -711,12 +1058,12 @@ impl Database{
                     headers_hash_map.insert(i.0,i.1);
                 }
                 
-                let qc = QueryConditions::from_primitive_conditions( if let Some(c) = structure.conditions{c}else{(Vec::new(),Vec::new())}, &headers_hash_map,if let Some(a) = header_types.first(){a.0.clone()}else{return Err(gerr("Error, no primary key found"))})?;
                 let container_book = container.lock().await;
+                let indexes = container_book.indexes();
+                let qc = QueryConditions::from_primitive_conditions( structure.conditions, &headers_hash_map,indexes.iter().map(|i| i.0.clone()).collect())?;
                 let element_size = container_book.element_size.clone();
                 let headers_offset = container_book.headers_offset.clone();
                 let file = container_book.file.clone();
-                let indexing = container_book.indexing.clone();
                 let qt = qc.query_type()?;
                 
                 drop(container_book);
@@ -729,19 +1076,17 @@ impl Database{
                             file,
                             container_values: header_types,
                             conditions: qc,
+                            session: self.session,
+                            snapshot: self.snapshot,
                         }).await?;
                         
                         
                         r
                     }
-                    QueryType::Indexed(query_index_type) => {
+                    QueryType::Indexed(index, query_index_type) => {
 
                         
-                        let values = match query_index_type{
-                            crate::query_conditions::QueryIndexType::Strict(t) => indexing.search(t).await,
-                            crate::query_conditions::QueryIndexType::Range(t) => indexing.search(t).await,
-                            crate::query_conditions::QueryIndexType::InclusiveRange(t) => indexing.search(t).await,
-                        }?;
+                        let values = query_index_type.search(&indexes[index].1).await?;
 
                         
                         let r= indexed_search_direct(container.clone(), SearchArguments{
@@ -750,6 +1095,8 @@ impl Database{
                             file,
                             container_values: header_types,
                             conditions: qc,
+                            session: self.session,
+                            snapshot: self.snapshot,
                         },&values).await?;
 
                         
@@ -758,11 +1105,10 @@ impl Database{
                 };
                 
                 let container_book = container.lock().await;
-                let mut mvcc = container_book.mvcc.lock().await;
                 for i in result{
                     let k = (i.1-headers_offset)/element_size as u64;
                     
-                    mvcc.0.insert(k, (true,i.0));
+                    container_book.stage(&self.session, k, true, i.0).await;
                 }
 
                 
@@ -781,7 +1127,15 @@ impl Database{
                         self.containers.remove(i);
                         
                     }
-                    self.container.remove(&structure.container);
+                    if let Some(c) = self.container.remove(&structure.container) {
+                        let c = c.lock().await;
+                        let count = (c.len().await?.saturating_sub(c.headers_offset)) / c.element_size as u64;
+                        for i in 0..count {
+                            for reference in c.stored_references(c.headers_offset + i * c.element_size as u64).await? {
+                                let _ = tokio::fs::remove_file(c.reference_path(&reference)).await;
+                            }
+                        }
+                    }
                     
                     let path = format!("{}/{}", self.location, structure.container);
                     let _ = tokio::fs::remove_file(path.clone()).await;
@@ -789,24 +1143,87 @@ impl Database{
                     let _ = tokio::fs::remove_file(path.clone()).await;
                     
                     self.save_containers()?;
+                    let (dropped, kept) : (Vec<IndexDefinition>, Vec<IndexDefinition>) = self.indexes.drain(..).partition(|i| i.container == structure.container);
+                    self.indexes = kept;
+                    if !dropped.is_empty() {
+                        self.save_indexes()?;
+                    }
+                    let _ = tokio::fs::remove_dir_all(format!("{}/{}/{}", self.location, SECONDARY_INDEXES_FOLDER, structure.container)).await;
                     
                 } else {
                     
                     return Err(gerr(&format!("There is no database with the name {}", structure.container)));
                 }
             },
+            AST::AlterContainer(structure) => {
+                self.alter_container(structure, min_column, max_columns).await?;
+            },
+            AST::CreateIndex(structure) => {
+                self.create_index(structure).await?;
+            },
+            AST::DeleteIndex(structure) => {
+                let position = match self.indexes.iter().position(|i| i.name == structure.name){
+                    Some(a) => a,
+                    None => return Err(gerr(&format!("There is no index named {}", structure.name)))
+                };
+                if structure.name.starts_with(UNIQUE_INDEX_PREFIX){
+                    return Err(gerr(&format!("The index {} keeps a UNIQUE column unique, it is deleted with the column", structure.name)))
+                }
+                let definition = self.indexes.remove(position);
+                if let Some(c) = self.container.get(&definition.container){
+                    c.lock().await.secondary_indexes.retain(|i| i.name != definition.name);
+                }
+                self.save_indexes()?;
+                let path = format!("{}/{}.index", self.location, index_file_id(&definition.container, Some(&definition.name)));
+                let _ = tokio::fs::remove_file(path).await;
+            },
+            AST::Begin => {
+                if self.transactions.contains_key(&self.session){
+                    return Err(gerr("There is already a transaction open in this session"));
+                }
+                // every statement of the transaction reads at the snapshot taken here
+                let snapshot = self.clock.take();
+                self.transactions.insert(self.session, snapshot);
+                self.activity.insert(self.session, Instant::now());
+            },
+            AST::Savepoint(structure) => {
+                if !self.transactions.contains_key(&self.session){
+                    return Err(gerr("SAVEPOINT can only be used inside a transaction, run BEGIN first"));
+                }
+                let mut write_sets = Vec::with_capacity(self.container.len());
+                for (name, c) in self.container.iter(){
+                    write_sets.push((name.clone(), c.lock().await.write_set(&self.session).await));
+                }
+                // a name used again refers to the newest savepoint, like in SQL
+                self.savepoints.entry(self.session).or_default().push((structure.name, write_sets));
+            },
+            AST::RollbackTo(structure) => {
+                let position = self.savepoint_position(&structure.name)?;
+                let savepoints = self.savepoints.get_mut(&self.session).unwrap();
+                savepoints.truncate(position + 1);
+                let mut write_sets : HashMap<String, WriteSet> = savepoints[position].1.iter().cloned().collect();
+                for (name, c) in self.container.iter(){
+                    c.lock().await.restore_write_set(&self.session, write_sets.remove(name).unwrap_or_default()).await?;
+                }
+            },
+            AST::Release(structure) => {
+                let position = self.savepoint_position(&structure.name)?;
+                if let Some(savepoints) = self.savepoints.get_mut(&self.session){
+                    savepoints.truncate(position);
+                }
+            },
             AST::Commit(structure) => {
                 
                 match structure.container {
                     Some(container) => {
-                        match self.container.get_mut(&container) {
-                            Some(a) => {
+                        match self.container.contains_key(&container) {
+                            true => {
                                 
-                                a.lock().await.commit().await.unwrap();
+                                self.commit_containers(Some(&container)).await?;
                                 
                                 return Ok(Query::new(Vec::new()));
                             },
-                            None => {
+                            false => {
                                 
                                 return Err(gerr(&format!("There is no container named {}", container)));
                             }
@@ -823,14 +1240,14 @@ impl Database{
                 
                 match structure.container {
                     Some(container) => {
-                        match self.container.get_mut(&container) {
-                            Some(a) => {
+                        match self.container.contains_key(&container) {
+                            true => {
                                 
-                                a.lock().await.rollback().await?;
+                                self.rollback_containers(Some(&container)).await?;
                                 
                                 return Ok(Query::new(Vec::new()));
                             },
-                            None => {
+                            false => {
                                 
                                 return Err(gerr(&format!("There is no container named {}", container)));
                             }
@@ -848,10 +1265,604 @@ impl Database{
         Ok(Query::new_none(Vec::new()))
     }
     
-    pub async fn execute(&mut self, input: &str, arguments: Vec<String>) -> Result<Query, Error> {
-        let ast = parse(input.to_owned(), arguments)?;
-        let result = self.run(ast).await?;
-        Ok(result)
+    /// Reads every committed row of a container.
+    async fn create_index(&mut self, structure : AstCreateIndex) -> Result<(), Error> {
+        if structure.name.is_empty() || structure.name.len() > 60 || structure.name.contains('/') || structure.name.starts_with('.'){
+            return Err(gerr("Failed to create index, the name must have between 1 and 60 characters, no '/' and not start with '.'"))
+        }
+        if self.indexes.iter().any(|i| i.name == structure.name){
+            return Err(gerr(&format!("Failed to create index, there is already an index named {}", structure.name)))
+        }
+        let container = match self.container.get(&structure.container){
+            Some(a) => a.clone(),
+            None => return Err(gerr(&format!("There is no container named {}", structure.container)))
+        };
+        let mut container = container.lock().await;
+        let column = match container.column_names().iter().position(|c| *c == structure.column){
+            Some(a) => a,
+            None => return Err(gerr(&format!("There is no column named {} in the container {}", structure.column, structure.container)))
+        };
+        if column == 0{
+            return Err(gerr(&format!("Failed to create index, {} is the first column of the container {} which is always indexed", structure.column, structure.container)))
+        }
+        self.add_index(&mut container, structure.name, column).await
+    }
+
+    /// Builds the index `name` over `column` from the rows of the container and registers it.
+    async fn add_index(&mut self, container : &mut Container, name : String, column : usize) -> Result<(), Error> {
+        let elements = container.index_elements(column).await?;
+        let indexing = Indexing::build(&index_file_id(&container.name, Some(&name)), elements).await?;
+        container.secondary_indexes.push(SecondaryIndex { name: name.clone(), column, indexing });
+        self.indexes.push(IndexDefinition { name, container: container.name.clone(), column: container.column_names()[column].clone() });
+        self.save_indexes()
+    }
+
+    /// Gives every UNIQUE column of the container without an index one, so new values are checked
+    /// against the stored ones through it.
+    async fn create_unique_indexes(&mut self, container_name : &str) -> Result<(), Error> {
+        let container = match self.container.get(container_name){
+            Some(a) => a.clone(),
+            None => return Ok(())
+        };
+        let mut container = container.lock().await;
+        for column in container.unique_columns(){
+            if column == 0 || container.secondary_indexes.iter().any(|i| i.column == column){
+                continue;
+            }
+            let suffix : String = rand::thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect();
+            self.add_index(&mut container, format!("{}{}", UNIQUE_INDEX_PREFIX, suffix), column).await?;
+        }
+        Ok(())
+    }
+
+    /// Applies an ALTER CONTAINER by rewriting every live row into the new layout in a temporary file,
+    /// building a new index next to it and then renaming both over the old ones.
+    async fn alter_container(&mut self, structure : AstAlterContainer, min_column : usize, max_columns : usize) -> Result<(), Error> {
+        let container = match self.container.get(&structure.container){
+            Some(a) => a.clone(),
+            None => return Err(gerr(&format!("There is no container named {}", structure.container)))
+        };
+        let old = container.lock().await;
+        if old.has_staged().await{
+            return Err(gerr(&format!("The container {} has uncommitted changes, commit or rollback them before altering it", structure.container)))
+        }
+
+        let mut names = old.column_names();
+        let mut types = old.columns();
+        let mut nullable = old.nullable.clone();
+        let mut unique = old.unique.clone();
+        // for every column of the new layout, the column of the old layout its value comes from
+        let mut sources : Vec<Option<usize>> = (0..names.len()).map(Some).collect();
+        let mut dropped : Option<usize> = None;
+        match structure.change{
+            AlterColumn::Add { name, col_val, nullable: n } => {
+                if names.contains(&name){
+                    return Err(gerr(&format!("The container {} already has a column named {}", structure.container, name)))
+                }
+                if names.len() + 1 > max_columns{
+                    return Err(gerr("Failed to alter container, the count of columns would be higher than the maximum set on the settings file."))
+                }
+                names.push(name);
+                types.push(col_val);
+                nullable.push(n);
+                unique.push(false);
+                sources.push(None);
+            },
+            AlterColumn::Drop { name } => {
+                let id = match names.iter().position(|c| *c == name){
+                    Some(a) => a,
+                    None => return Err(gerr(&format!("There is no column named {} in the container {}", name, structure.container)))
+                };
+                // the next column would become the primary key without its values being unique
+                if id == 0{
+                    return Err(gerr(&format!("The column {} is the primary key of the container {}, it can't be dropped", name, structure.container)))
+                }
+                if let Some(i) = old.secondary_indexes.iter().find(|i| i.column == id && !i.name.starts_with(UNIQUE_INDEX_PREFIX)){
+                    return Err(gerr(&format!("The column {} is used by the index {}, delete the index before dropping the column", name, i.name)))
+                }
+                if names.len() - 1 < min_column{
+                    return Err(gerr(&format!("Failed to alter container, it must have at least {} columns", min_column)))
+                }
+                names.remove(id);
+                types.remove(id);
+                nullable.remove(id);
+                unique.remove(id);
+                sources.remove(id);
+                dropped = Some(id);
+            },
+            AlterColumn::Rename { name, new_name } => {
+                if names.contains(&new_name){
+                    return Err(gerr(&format!("The container {} already has a column named {}", structure.container, new_name)))
+                }
+                match names.iter().position(|c| *c == name){
+                    Some(id) => names[id] = new_name,
+                    None => return Err(gerr(&format!("There is no column named {} in the container {}", name, structure.container)))
+                }
+            },
+        }
+
+        let old_types = old.columns();
+        let old_bitmap = null_bitmap_size(&old.nullable);
+        let mut old_offsets = Vec::with_capacity(old_types.len());
+        let mut offset = old_bitmap;
+        for ty in old_types.iter(){
+            old_offsets.push(offset);
+            offset += ty.size();
+        }
+        let bitmap = null_bitmap_size(&nullable);
+        let element_size = bitmap + types.iter().map(|t| t.size()).sum::<usize>();
+
+        let headers = encode_container_headers(&names, &types, &nullable, &unique)?;
+        let headers_offset = headers.len() as u64;
+        let temporary = format!("{}/{}", self.location, alter_file_name(&structure.container));
+        let temporary_index = format!("{}/{}.index", self.location, alter_index_id(&structure.container, None));
+        let _ = fs::remove_file(&temporary_index);
+        let index = Indexing::load_index(&alter_index_id(&structure.container, None)).await?;
+        // (name, column in the old layout, column in the new layout, index being built)
+        let mut secondary = Vec::with_capacity(old.secondary_indexes.len());
+        // the index that kept a dropped UNIQUE column unique goes with it
+        let dropped_index = dropped.and_then(|d| old.secondary_indexes.iter().find(|i| i.column == d)).map(|i| i.name.clone());
+        for i in old.secondary_indexes.iter().filter(|i| Some(i.column) != dropped){
+            let id = alter_index_id(&structure.container, Some(&i.name));
+            let _ = fs::remove_file(format!("{}/{}.index", self.location, id));
+            let column = sources.iter().position(|s| *s == Some(i.column)).unwrap_or(i.column);
+            secondary.push((i.name.clone(), i.column, column, Indexing::load_index(&id).await?));
+        }
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(&headers)?;
+
+        let graveyard = old.graveyard.lock().await.clone();
+        let count = (old.len().await?.saturating_sub(old.headers_offset)) / old.element_size as u64;
+        let mut written : u64 = 0;
+        let mut removed_references = Vec::new();
+        for i in 0..count{
+            if graveyard.contains(&i){
+                continue;
+            }
+            let mut row = vec![0u8; old.element_size];
+            old.file.lock().await.read_exact_at(&mut row, old.headers_offset + i * old.element_size as u64)?;
+            if row.iter().all(|b| *b == 0){
+                continue;
+            }
+            let is_null = |column : usize| old_bitmap > 0 && row[column / 8] & (1 << (column % 8)) != 0;
+            let mut buffer = vec![0u8; bitmap];
+            for (column, source) in sources.iter().enumerate(){
+                match source{
+                    Some(s) if !is_null(*s) => buffer.extend_from_slice(&row[old_offsets[*s]..old_offsets[*s] + old_types[*s].size()]),
+                    _ => {
+                        if nullable[column]{
+                            buffer[column / 8] |= 1 << (column % 8);
+                        }
+                        match types[column]{
+                            AlbaTypes::Uuid(_) if !nullable[column] => buffer.extend_from_slice(&generate_uuid().to_be_bytes()),
+                            _ => buffer.extend(vec![0u8; types[column].size()]),
+                        }
+                    }
+                }
+            }
+            if let Some(d) = dropped && !is_null(d) && old_types[d].is_referenced(){
+                let mut id = [0u8; 8];
+                id.copy_from_slice(&row[old_offsets[d]..old_offsets[d] + 8]);
+                let id = u64::from_be_bytes(id);
+                if id != 0{
+                    removed_references.push(id.to_string());
+                }
+            }
+            let row_offset = headers_offset + written * element_size as u64;
+            file.write_all(&buffer)?;
+            let values = old.deserialize_row(&row).await?;
+            if let Some(Some(s)) = sources.first(){
+                let key = &values[*s];
+                if *key != AlbaTypes::NONE{
+                    index.add(key.get_index(), row_offset).await?;
+                }
+            }
+            for (_, column, _, indexing) in secondary.iter(){
+                if values[*column] != AlbaTypes::NONE{
+                    indexing.add(values[*column].get_index(), row_offset).await?;
+                }
+            }
+            written += 1;
+        }
+        file.sync_all()?;
+        index.flush().await?;
+        for (_, _, _, indexing) in secondary.iter(){
+            indexing.flush().await?;
+        }
+        drop(file);
+        drop(index);
+
+        let path = format!("{}/{}", self.location, structure.container);
+        fs::rename(&temporary, &path)?;
+        fs::File::open(&self.location)?.sync_all()?;
+        fs::rename(&temporary_index, format!("{}/{}.index", self.location, structure.container))?;
+        for (name, _, _, _) in secondary.iter(){
+            fs::rename(
+                format!("{}/{}.index", self.location, alter_index_id(&structure.container, Some(name))),
+                format!("{}/{}.index", self.location, index_file_id(&structure.container, Some(name)))
+            )?;
+        }
+        fs::File::open(&self.location)?.sync_all()?;
+        if !secondary.is_empty(){
+            fs::File::open(format!("{}/{}/{}", self.location, SECONDARY_INDEXES_FOLDER, structure.container))?.sync_all()?;
+        }
+        // a crash before this point only leaks the reference files of the dropped column
+        for reference in removed_references{
+            let _ = fs::remove_file(old.reference_path(&reference));
+        }
+        drop(old);
+
+        let c = Container::new(structure.container.clone(),
+            &path,
+            self.location.clone(),
+            element_size,
+            types.clone(),
+            headers_offset,
+            names.clone(),
+            nullable.clone(),
+            unique.clone(),
+        ).await?;
+        {
+            let mut c = c.lock().await;
+            for (name, _, column, _) in secondary.drain(..){
+                if let Some(definition) = self.indexes.iter_mut().find(|i| i.name == name){
+                    definition.column = names[column].clone();
+                }
+                let indexing = Indexing::load_index(&index_file_id(&structure.container, Some(&name))).await?;
+                c.secondary_indexes.push(SecondaryIndex { name, column, indexing });
+            }
+        }
+        self.container.insert(structure.container.clone(), c);
+        if let Some(position) = self.containers.iter().position(|c| *c == structure.container){
+            if let Some(h) = self.headers.get_mut(position){
+                *h = (names, types, nullable, unique);
+            }
+        }
+        if let Some(name) = dropped_index{
+            self.indexes.retain(|i| i.name != name);
+            let _ = fs::remove_file(format!("{}/{}.index", self.location, index_file_id(&structure.container, Some(&name))));
+        }
+        self.save_containers()?;
+        self.save_indexes()?;
+        self.create_unique_indexes(&structure.container).await
+    }
+    
+    /// Runs a statement for a client session. Its writes go to the write set of the session, which
+    /// COMMIT and ROLLBACK act on. Outside a BEGIN the statement is committed on its own when
+    /// `auto_commit` is set. The statement reads at the snapshot of the transaction of the session,
+    /// or at one of its own outside a BEGIN.
+    pub async fn execute(&mut self, session: SessionId, ast: AST) -> Result<Query, Error> {
+        self.session = session;
+        let statement_snapshot = match self.transactions.get(&session){
+            Some(snapshot) => {
+                self.activity.insert(session, Instant::now());
+                self.snapshot = *snapshot;
+                None
+            },
+            None => {
+                self.snapshot = self.clock.take();
+                Some(self.snapshot)
+            }
+        };
+        let writes = matches!(ast, AST::CreateRow(_) | AST::EditRow(_) | AST::DeleteRow(_));
+        let rewrites = matches!(ast, AST::CreateContainer(_) | AST::DeleteContainer(_) | AST::AlterContainer(_) | AST::CreateIndex(_) | AST::DeleteIndex(_));
+        let mut result = match self.check_applied(){
+            Err(e) if rewrites => Err(e),
+            _ => self.run(ast).await,
+        };
+        if writes && self.settings.auto_commit && !self.transactions.contains_key(&session){
+            let finished = if result.is_ok(){
+                match self.commit().await{
+                    // the session has no transaction to keep a statement that can't be committed
+                    Err(e) => {
+                        if let Err(rollback) = self.rollback().await{
+                            logerr!("Failed to roll back a statement that couldn't be committed: {}", rollback);
+                        }
+                        Err(e)
+                    },
+                    ok => ok,
+                }
+            }else{
+                self.rollback().await
+            };
+            if let Err(e) = finished{
+                result = Err(e);
+            }
+        }
+        if let Some(snapshot) = statement_snapshot{
+            self.clock.release(snapshot);
+        }
+        self.collect_versions().await;
+        result
+    }
+
+    /// Reader for a SEARCH of `session` that runs without the lock of the database, at the snapshot of the open
+    /// transaction of the session or at the last commit. The snapshot is held until the reader is given to `end_read`.
+    pub fn begin_read(&mut self, session: SessionId) -> Reader {
+        let snapshot = match self.transactions.get(&session){
+            Some(snapshot) => {
+                self.activity.insert(session, Instant::now());
+                self.clock.hold(*snapshot)
+            },
+            None => self.clock.take(),
+        };
+        Reader{ container: self.container.clone(), session, snapshot }
+    }
+
+    pub async fn end_read(&mut self, reader: Reader) {
+        self.clock.release(reader.snapshot);
+        self.collect_versions().await;
+    }
+
+    /// Reader of the running statement, at its snapshot.
+    fn reader(&self) -> Reader {
+        Reader{ container: self.container.clone(), session: self.session, snapshot: self.snapshot }
+    }
+
+    /// Whether the current session has uncommitted changes on any container.
+    async fn has_staged(&self) -> bool{
+        for (_, c) in self.container.iter(){
+            if !c.lock().await.staged(&self.session).await.is_empty(){
+                return true
+            }
+        }
+        false
+    }
+}
+
+/// What a SEARCH reads: the containers of the database, and the session and snapshot of the statement.
+/// It doesn't borrow the database, so a search can run without holding its lock while other sessions commit,
+/// see `Database::begin_read`.
+pub struct Reader{
+    container : HashMap<String,Arc<Mutex<Container>>>,
+    session : SessionId,
+    snapshot : u64,
+}
+
+impl Reader{
+    pub async fn search(&self, mut structure : AstSearch) -> Result<Query, Error> {
+        let mut query : Option<Query> = None;
+        if let [AlbaContainer::Real(base)] = structure.container.as_slice() && !structure.joins.is_empty(){
+            let joined = self.search_join(base, &structure.joins, structure.conditions.clone()).await?;
+            let names = &joined.rows.0;
+            for p in structure.projection.iter_mut(){
+                if let Projection::Column(name) | Projection::Aggregate(_, Some(name)) = p{
+                    *name = qualify(name, names)?;
+                }
+            }
+            for name in structure.group_by.iter_mut().chain(structure.order_by.iter_mut().map(|o| &mut o.0)){
+                *name = qualify(name, names)?;
+            }
+            query = Some(joined);
+            structure.container.clear();
+        }
+        let order = ResultOrder{ columns: structure.order_by.clone(), limit: structure.limit, offset: structure.offset };
+        let aggregated = !structure.group_by.is_empty() || structure.projection.iter().any(|p| matches!(p, Projection::Aggregate(..)));
+        // the order and the limit apply to the groups, so every matching row has to reach the aggregation
+        let row_order = if aggregated { ResultOrder::default() } else { order.clone() };
+        for i in structure.container{
+            
+            if let AlbaContainer::Virtual(virt) = i{
+                let result_query = match debug_tokens(&virt)?{
+                    AST::Search(structure) => Box::pin(self.search(structure)).await?,
+                    _ => return Err(gerr("Failed to perform the query, a virtual container must be a SEARCH")),
+                };
+                if let Some(ref mut q) = query{
+                    q.join(result_query);
+                }else{
+                    query = Some(result_query)
+                }
+                continue;
+            }
+            
+            if let AlbaContainer::Real(container_name) = i{
+                let container = match self.container.get(&container_name){
+                    Some(a) => a,
+                    None => {return Err(gerr(&format!("Failed to perform the query, there is no container named {}",container_name)))}
+                };
+                let container_book = container.lock().await;
+                let header_types = container_book.headers.clone();
+
+                
+                let mut headers_hash_map = HashMap::new();
+                for i in header_types.iter().cloned(){
+                    headers_hash_map.insert(i.0,i.1);
+                }
+                for p in structure.projection.iter(){
+                    if let Projection::Column(name) | Projection::Aggregate(_, Some(name)) = p && !headers_hash_map.contains_key(name){
+                        return Err(gerr(&format!("Failed to perform the query, there is no column named {} in the container {}", name, container_name)))
+                    }
+                }
+                let indexes = container_book.indexes();
+                let qc = QueryConditions::from_primitive_conditions( structure.conditions.clone(), &headers_hash_map,indexes.iter().map(|i| i.0.clone()).collect())?;
+                let qt = qc.query_type().unwrap();
+                let element_size = container_book.element_size.clone();
+                let headers_offset = container_book.headers_offset.clone();
+                let file = container_book.file.clone();
+                drop(container_book);
+                let result = match qt{
+                    QueryType::Scan => { 
+                        
+                        let r = search(container.to_owned(), SearchArguments{
+                            element_size,
+                            header_offset: headers_offset as usize,
+                            file,
+                            container_values: header_types,
+                            conditions: qc,
+                            session: self.session,
+                            snapshot: self.snapshot,
+                        }, &row_order).await?;
+                        
+                        r
+                    },
+                    QueryType::Indexed(index, query_index_type) => {
+
+                        
+                        let values = query_index_type.search(&indexes[index].1).await?;
+                        loginfo!("values: {:?}",values);
+                        let r = indexed_search(container.to_owned(), SearchArguments{
+                            element_size,
+                            header_offset: headers_offset as usize,
+                            file,
+                            container_values: header_types,
+                            conditions: qc,
+                            session: self.session,
+                            snapshot: self.snapshot,
+                        },&values, &row_order).await?;
+
+                        
+                        r
+                    }
+                };
+
+                
+                match query{
+                    Some(ref mut b) => b.join(result),
+                    None => {query = Some(result)}
+                }
+            };
+        }
+        if let Some(mut q) = query{
+            if aggregated{
+                q = aggregate(&q, &structure.projection, &structure.group_by)?;
+            }
+            order.apply(&mut q)?;
+            if !aggregated{
+                project(&mut q, &structure.projection)?;
+            }
+            Ok(q)
+        }else{
+            Err(gerr("Error, no query result found"))
+        }
+    }
+
+    async fn scan_container(&self, container_name : &str) -> Result<(Vec<String>, Vec<AlbaTypes>, Vec<Vec<AlbaTypes>>), Error> {
+        let container = match self.container.get(container_name){
+            Some(a) => a,
+            None => return Err(gerr(&format!("Failed to perform the query, there is no container named {}", container_name)))
+        };
+        let arguments = scan_arguments(container, self.session, self.snapshot).await?;
+        let book = container.lock().await;
+        let (names, types) = (book.column_names(), book.columns());
+        drop(book);
+        let rows = search(container.clone(), arguments, &ResultOrder::default()).await?.rows.1;
+        Ok((names, types, rows))
+    }
+
+    /// Runs a SEARCH with JOIN clauses. The rows of the first container are joined with every joined
+    /// container in turn, looking the matches up in the index of the join column when there is one
+    /// and through a hash table of the joined container otherwise. WHERE is applied to the joined rows.
+    async fn search_join(&self, base : &str, joins : &[AstJoin], conditions : PrimitiveQueryConditions) -> Result<Query, Error> {
+        let (base_names, mut types, mut rows) = self.scan_container(base).await?;
+        let mut names : Vec<String> = base_names.iter().map(|n| format!("{}.{}", base, n)).collect();
+
+        for join in joins{
+            let container = match self.container.get(&join.container){
+                Some(a) => a,
+                None => return Err(gerr(&format!("Failed to perform the query, there is no container named {}", join.container)))
+            };
+            let book = container.lock().await;
+            let (right_names, right_types) = (book.column_names(), book.columns());
+            let right_qualified : Vec<String> = right_names.iter().map(|n| format!("{}.{}", join.container, n)).collect();
+            // either side of the '=' may name the joined container
+            let columns = |l : &str, r : &str| -> Result<Option<(usize, usize)>, Error>{
+                let (l, r) = (qualify(l, &names)?, qualify(r, &right_qualified)?);
+                Ok(names.iter().position(|n| *n == l).zip(right_qualified.iter().position(|n| *n == r)))
+            };
+            let (left_column, right_column) = match columns(&join.left, &join.right)?.or(columns(&join.right, &join.left)?){
+                Some(a) => a,
+                None => return Err(gerr(&format!("Failed to join {}, ON must compare a column of {} with a column of the containers before it, but found {} = {}", join.container, join.container, join.left, join.right)))
+            };
+            let index = book.indexes().into_iter().find(|i| i.0 == right_names[right_column]).map(|i| i.1);
+            drop(book);
+
+            let mut joined = Vec::with_capacity(rows.len());
+            let mut append = |row : Vec<AlbaTypes>, matches : Vec<&Vec<AlbaTypes>>|{
+                if matches.is_empty(){
+                    if join.outer{
+                        let mut r = row;
+                        r.extend(std::iter::repeat_n(AlbaTypes::NONE, right_names.len()));
+                        joined.push(r);
+                    }
+                    return;
+                }
+                for m in matches{
+                    let mut r = row.clone();
+                    r.extend(m.iter().cloned());
+                    joined.push(r);
+                }
+            };
+            match index{
+                Some(index) => {
+                    // index nested loop, the rows found for a key are kept for the next rows with the same key.
+                    // The value is looked up in the form of the joined column, and the rows sharing its key are
+                    // compared with it again since a key is only a prefix of the value
+                    let right_type = right_types[right_column].clone();
+                    let arguments = scan_arguments(container, self.session, self.snapshot).await?;
+                    let mut found : HashMap<u64, Vec<Vec<AlbaTypes>>> = HashMap::new();
+                    for row in rows{
+                        let key = row[left_column].clone();
+                        if key == AlbaTypes::NONE{
+                            append(row, Vec::new());
+                            continue;
+                        }
+                        let Ok(k) = right_type.try_from_existing(key.clone()).map(|value| value.get_index()) else {
+                            append(row, Vec::new());
+                            continue;
+                        };
+                        if !found.contains_key(&k){
+                            let offsets = index.search(k).await?;
+                            let r = indexed_search(container.clone(), arguments.clone(), &offsets, &ResultOrder::default()).await?;
+                            found.insert(k, r.rows.1);
+                        }
+                        append(row, found[&k].iter().filter(|r| compare_values(&r[right_column], &key) == std::cmp::Ordering::Equal).collect());
+                    }
+                },
+                None => {
+                    // hash join over `join_key`, the rows sharing a key are compared with the value again
+                    let (_, _, right_rows) = self.scan_container(&join.container).await?;
+                    let mut table : HashMap<u64, Vec<usize>> = HashMap::new();
+                    for (i, r) in right_rows.iter().enumerate(){
+                        if r[right_column] != AlbaTypes::NONE{
+                            table.entry(join_key(&r[right_column])).or_default().push(i);
+                        }
+                    }
+                    for row in rows{
+                        let key = &row[left_column];
+                        let matches : Vec<&Vec<AlbaTypes>> = match table.get(&join_key(key)){
+                            Some(m) if *key != AlbaTypes::NONE => m.iter().map(|i| &right_rows[*i]).filter(|r| compare_values(&r[right_column], key) == std::cmp::Ordering::Equal).collect(),
+                            _ => Vec::new(),
+                        };
+                        append(row, matches);
+                    }
+                },
+            }
+            rows = joined;
+            names.extend(right_qualified);
+            types.extend(right_types);
+        }
+
+        let mut conditions = conditions;
+        if let Some(expression) = conditions.as_mut(){
+            expression.columns_mut(&mut |column| {
+                *column = qualify(column, &names)?;
+                Ok(())
+            })?;
+        }
+        let mut headers = HashMap::new();
+        for (name, column_type) in names.iter().zip(types.iter()){
+            headers.insert(name.clone(), column_type.clone());
+        }
+        let qc = QueryConditions::from_primitive_conditions(conditions, &headers, Vec::new())?;
+        let mut query = Query::new(types);
+        for row in rows{
+            let data = names.iter().cloned().zip(row.iter().cloned()).collect();
+            if qc.row_match(&Row{ data })?{
+                query.rows.1.push(row);
+            }
+        }
+        query.rows.0 = names;
+        Ok(query)
     }
 }
 
@@ -879,7 +1890,8 @@ pub async fn connect() -> Result<Database, Error>{
     //     start_strix(strix.clone()).await;
     // }
 
-    let mut db = Database{location:database_path().to_string(),settings:Default::default(),containers:Vec::new(),headers:Vec::new(),container:HashMap::new(),secret_keys:Arc::new(Mutex::new(HashMap::new()))};
+    let wal = Arc::new(Mutex::new(WriteAheadLog::open(path)?));
+    let mut db = Database{location:database_path().to_string(),settings:Default::default(),containers:Vec::new(),headers:Vec::new(),indexes:Vec::new(),container:HashMap::new(),secret_keys:Arc::new(Mutex::new(HashMap::new())),wal,session:LOCAL_SESSION,snapshot:0,transactions:AHashMap::new(),unapplied:false,activity:AHashMap::new(),savepoints:AHashMap::new(),clock:SnapshotClock::default()};
     db.setup().await?;
     if let Err(e) = db.load_settings(){
         logerr!("err: load_settings");
@@ -1011,6 +2023,7 @@ struct DataConnection{
 
 #[derive(Serialize)]
 struct QueryResponse{
+    columns : Vec<String>,
     rows : Vec<Vec<AlbaTypes>>
 }
 
@@ -1047,7 +2060,6 @@ async fn handle_data_tcp_inner(dbref: Arc<Mutex<Database>>,rc_payload:Vec<u8>) -
 
 
     let ssr = session_secret_rel.lock().await;
-    let mut db = dbref.lock().await;
     let mut payload: Vec<u8> = Vec::with_capacity(512);
     payload.extend_from_slice(&rc_payload[32..]);
     if let Some(_) = ssr.get(&session_id) {
@@ -1068,17 +2080,30 @@ async fn handle_data_tcp_inner(dbref: Arc<Mutex<Database>>,rc_payload:Vec<u8>) -
         payload.clear();
         return (0 as u64).to_be_bytes().as_slice().to_vec();
     }
+    drop(ssr);
     //
     let mut response: Vec<u8> = Vec::with_capacity(510);
     match serde_json::from_slice::<DataConnection>(&payload) {
         Ok(v) => {
             //
-            match db.execute(&v.command,v.arguments).await {
+            // a SEARCH only holds the lock of the database to take its snapshot, commits go on while it reads
+            let result = match parse(v.command.clone(), v.arguments){
+                Ok(AST::Search(structure)) => {
+                    let reader = dbref.lock().await.begin_read(session_id);
+                    let result = reader.search(structure).await;
+                    dbref.lock().await.end_read(reader).await;
+                    result
+                },
+                Ok(ast) => dbref.lock().await.execute(session_id, ast).await,
+                Err(e) => Err(e),
+            };
+            match result {
                 Ok(query_result) => {
                     //
                     //
                     let l = query_result.rows.1.len();
                     let mut qr = QueryResponse{
+                        columns : query_result.column_names,
                         rows : Vec::with_capacity(l)
                     };
                     for i in query_result.rows.1{
@@ -1259,6 +2284,14 @@ impl Database{
         //
         
         let mtx_db = Arc::new(Mutex::new(self));
+        let db = mtx_db.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                db.lock().await.expire_transactions().await;
+            }
+        });
         // loop {
             
         //     handle_connections_tcp_sync(&connections_tcp,mtx_db.clone()).await;
@@ -1287,4 +2320,76 @@ impl Database{
         }
 
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+pub(crate) mod tests{
+    use super::*;
+
+    lazy_static!{
+        // the database lives under HOME, the tests that open one take turns
+        static ref HOME_LOCK : Mutex<()> = Mutex::new(());
+    }
+
+    /// Opens an empty database in a temporary HOME, it's only used by one test while the guard is held.
+    pub(crate) async fn test_database(name : &str) -> (tokio::sync::MutexGuard<'static, ()>, Database){
+        let guard = HOME_LOCK.lock().await;
+        let home = std::env::temp_dir().join(format!("tytodb-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(&home).unwrap();
+        // SAFETY: the tests reading HOME hold the guard
+        unsafe { std::env::set_var("HOME", &home) };
+        (guard, connect().await.unwrap())
+    }
+
+    pub(crate) async fn run(db : &mut Database, session : SessionId, statement : &str) -> Result<Query, Error>{
+        db.execute(session, parse(statement.to_string(), vec![])?).await
+    }
+
+    #[tokio::test]
+    async fn a_statement_that_fails_to_commit_is_rolled_back(){
+        let (_guard, mut db) = test_database("autocommit").await;
+        run(&mut db, LOCAL_SESSION, "CREATE CONTAINER 't' ['id', 's'] ['INT', 'NANO-STRING']").await.unwrap();
+        run(&mut db, LOCAL_SESSION, "CREATE ROW ['id', 's'] [1, 'a'] ON 't'").await.unwrap();
+        run(&mut db, LOCAL_SESSION, "CREATE ROW ['id', 's'] [2, 'b'] ON 't'").await.unwrap();
+        // a second row with the key of the first one, as a file written before keys were unique holds
+        {
+            let container = db.container["t"].lock().await;
+            let file = container.file.lock().await;
+            let mut key = [0u8; 4];
+            file.read_exact_at(&mut key, container.headers_offset).unwrap();
+            file.write_all_at(&key, container.headers_offset + container.element_size as u64).unwrap();
+        }
+        assert!(run(&mut db, LOCAL_SESSION, "EDIT ROW ['s']['zz'] ON 't' WHERE 's' = 'b'").await.is_err());
+        let rows = run(&mut db, LOCAL_SESSION, "SEARCH ['s'] ON 't' WHERE 's' = 'zz'").await.unwrap();
+        assert!(rows.rows.1.is_empty());
+        assert!(db.container["t"].lock().await.staged(&LOCAL_SESSION).await.is_empty());
+        run(&mut db, LOCAL_SESSION, "ALTER CONTAINER 't' RENAME COLUMN 's' TO 'r'").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_commit_that_fails_to_apply_is_finished_on_restart(){
+        let (_guard, mut db) = test_database("unapplied").await;
+        run(&mut db, LOCAL_SESSION, "CREATE CONTAINER 'a' ['id'] ['INT']").await.unwrap();
+        run(&mut db, LOCAL_SESSION, "CREATE CONTAINER 'b' ['id', 'text'] ['INT', 'TEXT']").await.unwrap();
+        run(&mut db, LOCAL_SESSION, "BEGIN").await.unwrap();
+        run(&mut db, LOCAL_SESSION, "CREATE ROW ['id'] [1] ON 'a'").await.unwrap();
+        run(&mut db, LOCAL_SESSION, "CREATE ROW ['id', 'text'] [1, 'hello'] ON 'b'").await.unwrap();
+        // the reference file of the TEXT value can't be written anymore
+        let references = format!("{}/rf", database_path());
+        fs::remove_dir_all(&references).unwrap();
+        fs::write(&references, b"").unwrap();
+        assert!(run(&mut db, LOCAL_SESSION, "COMMIT").await.is_err());
+        run(&mut db, LOCAL_SESSION, "ROLLBACK").await.unwrap();
+        // nothing is written over the files of the logged commit until it is finished
+        assert!(run(&mut db, LOCAL_SESSION, "CREATE ROW ['id'] [2] ON 'a'").await.is_err());
+        assert!(run(&mut db, LOCAL_SESSION, "CREATE CONTAINER 'c' ['id'] ['INT']").await.is_err());
+        drop(db);
+
+        fs::remove_file(&references).unwrap();
+        fs::create_dir(&references).unwrap();
+        let mut db = connect().await.unwrap();
+        assert_eq!(run(&mut db, LOCAL_SESSION, "SEARCH ['*'] ON 'a'").await.unwrap().rows.1, vec![vec![AlbaTypes::Int(1)]]);
+        assert_eq!(run(&mut db, LOCAL_SESSION, "SEARCH ['*'] ON 'b'").await.unwrap().rows.1, vec![vec![AlbaTypes::Int(1), AlbaTypes::Text("hello".to_string())]]);
+        run(&mut db, LOCAL_SESSION, "CREATE ROW ['id'] [2] ON 'a'").await.unwrap();
+    }
+}
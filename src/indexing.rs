@@ -1,10 +1,9 @@
-use tokio::sync::{Mutex, RwLock};
-use crate::{alba_types::AlbaTypes, database::database_path, gerr, logerr, loginfo};
-use std::{collections::{BTreeMap, BTreeSet, HashMap}, fs::{self, File, OpenOptions}, hash::{DefaultHasher, Hash, Hasher}, io::{Error, Read, Write}, ops::{Range, RangeInclusive}, os::unix::fs::{FileExt, MetadataExt}, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+use crate::{alba_types::AlbaTypes, database::database_path, gerr, loginfo};
+use std::{collections::BTreeSet, fs::{self, File}, hash::{DefaultHasher, Hash, Hasher}, io::Error, ops::{Range, RangeInclusive}, os::unix::fs::{FileExt, MetadataExt}, path::Path, sync::Arc};
 
 
-//type IndexElement = (u64,u64); // index value , offset value
-type MetadataElement = (u64,u64,u16); // minimum index value, maximum index value , items in chunk
+type IndexElement = (u64,u64); // index value , offset value
 
 pub trait Add{
     /// Insert a index value into indexes
@@ -25,206 +24,482 @@ pub trait Search <T:SearchQuery>{
     async fn search(&self, arg:T) -> Result<BTreeSet<u64>,Error>;
 }
 
+// The .index file is a B+tree made of PAGE_SIZE pages. Page 0 is the header:
+//   magic (4) | root page (8) | first free page (8) | page count (8) | key format (1) | dirty (1)
+// every other page is a node or a free page:
+//   kind (1) | count (2) | next (8) | body
+// leaves hold `count` (index value, offset) pairs sorted and link to the next leaf so range scans
+// walk them in order, internal nodes hold a first child followed by `count` (separator, child) triples.
+// The pairs themselves are the keys of the tree, so the same index value may point to many rows.
+// Free pages are chained through `next`.
+// Pages are rewritten in place and not logged. Before the first change after a flush the header is written
+// dirty, `flush` writes it clean once the pages are synced, so an index left dirty by a crash is known to be
+// inconsistent and rebuilt from the rows of its container.
+
+const PAGE_SIZE : u64 = 4096;
+const MAGIC : &[u8;4] = b"TIDX";
+const NODE_HEADER : usize = 11;
+const LEAF_CAPACITY : usize = (PAGE_SIZE as usize - NODE_HEADER) / 16;
+const INTERNAL_CAPACITY : usize = (PAGE_SIZE as usize - NODE_HEADER - 8) / 24;
+
+const FREE_PAGE : u8 = 0;
+const LEAF_PAGE : u8 = 1;
+const INTERNAL_PAGE : u8 = 2;
+
+// version of the index values computed by `GetIndex`, an index written with another one is stale
+// and rebuilt from the rows of its container when the database loads.
+// 0: hashes and the first order-preserving keys, 1: order-preserving keys, 2: -0.0 shares the key of 0.0
+const KEY_FORMAT : u8 = 2;
+
 #[derive(Debug)]
 pub struct Indexing{
     file : Arc<Mutex<File>>,
-    metadata : Arc<Mutex<Vec<(RangeInclusive<u64>,u64)>>>,
-    available_page : Arc<Mutex<usize>>
+    header : Arc<Mutex<IndexHeader>>,
+    stale : bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IndexHeader{
+    root : u64,
+    free : u64,
+    pages : u64,
+    keys : u8,
+    dirty : bool,
 }
+
 #[derive(Debug)]
-struct IndexPage{
-    count : u16,
-    range : RangeInclusive<u64>,
-    elements : Vec<(u64,u64)>
-}
-fn index_page_from_b(b : [u8;PAGE_SIZE as usize]) -> IndexPage{
-    let mut min_be_bytes = [0u8;8];
-    let mut max_be_bytes = [0u8;8];
-    let mut count_be_bytes = [0u8;2];
-
-    min_be_bytes.clone_from_slice(&b[0..8]);
-    max_be_bytes.clone_from_slice(&b[8..16]);
-    count_be_bytes.clone_from_slice(&b[16..18]);
-    
-    let min = u64::from_be_bytes(min_be_bytes);
-    let max = u64::from_be_bytes(max_be_bytes);
-    let count = u16::from_be_bytes(count_be_bytes);
+enum Node{
+    Leaf{ elements : Vec<IndexElement>, next : u64 },
+    Internal{ separators : Vec<IndexElement>, children : Vec<u64> },
+}
 
-    let mut elements : Vec<(u64,u64)> = Vec::new();
-    let a = &b[18..18+(count*16) as usize];
+impl Node{
+    fn len(&self) -> usize{
+        match self{
+            Node::Leaf { elements, .. } => elements.len(),
+            Node::Internal { separators, .. } => separators.len(),
+        }
+    }
+    fn minimum(&self) -> usize{
+        match self{
+            Node::Leaf { .. } => LEAF_CAPACITY / 2,
+            Node::Internal { .. } => INTERNAL_CAPACITY / 2,
+        }
+    }
+}
 
-    for i in a.chunks_exact(16){
-        let mut key = [0u8;8];
-        let mut address = [0u8;8];
-        key.clone_from_slice(&i[..8]);
-        address.clone_from_slice(&i[8..]);
-        elements.push((u64::from_be_bytes(key),u64::from_be_bytes(address)))
+fn read_u64(b : &[u8], at : usize) -> u64{
+    let mut bytes = [0u8;8];
+    bytes.copy_from_slice(&b[at..at+8]);
+    u64::from_be_bytes(bytes)
+}
+
+fn node_from_b(b : &[u8]) -> Result<Node,Error>{
+    let count = u16::from_be_bytes([b[1],b[2]]) as usize;
+    let next = read_u64(b, 3);
+    match b[0]{
+        LEAF_PAGE if count <= LEAF_CAPACITY => {
+            let elements = (0..count).map(|i| {
+                let at = NODE_HEADER + i*16;
+                (read_u64(b, at), read_u64(b, at+8))
+            }).collect();
+            Ok(Node::Leaf { elements, next })
+        },
+        INTERNAL_PAGE if count <= INTERNAL_CAPACITY => {
+            let mut separators = Vec::with_capacity(count);
+            let mut children = Vec::with_capacity(count+1);
+            children.push(read_u64(b, NODE_HEADER));
+            for i in 0..count{
+                let at = NODE_HEADER + 8 + i*24;
+                separators.push((read_u64(b, at), read_u64(b, at+8)));
+                children.push(read_u64(b, at+16));
+            }
+            Ok(Node::Internal { separators, children })
+        },
+        _ => Err(gerr("The index file is corrupted, found an invalid page"))
+    }
+}
+
+fn node_to_b(node : &Node) -> Vec<u8>{
+    let mut b = vec![0u8; PAGE_SIZE as usize];
+    b[1..3].copy_from_slice(&(node.len() as u16).to_be_bytes());
+    match node{
+        Node::Leaf { elements, next } => {
+            b[0] = LEAF_PAGE;
+            b[3..11].copy_from_slice(&next.to_be_bytes());
+            for (i, (key, offset)) in elements.iter().enumerate(){
+                let at = NODE_HEADER + i*16;
+                b[at..at+8].copy_from_slice(&key.to_be_bytes());
+                b[at+8..at+16].copy_from_slice(&offset.to_be_bytes());
+            }
+        },
+        Node::Internal { separators, children } => {
+            b[0] = INTERNAL_PAGE;
+            b[NODE_HEADER..NODE_HEADER+8].copy_from_slice(&children[0].to_be_bytes());
+            for (i, ((key, offset), child)) in separators.iter().zip(children[1..].iter()).enumerate(){
+                let at = NODE_HEADER + 8 + i*24;
+                b[at..at+8].copy_from_slice(&key.to_be_bytes());
+                b[at+8..at+16].copy_from_slice(&offset.to_be_bytes());
+                b[at+16..at+24].copy_from_slice(&child.to_be_bytes());
+            }
+        },
     }
+    b
+}
 
-    IndexPage{count,range:min..=max,elements}
+fn read_node(file : &File, page : u64) -> Result<Node,Error>{
+    let mut buf = vec![0u8; PAGE_SIZE as usize];
+    file.read_exact_at(&mut buf, page*PAGE_SIZE)?;
+    node_from_b(&buf)
 }
-fn index_page_to_b(page: &IndexPage) -> [u8; PAGE_SIZE as usize] {
-    let mut b = [0u8; PAGE_SIZE as usize];
-    
-    let min_bytes = page.range.start().to_be_bytes();
-    b[0..8].copy_from_slice(&min_bytes);
-    
-    let max_bytes = page.range.end().to_be_bytes();
-    b[8..16].copy_from_slice(&max_bytes);
-    
-    let count_bytes = page.count.to_be_bytes();
-    b[16..18].copy_from_slice(&count_bytes);
-    loginfo!("{:?}", page.elements);
-
-    let mut offset = 18;
-    for (key, address) in &page.elements {
-        let key_bytes = key.to_be_bytes();
-        let address_bytes = address.to_be_bytes();
-        
-        b[offset..offset + 8].copy_from_slice(&key_bytes);
-        b[offset + 8..offset + 16].copy_from_slice(&address_bytes);
-        
-        offset += 16;
+
+fn write_node(file : &File, page : u64, node : &Node) -> Result<(),Error>{
+    file.write_all_at(&node_to_b(node), page*PAGE_SIZE)
+}
+
+fn write_header(file : &File, header : &IndexHeader) -> Result<(),Error>{
+    let mut b = vec![0u8; PAGE_SIZE as usize];
+    b[0..4].copy_from_slice(MAGIC);
+    b[4..12].copy_from_slice(&header.root.to_be_bytes());
+    b[12..20].copy_from_slice(&header.free.to_be_bytes());
+    b[20..28].copy_from_slice(&header.pages.to_be_bytes());
+    b[28] = header.keys;
+    b[29] = header.dirty as u8;
+    file.write_all_at(&b, 0)
+}
+
+fn allocate_page(file : &File, header : &mut IndexHeader) -> Result<u64,Error>{
+    if header.free != 0{
+        let page = header.free;
+        let mut buf = [0u8; NODE_HEADER];
+        file.read_exact_at(&mut buf, page*PAGE_SIZE)?;
+        header.free = read_u64(&buf, 3);
+        return Ok(page)
     }
-    
-    b
+    let page = header.pages;
+    header.pages += 1;
+    Ok(page)
 }
 
-const PAGE_SIZE : u64 = 102226;
-const ELEMENT_COUNT : u16 = 6388;
+fn release_page(file : &File, header : &mut IndexHeader, page : u64) -> Result<(),Error>{
+    let mut b = vec![0u8; PAGE_SIZE as usize];
+    b[0] = FREE_PAGE;
+    b[3..11].copy_from_slice(&header.free.to_be_bytes());
+    file.write_all_at(&b, page*PAGE_SIZE)?;
+    header.free = page;
+    Ok(())
+}
 
-fn new_empty_page() -> [u8; 102226]{
-    let count : u16 = 0;
-    let min : u64 = 0;
-    let max : u64 = 0;
-    let mut buffer: [u8; 102226] = [0u8;PAGE_SIZE as usize];
-    buffer[..8].clone_from_slice(&min.to_be_bytes());
-    buffer[8..16].clone_from_slice(&max.to_be_bytes());
-    buffer[16..18].clone_from_slice(&count.to_be_bytes());
-    buffer
+/// Position of the child of an internal node that may hold the element.
+fn child_for(separators : &[IndexElement], element : IndexElement) -> usize{
+    separators.partition_point(|s| *s <= element)
+}
+
+/// Inserts the element under `page`, if the node had to split returns the separator and the page of its new right half.
+fn insert_element(file : &File, header : &mut IndexHeader, page : u64, element : IndexElement) -> Result<Option<(IndexElement,u64)>,Error>{
+    match read_node(file, page)?{
+        Node::Leaf { mut elements, next } => {
+            match elements.binary_search(&element){
+                Ok(_) => return Ok(None),
+                Err(i) => elements.insert(i, element),
+            }
+            if elements.len() <= LEAF_CAPACITY{
+                write_node(file, page, &Node::Leaf { elements, next })?;
+                return Ok(None)
+            }
+            let right_elements = elements.split_off(elements.len()/2);
+            let separator = right_elements[0];
+            let right = allocate_page(file, header)?;
+            write_node(file, right, &Node::Leaf { elements: right_elements, next })?;
+            write_node(file, page, &Node::Leaf { elements, next: right })?;
+            Ok(Some((separator, right)))
+        },
+        Node::Internal { mut separators, mut children } => {
+            let i = child_for(&separators, element);
+            let Some((separator, new_child)) = insert_element(file, header, children[i], element)? else {
+                return Ok(None)
+            };
+            separators.insert(i, separator);
+            children.insert(i+1, new_child);
+            if separators.len() <= INTERNAL_CAPACITY{
+                write_node(file, page, &Node::Internal { separators, children })?;
+                return Ok(None)
+            }
+            let middle = separators.len()/2;
+            let right_separators = separators.split_off(middle+1);
+            let right_children = children.split_off(middle+1);
+            let separator = separators.pop().unwrap();
+            let right = allocate_page(file, header)?;
+            write_node(file, right, &Node::Internal { separators: right_separators, children: right_children })?;
+            write_node(file, page, &Node::Internal { separators, children })?;
+            Ok(Some((separator, right)))
+        },
+    }
+}
+
+/// Removes the element under `page`, returns the node as it was written or None if the element was not there.
+fn remove_element(file : &File, header : &mut IndexHeader, page : u64, element : IndexElement) -> Result<Option<Node>,Error>{
+    let node = match read_node(file, page)?{
+        Node::Leaf { mut elements, next } => {
+            match elements.binary_search(&element){
+                Ok(i) => { elements.remove(i); },
+                Err(_) => return Ok(None),
+            }
+            Node::Leaf { elements, next }
+        },
+        Node::Internal { mut separators, mut children } => {
+            let i = child_for(&separators, element);
+            let Some(child) = remove_element(file, header, children[i], element)? else {
+                return Ok(None)
+            };
+            if child.len() < child.minimum(){
+                rebalance(file, header, &mut separators, &mut children, i, child)?;
+            }
+            Node::Internal { separators, children }
+        },
+    };
+    write_node(file, page, &node)?;
+    Ok(Some(node))
+}
+
+/// Refills the underfull child `i` of an internal node by borrowing from a sibling, or merges them when the sibling has nothing to spare.
+fn rebalance(file : &File, header : &mut IndexHeader, separators : &mut Vec<IndexElement>, children : &mut Vec<u64>, i : usize, child : Node) -> Result<(),Error>{
+    // the left sibling is used when there is one, the child is then always the right node of the pair
+    let (l, r) = if i > 0 { (i-1, i) } else { (i, i+1) };
+    let (mut left, mut right) = if i > 0 {
+        (read_node(file, children[l])?, child)
+    } else {
+        (child, read_node(file, children[r])?)
+    };
+    let lender_len = if i > 0 { left.len() } else { right.len() };
+    if lender_len > left.minimum(){
+        match (&mut left, &mut right){
+            (Node::Leaf { elements: le, .. }, Node::Leaf { elements: re, .. }) => {
+                if i > 0 {
+                    re.insert(0, le.pop().unwrap());
+                } else {
+                    le.push(re.remove(0));
+                }
+                separators[l] = re[0];
+            },
+            (Node::Internal { separators: ls, children: lc }, Node::Internal { separators: rs, children: rc }) => {
+                if i > 0 {
+                    rs.insert(0, separators[l]);
+                    rc.insert(0, lc.pop().unwrap());
+                    separators[l] = ls.pop().unwrap();
+                } else {
+                    ls.push(separators[l]);
+                    lc.push(rc.remove(0));
+                    separators[l] = rs.remove(0);
+                }
+            },
+            _ => return Err(gerr("The index file is corrupted, siblings have different kinds"))
+        }
+        write_node(file, children[l], &left)?;
+        write_node(file, children[r], &right)?;
+        return Ok(())
+    }
+    match (&mut left, right){
+        (Node::Leaf { elements: le, next }, Node::Leaf { elements: re, next: right_next }) => {
+            le.extend(re);
+            *next = right_next;
+        },
+        (Node::Internal { separators: ls, children: lc }, Node::Internal { separators: rs, children: rc }) => {
+            ls.push(separators[l]);
+            ls.extend(rs);
+            lc.extend(rc);
+        },
+        _ => return Err(gerr("The index file is corrupted, siblings have different kinds"))
+    }
+    write_node(file, children[l], &left)?;
+    release_page(file, header, children[r])?;
+    separators.remove(l);
+    children.remove(r);
+    Ok(())
 }
+
 impl Indexing{
+    fn open(path : &str) -> Result<Self,Error>{
+        if let Some(folder) = Path::new(path).parent(){
+            fs::create_dir_all(folder)?;
+        }
+        let file = File::options().read(true).write(true).create(true).truncate(false).open(path)?;
+        let header = if file.metadata()?.size() == 0 {
+            let header = IndexHeader{root:1,free:0,pages:2,keys:KEY_FORMAT,dirty:false};
+            write_node(&file, 1, &Node::Leaf { elements: Vec::new(), next: 0 })?;
+            write_header(&file, &header)?;
+            file.sync_all()?;
+            header
+        }else{
+            let mut b = [0u8;30];
+            file.read_exact_at(&mut b, 0)?;
+            if &b[0..4] != MAGIC{
+                return Err(gerr(&format!("The file {} is not an index", path)))
+            }
+            IndexHeader{root:read_u64(&b, 4),free:read_u64(&b, 12),pages:read_u64(&b, 20),keys:b[28],dirty:b[29] != 0}
+        };
+        let stale = header.keys != KEY_FORMAT || header.dirty;
+        Ok(Indexing{file:Arc::new(Mutex::new(file)),header:Arc::new(Mutex::new(header)),stale})
+    }
+
     pub async fn create_index(container_name : &String) -> Result<(),Error>{
         let path = format!("{}/{}.index",database_path(),container_name);
         if fs::exists(&path)?{
+            let file = File::open(&path)?;
+            let mut magic = [0u8;4];
+            if file.metadata()?.size() < PAGE_SIZE || file.read_exact_at(&mut magic, 0).is_err() || magic != *MAGIC{
+                // the keys of the old format were hashes, the index is replaced by an empty stale one
+                // that is rebuilt from the rows once the container is loaded
+                loginfo!("Converting the index {} to the B+tree format", container_name);
+                let temporary = format!("{}.tmp", path);
+                let _ = fs::remove_file(&temporary);
+                let index = Indexing::open(&temporary)?;
+                {
+                    let file = index.file.lock().await;
+                    let mut header = index.header.lock().await;
+                    header.keys = 0;
+                    write_header(&file, &header)?;
+                    file.sync_all()?;
+                }
+                fs::rename(&temporary, &path)?;
+                File::open(database_path())?.sync_all()?;
+            }
             return Ok(())
-        }else{
-            let mut file = fs::File::create_new(path)?;
-            file.write_all(&mut new_empty_page())?;
-            file.sync_all()?;
         }
+        Indexing::open(&path)?;
         Ok(())
     }
     
     pub async fn load_index(container_name : &String) -> Result<Arc<Self>,Error>{
         Indexing::create_index(container_name).await?;
         let path = format!("{}/{}.index",database_path(),container_name);
-        let file = File::options().read(true).write(true).open(path)?;
-        let size = file.metadata()?.size();
-        let pages = size.saturating_div(PAGE_SIZE);
-        let mut metadata : Vec<(RangeInclusive<u64>,u64)> = Vec::new();
-        let mut available = 0;
-        for i in 0..pages{
-            let mut buf = [0u8;PAGE_SIZE as usize];
-            file.read_exact_at(&mut buf, i*PAGE_SIZE).unwrap();
-            let page = index_page_from_b(buf);
-            if page.count < 6388{
-                available = i as usize;
+        Ok(Arc::new(Indexing::open(&path)?))
+    }
+
+    /// True when the file was written with another version of `GetIndex` or left dirty by a crash,
+    /// the index has to be rebuilt.
+    pub fn is_stale(&self) -> bool{
+        self.stale
+    }
+
+    /// Writes a new index holding `elements` aside and renames it over the index `id`.
+    pub async fn build(id : &str, mut elements : Vec<IndexElement>) -> Result<Arc<Self>,Error>{
+        let path = format!("{}/{}.index",database_path(),id);
+        let temporary = format!("{}.tmp", path);
+        let _ = fs::remove_file(&temporary);
+        elements.sort_unstable();
+        let index = Indexing::open(&temporary)?;
+        {
+            let file = index.file.lock().await;
+            let mut header = index.header.lock().await;
+            for element in elements{
+                index.insert_locked(&file, &mut header, element)?;
             }
-            metadata.push((page.range,i*PAGE_SIZE));
-            loginfo!("load_index-i: {}",i);
+            write_header(&file, &header)?;
+            file.sync_all()?;
         }
-        Ok(Arc::new(Indexing{file:Arc::new(Mutex::new(file)),metadata:Arc::new(Mutex::new(metadata)), available_page: Arc::new(Mutex::new(available))}))
+        fs::rename(&temporary, &path)?;
+        if let Some(folder) = Path::new(&path).parent(){
+            File::open(folder)?.sync_all()?;
+        }
+        Ok(Arc::new(Indexing::open(&path)?))
     }
-    pub async fn insert_index(&self,arg : u64, arg_offset : u64) -> Result<(),Error>{
-        let available = {
-            let r = self.available_page.lock().await;
-            r.clone()
-        };
-        let mut metadata = self.metadata.lock().await;
-        if let Some(val) = metadata.get(available){
-            let offset = val.1;
-            let file = self.file.lock().await;
-            loginfo!("read_offset: {}",offset);
-            let mut buf = [0u8;PAGE_SIZE as usize];
-            file.read_exact_at(&mut buf, offset).unwrap();
-            let mut page: IndexPage = index_page_from_b(buf);
-            
-            page.elements.retain(|f|f.1!=arg_offset);
-            loginfo!("arg: {} offset: {}",arg,arg_offset);
-            
-            page.elements.push((arg,arg_offset));
-            page.elements.sort_by_key(|f|f.0);
-            page.count = page.elements.len() as u16;
-
-            let (f,l) = (page.elements.first(),page.elements.last());
-            if page.count > 0 && f.is_some() && l.is_some(){
-                let (f,l) = (f.unwrap(),l.unwrap());
-                if f.0 <= f.1{
-                    page.range = f.0 ..=l.0
-                }
-            }
-            let size = file.metadata()?.size();
-            metadata[available] = (page.range.clone(),offset);
-            let bytes: [u8; PAGE_SIZE as usize] = index_page_to_b(&page);
-            file.write_all_at(&bytes, offset)?;
-            if page.count == ELEMENT_COUNT{
-                let i = metadata.len();
-                let nep: [u8; 102226] = new_empty_page();
-                metadata.push((page.range,size));
-                file.set_len(size+102226)?;
-                file.write_all_at(&nep, size)?;
-                *self.available_page.lock().await = i;
-            }
-            loginfo!("{:?}",metadata);
-            drop(metadata);
+
+    fn insert_locked(&self, file : &File, header : &mut IndexHeader, element : IndexElement) -> Result<(),Error>{
+        if let Some((separator, right)) = insert_element(file, header, header.root, element)?{
+            let root = allocate_page(file, header)?;
+            write_node(file, root, &Node::Internal { separators: vec![separator], children: vec![header.root, right] })?;
+            header.root = root;
+        }
+        Ok(())
+    }
+
+    /// Marks the index `id` dirty so it is rebuilt from the rows the next time it is loaded, when the offsets
+    /// of the rows of its container changed.
+    pub fn invalidate(id : &str) -> Result<(),Error>{
+        let path = format!("{}/{}.index",database_path(),id);
+        if !fs::exists(&path)?{
+            return Ok(())
+        }
+        let file = File::options().read(true).write(true).open(&path)?;
+        let mut magic = [0u8;4];
+        // files of the old format are rebuilt anyway
+        if file.metadata()?.size() >= PAGE_SIZE && file.read_exact_at(&mut magic, 0).is_ok() && magic == *MAGIC{
+            file.write_all_at(&[1], 29)?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Marks the index dirty on disk before its pages change, once until the next flush.
+    fn begin_change(file : &File, header : &mut IndexHeader) -> Result<(),Error>{
+        if !header.dirty{
+            header.dirty = true;
+            write_header(file, header)?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the changes made since the last flush and marks the index clean.
+    pub async fn flush(&self) -> Result<(),Error>{
+        let mut header = self.header.lock().await;
+        let file = self.file.lock().await;
+        if header.dirty{
+            file.sync_all()?;
+            header.dirty = false;
+            write_header(&file, &header)?;
             file.sync_all()?;
         }
         Ok(())
     }
+
+    pub async fn insert_index(&self,arg : u64, arg_offset : u64) -> Result<(),Error>{
+        let mut header = self.header.lock().await;
+        let file = self.file.lock().await;
+        Indexing::begin_change(&file, &mut header)?;
+        self.insert_locked(&file, &mut header, (arg, arg_offset))
+    }
     
     pub async fn remove_index(&self, arg: u64, arg_offset: u64) -> Result<(), Error> {
-        let metadata = self.metadata.lock().await;
+        let mut header = self.header.lock().await;
         let file = self.file.lock().await;
-        
-        for (page_idx, (range, offset)) in metadata.iter().enumerate() {
-            if range.contains(&arg) || range.start() == &0 && range.end() == &0 {
-                let mut buf = [0u8; PAGE_SIZE as usize];
-                file.read_exact_at(&mut buf, *offset)?;
-                let mut page: IndexPage = index_page_from_b(buf);
-                
-                let original_len = page.elements.len();
-                page.elements.retain(|(key, offset_val)| !(*key == arg || *offset_val == arg_offset));
-                
-                if page.elements.len() < original_len {
-                    page.count = page.elements.len() as u16;
-                    
-                    // Update range if necessary
-                    if page.elements.is_empty() {
-                        page.range = 0..=0;
-                    } else {
-                        let min = page.elements.iter().map(|(k, _)| *k).min().unwrap();
-                        let max = page.elements.iter().map(|(k, _)| *k).max().unwrap();
-                        page.range = min..=max;
-                    }
-                    
-                    let bytes = index_page_to_b(&page);
-                    file.write_all_at(&bytes, *offset)?;
-                    
-                    if page.count < ELEMENT_COUNT {
-                        let mut available = self.available_page.lock().await;
-                        if page_idx < *available || (*available >= metadata.len() && !metadata.is_empty()) {
-                            *available = page_idx;
+        Indexing::begin_change(&file, &mut header)?;
+        let root = header.root;
+        if let Some(Node::Internal { separators, children }) = remove_element(&file, &mut header, root, (arg, arg_offset))?
+            && separators.is_empty(){
+            // the root lost its last separator, its only child becomes the root
+            header.root = children[0];
+            release_page(&file, &mut header, root)?;
+        }
+        Ok(())
+    }
+
+    /// Offsets of every element whose index value is between `start` and `end`, both included.
+    async fn scan(&self, start : u64, end : u64) -> Result<BTreeSet<u64>,Error>{
+        let mut results = BTreeSet::new();
+        let header = self.header.lock().await;
+        let file = self.file.lock().await;
+        let mut page = header.root;
+        loop{
+            match read_node(&file, page)?{
+                Node::Internal { separators, children } => page = children[child_for(&separators, (start, 0))],
+                Node::Leaf { elements, next } => {
+                    for (key, offset) in elements.iter(){
+                        if *key > end{
+                            return Ok(results)
+                        }
+                        if *key >= start{
+                            results.insert(*offset);
                         }
                     }
-                    
-                    file.sync_all()?;
-                    return Ok(());
-                }
+                    if next == 0{
+                        return Ok(results)
+                    }
+                    page = next;
+                },
             }
         }
-        
-        Ok(())
     }
     
 }
@@ -243,84 +518,25 @@ impl Remove for Indexing{
 
 impl Search<Range<u64>> for Indexing {
     async fn search(&self, arg: Range<u64>) -> Result<BTreeSet<u64>, Error> {
-        let mut results = BTreeSet::new();
-        let metadata = self.metadata.lock().await;
-        let file = self.file.lock().await;
-        loginfo!("\nmetadata: {:?}\n",metadata);
-        for (_page_idx, (range, offset)) in metadata.iter().enumerate() {
-            // Check if page range overlaps with search range
-            if range.start() < &arg.end && range.end() >= &arg.start || (range.start() == &0 && range.end() == &0) {
-                let mut buf = [0u8; PAGE_SIZE as usize];
-                file.read_exact_at(&mut buf, *offset)?;
-                let page: IndexPage = index_page_from_b(buf);
-                loginfo!("\npage({:?}): {:?}\n",range,page);
-                
-                // Search within the page elements
-                for (key, value) in &page.elements {
-                    if arg.contains(key) {
-                        results.insert(*value);
-                    }
-                }
-            }
+        if arg.start >= arg.end{
+            return Ok(BTreeSet::new())
         }
-        loginfo!("results: {:?}",results);
-        Ok(results)
+        self.scan(arg.start, arg.end - 1).await
     }
 }
 
 impl Search<RangeInclusive<u64>> for Indexing {
     async fn search(&self, arg: RangeInclusive<u64>) -> Result<BTreeSet<u64>, Error> {
-        let mut results = BTreeSet::new();
-        let metadata = self.metadata.lock().await;
-        let file = self.file.lock().await;
-        loginfo!("\nmetadata: {:?}\n",metadata);
-        for (_page_idx, (range, offset)) in metadata.iter().enumerate() {
-            // Check if page range overlaps with search range
-            if range.start() <= arg.end() && range.end() >= arg.start() || range.start() == &0 && range.end() == &0 {
-                let mut buf = [0u8; PAGE_SIZE as usize];
-                file.read_exact_at(&mut buf, *offset)?;
-                let page: IndexPage = index_page_from_b(buf);
-                loginfo!("\npage({:?}): {:?}\n",range,page);
-                // Search within the page elements
-                for (key, value) in &page.elements {
-                    if arg.contains(key) {
-                        results.insert(*value);
-                    }
-                }
-            }
+        if arg.start() > arg.end(){
+            return Ok(BTreeSet::new())
         }
-        
-        loginfo!("results: {:?}",results);
-        Ok(results)
+        self.scan(*arg.start(), *arg.end()).await
     }
 }
 
 impl Search<u64> for Indexing {
     async fn search(&self, arg: u64) -> Result<BTreeSet<u64>, Error> {
-        let mut results = BTreeSet::new();
-        let metadata = self.metadata.lock().await;
-        let file = self.file.lock().await;
-        
-        for (_page_idx, (range, offset)) in metadata.iter().enumerate() {
-            // Check if page range contains the search key
-            loginfo!("{:?} :: {}",range,arg);
-            if range.contains(&arg) || (range.start() == &0 && range.end() == &0) {
-                let mut buf = [0u8; PAGE_SIZE as usize];
-                file.read_exact_at(&mut buf, *offset)?;
-                let page: IndexPage = index_page_from_b(buf);
-                loginfo!("page: {:?}",page);
-                
-                // Search within the page elements
-                for (key, value) in &page.elements {
-                    if *key == arg {
-                        results.insert(*value);
-                    }
-                }
-            }
-        }
-        
-        loginfo!("results: {:?}",results);
-        Ok(results)
+        self.scan(arg, arg).await
     }
 }
 
@@ -408,21 +624,26 @@ pub trait GetIndex{
     fn get_index(&self) -> u64;
 }
 
+// Index keys preserve the order of the values they come from, so a range of values is also a range of keys.
+// Different values may share a key (strings only keep their first 8 bytes), the rows found through the
+// index are always checked against the conditions afterwards.
+
 impl GetIndex for i32{
     fn get_index(&self) -> u64{
-        self.index_hash()
+        (*self as i64).get_index()
     }
 }
 
 impl GetIndex for i64{
     fn get_index(&self) -> u64{
-        self.index_hash()
+        // flipping the sign bit moves the negative values below the positive ones
+        (*self as u64) ^ (1 << 63)
     }
 }
 
 impl GetIndex for i16{
     fn get_index(&self) -> u64{
-        self.index_hash()
+        (*self as i64).get_index()
     }
 }
 
@@ -452,13 +673,20 @@ impl GetIndex for u16{
 
 impl GetIndex for u8{
     fn get_index(&self) -> u64{
-        self.index_hash()
+        *self as u64
     }
 }
 
 impl GetIndex for f64{
     fn get_index(&self) -> u64{
-        *self as u64
+        // IEEE 754 total order: negative values get every bit flipped, positive ones only the sign bit.
+        // -0.0 is equal to 0.0 so both must share a key
+        let bits = if *self == 0.0 { 0 } else { self.to_bits() };
+        if bits >> 63 == 1 {
+            !bits
+        } else {
+            bits ^ (1 << 63)
+        }
     }
 }
 
@@ -471,11 +699,18 @@ impl GetIndex for bool{
     }
 }
 
+impl GetIndex for [u8]{
+    fn get_index(&self) -> u64{
+        let mut prefix = [0u8;8];
+        let l = self.len().min(8);
+        prefix[..l].copy_from_slice(&self[..l]);
+        u64::from_be_bytes(prefix)
+    }
+}
+
 impl GetIndex for String{
     fn get_index(&self) -> u64{
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish()
+        self.as_bytes().get_index()
     }
 }
 
@@ -483,6 +718,7 @@ impl GetIndex for AlbaTypes {
     fn get_index(&self) -> u64 {
         match self {
             AlbaTypes::Text(s) => s.get_index(),
+            AlbaTypes::Json(s) => s.get_index(),
             AlbaTypes::Int(i) => i.get_index(),
             AlbaTypes::Bigint(i) => i.get_index(),
             AlbaTypes::Float(f) => f.get_index(),
@@ -493,32 +729,151 @@ impl GetIndex for AlbaTypes {
             AlbaTypes::MediumString(s) => s.get_index(),
             AlbaTypes::BigString(s) => s.get_index(),
             AlbaTypes::LargeString(s) => s.get_index(),
-            AlbaTypes::NanoBytes(bytes) => {
-                let mut hasher = DefaultHasher::new();
-                bytes.hash(&mut hasher);
-                hasher.finish()
-            },
-            AlbaTypes::SmallBytes(bytes) => {
-                let mut hasher = DefaultHasher::new();
-                bytes.hash(&mut hasher);
-                hasher.finish()
-            },
-            AlbaTypes::MediumBytes(bytes) => {
-                let mut hasher = DefaultHasher::new();
-                bytes.hash(&mut hasher);
-                hasher.finish()
-            },
-            AlbaTypes::BigSBytes(bytes) => {
-                let mut hasher = DefaultHasher::new();
-                bytes.hash(&mut hasher);
-                hasher.finish()
-            },
-            AlbaTypes::LargeBytes(bytes) => {
-                let mut hasher = DefaultHasher::new();
-                bytes.hash(&mut hasher);
-                hasher.finish()
-            },
+            AlbaTypes::NanoBytes(bytes) => bytes.get_index(),
+            AlbaTypes::SmallBytes(bytes) => bytes.get_index(),
+            AlbaTypes::MediumBytes(bytes) => bytes.get_index(),
+            AlbaTypes::BigSBytes(bytes) => bytes.get_index(),
+            AlbaTypes::LargeBytes(bytes) => bytes.get_index(),
+            AlbaTypes::Timestamp(t) => t.get_index(),
+            AlbaTypes::Date(d) => d.get_index(),
+            // the high half keeps the order of the UUIDs
+            AlbaTypes::Uuid(u) => ((*u >> 64) as u64).get_index(),
+            // values past the range of i64 share the end keys
+            AlbaTypes::Decimal(v, _, _) => ((*v).clamp(i64::MIN as i128, i64::MAX as i128) as i64).get_index(),
+            // arrays are ordered by their first element
+            AlbaTypes::Array(items, _) => items.first().map_or(0, |item| item.get_index()),
             AlbaTypes::NONE => 0,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn temporary_index(name : &str) -> (String, Indexing){
+        let path = std::env::temp_dir().join(format!("tytodb-index-{}-{}.index", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+        let index = Indexing::open(&path).unwrap();
+        (path, index)
+    }
+
+    /// Deterministic permutation of 0..n
+    fn shuffled(n : u64) -> Vec<u64>{
+        let mut items : Vec<u64> = (0..n).collect();
+        let mut state = 0x9e3779b97f4a7c15u64;
+        for i in (1..items.len()).rev(){
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            items.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        items
+    }
+
+    async fn depth(index : &Indexing) -> usize{
+        let header = index.header.lock().await;
+        let file = index.file.lock().await;
+        let (mut page, mut depth) = (header.root, 1);
+        while let Node::Internal { children, .. } = read_node(&file, page).unwrap(){
+            page = children[0];
+            depth += 1;
+        }
+        depth
+    }
+
+    /// Every element of the leaves, walking the sibling links from the leftmost leaf.
+    async fn leaf_chain(index : &Indexing) -> Vec<IndexElement>{
+        let header = index.header.lock().await;
+        let file = index.file.lock().await;
+        let mut page = header.root;
+        while let Node::Internal { children, .. } = read_node(&file, page).unwrap(){
+            page = children[0];
+        }
+        let mut elements = Vec::new();
+        loop{
+            let Node::Leaf { elements: leaf, next } = read_node(&file, page).unwrap() else {
+                panic!("a sibling link points to an internal node")
+            };
+            elements.extend(leaf);
+            if next == 0{
+                return elements
+            }
+            page = next;
+        }
+    }
+
+    #[tokio::test]
+    async fn insert_and_remove_across_splits_and_merges(){
+        let (path, index) = temporary_index("splits");
+        let mut model = BTreeSet::new();
+        for i in shuffled(60_000){
+            index.insert_index(i * 3, i + 1).await.unwrap();
+            model.insert((i * 3, i + 1));
+        }
+        // inserting an element twice keeps one copy
+        index.insert_index(30, 11).await.unwrap();
+        assert!(depth(&index).await >= 3);
+        assert_eq!(leaf_chain(&index).await, model.iter().copied().collect::<Vec<_>>());
+
+        for i in shuffled(60_000).into_iter().filter(|i| i % 10 != 0){
+            index.remove_index(i * 3, i + 1).await.unwrap();
+            model.remove(&(i * 3, i + 1));
+        }
+        // removing a missing element changes nothing
+        index.remove_index(1, 1).await.unwrap();
+        assert_eq!(leaf_chain(&index).await, model.iter().copied().collect::<Vec<_>>());
+        assert!(index.header.lock().await.free != 0);
+
+        let pages = index.header.lock().await.pages;
+        for (key, offset) in model.iter(){
+            index.remove_index(*key, *offset).await.unwrap();
+        }
+        assert!(leaf_chain(&index).await.is_empty());
+        assert_eq!(depth(&index).await, 1);
+        // the pages released by the merges are used again
+        for i in 0..20_000{
+            index.insert_index(i, i + 1).await.unwrap();
+        }
+        assert_eq!(index.header.lock().await.pages, pages);
+        let _ = fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn range_scans_follow_sibling_links(){
+        let (path, index) = temporary_index("scans");
+        // four rows share each index value, so equal values span leaf boundaries
+        for i in shuffled(20_000){
+            index.insert_index(i / 4, i + 1).await.unwrap();
+        }
+        let offsets = |keys : std::ops::RangeInclusive<u64>| -> BTreeSet<u64> {
+            keys.flat_map(|key| (key * 4 + 1)..=(key * 4 + 4)).collect()
+        };
+        assert_eq!(index.search(0u64..5_000).await.unwrap(), offsets(0..=4_999));
+        assert_eq!(index.search(1_000u64..=3_999).await.unwrap(), offsets(1_000..=3_999));
+        assert_eq!(index.search(4_990u64..=u64::MAX).await.unwrap(), offsets(4_990..=4_999));
+        assert_eq!(index.search(2_500u64).await.unwrap(), offsets(2_500..=2_500));
+        assert!(index.search(5_000u64..=u64::MAX).await.unwrap().is_empty());
+        assert!(index.search(7u64..7).await.unwrap().is_empty());
+        for i in (0..20_000).filter(|i| (i / 4) % 2 == 1){
+            index.remove_index(i / 4, i + 1).await.unwrap();
+        }
+        let even : BTreeSet<u64> = (1_000..=3_999).filter(|key| key % 2 == 0).flat_map(|key| (key * 4 + 1)..=(key * 4 + 4)).collect();
+        assert_eq!(index.search(1_000u64..=3_999).await.unwrap(), even);
+        let _ = fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn an_index_changed_without_a_flush_is_stale(){
+        let (path, index) = temporary_index("dirty");
+        for i in 0..1_000{
+            index.insert_index(i, i + 1).await.unwrap();
+        }
+        assert!(Indexing::open(&path).unwrap().is_stale());
+        index.flush().await.unwrap();
+        let reopened = Indexing::open(&path).unwrap();
+        assert!(!reopened.is_stale());
+        assert_eq!(leaf_chain(&reopened).await, (0..1_000).map(|i| (i, i + 1)).collect::<Vec<_>>());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -2,7 +2,7 @@ use lazy_static::lazy_static;
 use std::io::Error;
 use base64::{alphabet, engine::{self, general_purpose}, Engine as _};
 
-use crate::lexer;
+use crate::{alba_types::{generate_uuid, parse_date, parse_decimal, parse_timestamp}, lexer};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token{
@@ -12,22 +12,46 @@ pub enum Token{
     Int(i64),
     Float(f64),
     Bool(bool),
+    Timestamp(i64),
+    Date(i32),
+    Uuid(u128),
+    Decimal(i128, u8),
     Operator(String),
     Group(Vec<Token>),
     SubCommand(Vec<Token>),
+    Function(String,Vec<Token>),
     Argument,
 }
 
 const KEYWORDS: &[&str] = &[
     "CREATE",
+    "BEGIN",
     "COMMIT",
     "ROLLBACK",
+    "SAVEPOINT",
+    "RELEASE",
     "DELETE",
     "EDIT",
+    "ALTER",
+    "ADD",
+    "DROP",
+    "RENAME",
+    "COLUMN",
+    "TO",
     "SEARCH",
     "WHERE",
+    "JOIN",
+    "LEFT",
+    "GROUP",
+    "ORDER",
+    "BY",
+    "ASC",
+    "DESC",
+    "LIMIT",
+    "OFFSET",
     "ROW",
     "CONTAINER",
+    "INDEX",
     "ON",
     "USING",
     "INT",
@@ -35,8 +59,20 @@ const KEYWORDS: &[&str] = &[
     "TEXT",
     "BOOL",
     "FLOAT",
+    "TIMESTAMP",
+    "DATE",
+    "UUID",
+    "DECIMAL",
+    "JSON",
     "AND",
     "OR",
+    "NOT",
+    "IS",
+    "NULL",
+    "IN",
+    "BETWEEN",
+    "CONTAINS",
+    "ANY",
     "NANO-STRING",
     "SMALL-STRING",
     "MEDIUM-STRING",
@@ -49,9 +85,27 @@ const KEYWORDS: &[&str] = &[
     "LARGE-BYTES",
 ];
 
-pub fn lexer_keyword_match(result: &mut Vec<Token>, dough: &mut String) -> bool {
+const FUNCTIONS: &[&str] = &[
+    "COUNT",
+    "SUM",
+    "AVG",
+    "MIN",
+    "MAX",
+    "NOW",
+    "GEN_UUID",
+];
+
+fn is_word_character(c : &char) -> bool {
+    c.is_alphanumeric() || *c == '-' || *c == '_'
+}
+
+pub fn lexer_keyword_match(result: &mut Vec<Token>, dough: &mut String, next: Option<&char>) -> bool {
     let keyword = dough.to_uppercase(); 
 
+    // a keyword must end on a word boundary, otherwise a word like "ORDER" would be read as "OR" + "DER"
+    if next.is_some_and(is_word_character) {
+        return false
+    }
     if KEYWORDS.contains(&keyword.as_str()) {
         result.push(Token::Keyword(keyword.to_uppercase())); 
         dough.clear(); 
@@ -105,8 +159,9 @@ fn split_group_args(input: &str) -> Vec<String> {
                 else if c == string_sort { in_string = false; }
                 current.push(c);
             }
-            '(' if !in_string => { parens += 1; current.push(c); }
-            ')' if !in_string => { if parens > 0 { parens -= 1; } current.push(c); }
+            // the commas of a nested group, like the array in [1, [2, 3]], stay inside it
+            '(' | '[' if !in_string => { parens += 1; current.push(c); }
+            ')' | ']' if !in_string => { if parens > 0 { parens -= 1; } current.push(c); }
             ',' if !in_string && parens == 0 => {
                 let t = current.trim();
                 if !t.is_empty() { result.push(t.to_string()); }
@@ -165,6 +220,10 @@ pub fn lexer_group_match<T: Iterator<Item = char>>(
                     continue;
                 }
                 match lexer(part.to_string()) {
+                    // an element made of several tokens, like 'counter' + 1, is kept together
+                    Ok(toks) if toks.len() > 1 => {
+                        abstract_tokens.push(Token::SubCommand(toks));
+                    }
                     Ok(mut toks) if !toks.is_empty() => {
                         abstract_tokens.push(toks.remove(0));
                     }
@@ -190,6 +249,7 @@ pub fn lexer_subcommand_match<T: Iterator<Item = char>>(
     if dough.starts_with('(') {
         let mut in_string : bool = false;
         let mut string_sort : char = '\\';
+        let mut depth : usize = 0;
         while let Some(c) = itr.next() {
             dough.push(c);
             if (c == '\'' || c == '"') && !in_string{
@@ -201,8 +261,14 @@ pub fn lexer_subcommand_match<T: Iterator<Item = char>>(
                 in_string = false;
                 continue;
             }
+            if c == '(' && !in_string {
+                depth += 1;
+            }
             if c == ')' && !in_string {
-                break;
+                if depth == 0{
+                    break;
+                }
+                depth -= 1;
             }
         }
         ////println!("{}",dough);
@@ -218,50 +284,131 @@ pub fn lexer_subcommand_match<T: Iterator<Item = char>>(
     Ok(false)
 }
 
+pub fn lexer_function_match<T: Iterator<Item = char>>(
+    result: &mut Vec<Token>,
+    dough: &mut String,
+    itr: &mut T,
+) -> Result<bool, Error> {
+    if !dough.ends_with('(') {
+        return Ok(false)
+    }
+    let name = dough[..dough.len() - 1].trim().to_uppercase();
+    if !FUNCTIONS.contains(&name.as_str()) {
+        return Ok(false)
+    }
+    let mut inner = String::new();
+    let mut in_string : bool = false;
+    let mut string_sort : char = '\\';
+    let mut depth = 1;
+    while let Some(c) = itr.next() {
+        if in_string {
+            if c == string_sort {
+                in_string = false;
+            }
+        } else if c == '\'' || c == '"' {
+            string_sort = c;
+            in_string = true;
+        } else if c == '(' {
+            depth += 1;
+        } else if c == ')' {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        inner.push(c);
+    }
+    if depth != 0 {
+        return Err(Error::new(std::io::ErrorKind::InvalidInput, format!("Missing ')' after the arguments of {}", name)))
+    }
+    // the arguments are lexed as their own input, a call without arguments has no tokens
+    let arguments = if inner.trim().is_empty() { Vec::new() } else { lexer(inner)? };
+    dough.clear();
+    // NOW() is the same for the whole statement, so it's read once here, every GEN_UUID() gets its own UUID
+    if name == "NOW" || name == "GEN_UUID" {
+        if !arguments.is_empty() {
+            return Err(Error::new(std::io::ErrorKind::InvalidInput, format!("{}() takes no arguments", name)))
+        }
+        result.push(if name == "NOW" { Token::Timestamp(chrono::Utc::now().timestamp_micros()) } else { Token::Uuid(generate_uuid()) });
+        return Ok(true)
+    }
+    result.push(Token::Function(name, arguments));
+    Ok(true)
+}
+
+/// Unquoted ISO-8601 literals, 2024-05-01 is a date and 2024-05-01T10:20:30Z a timestamp.
+pub fn lexer_temporal_match<T: Iterator<Item = char> + Clone>(
+    result: &mut Vec<Token>,
+    dough: &mut String,
+    itr: &mut std::iter::Peekable<T>
+) -> bool {
+    if dough.len() != 1 || !dough.starts_with(|c: char| c.is_ascii_digit()) {
+        return false
+    }
+    let literal : String = dough.chars().chain(itr.clone().take_while(|c| c.is_ascii_digit() || matches!(c, '-' | ':' | '.' | '+' | 'T' | 'Z'))).collect();
+    if literal.len() < 10 || literal.as_bytes()[4] != b'-' {
+        return false
+    }
+    let token = match parse_date(&literal) {
+        Some(date) => Token::Date(date),
+        None => match parse_timestamp(&literal) {
+            Some(timestamp) => Token::Timestamp(timestamp),
+            None => return false,
+        },
+    };
+    for _ in 1..literal.chars().count() {
+        itr.next();
+    }
+    result.push(token);
+    dough.clear();
+    true
+}
+
 const RADIX : u32= 10;
 pub fn lexer_number_match<T:Iterator<Item = char>>(result : &mut Vec<Token>,dough : &mut String, itr : &mut std::iter::Peekable<T>) -> bool{
     if let Some(d) = dough.chars().nth(0){
         let mut had_dot = false;
-        let mut negative = false;
+        // a leading '-' stays in the dough, parse already gives the negative value
         if d.is_digit(RADIX) || d == '-'{
-            if d == '-'{
-                negative = true;
-            }
             let mut cn : u8 = 0;
-            while let Some(n) = itr.next(){
+            // peeked so the character that ends the number, like a ',' or ')', is still lexed
+            while let Some(&n) = itr.peek(){
                 if n.is_digit(RADIX){
                     dough.push(n.clone());
                 }else{
                     if n == '.' && !had_dot{
                         dough.push(n.clone());
                         had_dot = true;
-                        continue;
-                    }
-                    if n == 'e' && cn == 0{
+                    }else if n == 'e' && cn == 0{
                         cn = 1;
                         dough.push(n.clone());
-                        continue
-                    }
-                    if (n == '-' || n == '+') && cn == 1{
+                    }else if (n == '-' || n == '+') && cn == 1{
                         cn = 2;
                         dough.push(n.clone());
-                        continue;
+                    }else{
+                        break;
                     }
-                    break;
                 }
+                itr.next();
             }
             if cn == 2 && (dough.ends_with("e+")||dough.ends_with("e-")){
                 return false
             }
-            if had_dot{
+            // a number with a point is kept exact, it only becomes a float when it meets one
+            if had_dot && cn == 0 && let Some((value, scale)) = parse_decimal(dough){
+                result.push(Token::Decimal(value, scale));
+                dough.clear();
+                return true
+            }
+            if had_dot || cn != 0{
                 if let Ok(float) = dough.parse::<f64>(){
-                    result.push(Token::Float(if negative {float*-1.0}else{float}));
+                    result.push(Token::Float(float));
                     dough.clear();
                     return true
                 }
             }else{
                 if let Ok(int) = dough.parse::<i64>(){
-                    result.push(Token::Int(if negative {int*-1}else{int}));
+                    result.push(Token::Int(int));
                     dough.clear();
                     return true
                 }
@@ -271,7 +418,7 @@ pub fn lexer_number_match<T:Iterator<Item = char>>(result : &mut Vec<Token>,doug
     false
 }  
 pub fn lexer_ignore_comments_match<T:Iterator<Item = char>>(dough : &mut String, itr : &mut std::iter::Peekable<T>) -> bool{
-    if dough.starts_with('/'){
+    if dough.starts_with('/') && itr.peek() == Some(&'*'){
         while let Some(c) = itr.next(){
             dough.push(c);
             let n = dough.len();
@@ -366,9 +513,23 @@ pub fn lexer_operator_match<T: Iterator<Item = char>>(
         },
         '+' => "+",
         '*' => "*",
-        '-' => "-",
+        '-' => {
+            // -> and ->> step into a JSON document
+            if let Some(&'>') = itr.peek() {
+                itr.next();
+                if let Some(&'>') = itr.peek() {
+                    itr.next();
+                    "->>"
+                } else {
+                    "->"
+                }
+            } else {
+                "-"
+            }
+        },
         '/' => "/",
         '%' => "%",
+        ',' => ",",
         '!' => {
             if let Some(&next_char) = itr.peek() {
                 if next_char == '=' {
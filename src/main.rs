@@ -7,12 +7,17 @@ mod query;
 mod indexing;
 mod alba_types;
 mod query_conditions;
+mod arithmetic;
+mod wal;
+mod snapshot;
 use std::io::{Error,ErrorKind};
 use alba_types::AlbaTypes;
 use tokio;
 use database::connect;
+use query::PrimitiveQueryConditions;
+use arithmetic::Expression;
 use lexer_functions::{
-    lexer_boolean_match, lexer_bytes_match, lexer_group_match, lexer_ignore_comments_match, lexer_keyword_match, lexer_number_match, lexer_operator_match, lexer_string_match, lexer_subcommand_match, Token
+    lexer_boolean_match, lexer_bytes_match, lexer_function_match, lexer_group_match, lexer_ignore_comments_match, lexer_keyword_match, lexer_number_match, lexer_operator_match, lexer_string_match, lexer_subcommand_match, lexer_temporal_match, Token
 };
 pub mod better_logs;
 
@@ -33,10 +38,12 @@ fn lexer(input: String) -> Result<Vec<Token>, Error> {
         dough.push(c);
 
         lexer_ignore_comments_match(&mut dough, &mut characters);
-        lexer_keyword_match(&mut result, &mut dough);
+        lexer_keyword_match(&mut result, &mut dough, characters.peek());
+        lexer_function_match(&mut result, &mut dough, &mut characters)?;
         lexer_subcommand_match(&mut result, &mut dough, &mut characters)?;
         lexer_group_match(&mut result, &mut dough, &mut characters);
         lexer_boolean_match(&mut result, &mut dough, &mut characters);
+        lexer_temporal_match(&mut result, &mut dough, &mut characters);
         lexer_number_match(&mut result, &mut dough, &mut characters);
         lexer_operator_match(&mut result, &mut dough, &mut characters);
         lexer_string_match(&mut result, &mut dough, &mut characters);
@@ -44,7 +51,8 @@ fn lexer(input: String) -> Result<Vec<Token>, Error> {
     }
 
     if !dough.trim().is_empty() {
-        lexer_keyword_match(&mut result, &mut dough);
+        lexer_keyword_match(&mut result, &mut dough, None);
+        lexer_function_match(&mut result, &mut dough, &mut characters)?;
         lexer_subcommand_match(&mut result, &mut dough, &mut characters)?;
         lexer_group_match(&mut result, &mut dough, &mut characters);
         lexer_boolean_match(&mut result, &mut dough, &mut characters);
@@ -72,18 +80,60 @@ fn lexer(input: String) -> Result<Vec<Token>, Error> {
 - CREATE <Instance> ...
 | CREATE CONTAINER <name> [col_nam][col_typ] 
 | CREATE ROW [col_nam][col_val] ON <container:name>
+| CREATE INDEX <name> ON <container:name> (<col_nam>)
+
+  a <col_typ> may be followed by NULLABLE and then UNIQUE, like 'TEXT NULLABLE UNIQUE', a UNIQUE column gets an index of its own
+  the first column is the primary key, it is unique like the UNIQUE columns, NULL never conflicts
+  TIMESTAMP and DATE values are written unquoted in ISO-8601, like 2024-05-01T10:20:30Z and 2024-05-01, or as NOW()
+  UUID values are quoted, like '550e8400-e29b-41d4-a716-446655440000', or generated with GEN_UUID(),
+  a UUID column that isn't NULLABLE gets a new UUID when a row leaves it out
+  JSON values are quoted documents, like '{"user": {"id": "abc"}}'
+  DECIMAL(<precision>, <scale>) holds up to 38 exact digits, <scale> of them after the point, like 'DECIMAL(12,2)' for 1234567890.50
+  ARRAY<INT>, ARRAY<BIGINT>, ARRAY<FLOAT> and ARRAY<NANO-STRING> values are written as groups, like ['red', 'blue'], or as quoted JSON arrays
 
 - EDIT <Instance> ...
 | EDIT ROW [col_name][col_val] ON <container:name> WHERE <conditions>
 
+  a <col_val> of EDIT ROW may be arithmetic over the row, like ['counter' + 1]
+
 - DELETE <instance> ...
 | DELETE ROW ON <container> WHERE <conditions>
 | DELETE ROW ON <container>
 | DELETE CONTAINER <container>
+| DELETE INDEX <name>
+
+- ALTER CONTAINER <container> ...
+| ALTER CONTAINER <container> ADD COLUMN <col_nam> <col_typ>
+| ALTER CONTAINER <container> DROP COLUMN <col_nam>
+| ALTER CONTAINER <container> RENAME COLUMN <col_nam> TO <col_nam>
+  the first column can't be dropped, it is the primary key
+
+- BEGIN
+| COMMIT [<container>]
+| ROLLBACK [<container>]
+| SAVEPOINT <name>
+| ROLLBACK TO <name>
+| RELEASE <name>
+
+  every client session has its own transaction, COMMIT and ROLLBACK only touch the changes of that session
+  outside a BEGIN each CREATE ROW, EDIT ROW and DELETE ROW is committed on its own when auto_commit is set
+  a statement reads the rows committed when it started, inside a BEGIN the ones committed when BEGIN ran
+  COMMIT fails when another session committed a change to a row the transaction changed since it began
+  inside a BEGIN, ROLLBACK TO discards the changes made after SAVEPOINT <name> and keeps the savepoint, RELEASE forgets it
+  a transaction that runs no statement for transaction_timeout_seconds is rolled back
 
 - SEARCH <col_nam> ON <container> ... 
 | SEARCH <col_nam> ON <container>
 | SEARCH <col_nam> ON <container> WHERE <conditions>
+| SEARCH <col_nam> ON <container> [LEFT] JOIN <container> ON <container.col_nam> = <container.col_nam> ... [WHERE <conditions>] ...
+| SEARCH <col_nam> ON <container> [WHERE <conditions>] [GROUP BY <col_nam>, ...] [ORDER BY <col_nam> [ASC|DESC], ...] [LIMIT <n>] [OFFSET <n>]
+
+  <conditions> are <col_nam> OP <value>, <expr> OP <expr>, <col_nam> IS [NOT] NULL, <col_nam> [NOT] IN (<value>, ...) or <col_nam> [NOT] BETWEEN <value> AND <value>, combined with AND, OR and NOT and grouped with ( ... ), AND binds tighter than OR
+  <expr> is arithmetic with + - * / % over numbers and <col_nam>, where a quoted name is always a column
+  an ARRAY <col_nam> is matched with <col_nam> [NOT] CONTAINS <value>, or with <col_nam> [NOT] CONTAINS ANY (<value>, ...) to hold one of the values
+  a JSON <col_nam> is followed by -> 'key' or -> <position> to step into the document and by ->> to end with a plain value, like 'doc'->'user'->>'id' = 'abc'
+  with JOIN the columns are named <container.col_nam>, the container may be left out when the name is not ambiguous
+  <col_nam> may be ['*'] for every column, or hold aggregates: COUNT(*), COUNT(<col_nam>), SUM(<col_nam>), AVG(<col_nam>), MIN(<col_nam>), MAX(<col_nam>)
 
 */
 #[derive(Debug, Clone, PartialEq)]
@@ -93,9 +143,16 @@ enum AST{
     EditRow(AstEditRow),
     DeleteRow(AstDeleteRow),
     DeleteContainer(AstDeleteContainer),
+    AlterContainer(AstAlterContainer),
+    CreateIndex(AstCreateIndex),
+    DeleteIndex(AstDeleteIndex),
     Search(AstSearch),
+    Begin,
     Commit(AstCommit),
     Rollback(AstRollback),
+    Savepoint(AstSavepoint),
+    RollbackTo(AstSavepoint),
+    Release(AstSavepoint),
 }
 
 
@@ -105,6 +162,8 @@ struct AstCreateContainer{
     name : String,
     col_nam : Vec<String>,
     col_val : Vec<AlbaTypes>,
+    nullable : Vec<bool>,
+    unique : Vec<bool>,
 }
 #[derive(Debug, Clone, PartialEq)]
 struct AstCreateRow{
@@ -115,20 +174,42 @@ struct AstCreateRow{
 #[derive(Debug, Clone, PartialEq)]
 struct AstEditRow{
     col_nam : Vec<String>,
-    col_val : Vec<AlbaTypes>,
+    col_val : Vec<Expression>,
     container : String,
-    conditions : (Vec<(Token,Token,Token)>,Vec<(usize,char)>)
+    conditions : PrimitiveQueryConditions
 }
 #[derive(Debug, Clone, PartialEq)]
 struct AstDeleteRow{
     container : String,
-    conditions : Option<(Vec<(Token,Token,Token)>,Vec<(usize,char)>)>
+    conditions : PrimitiveQueryConditions
 }
 #[derive(Debug, Clone, PartialEq)]
 struct AstDeleteContainer{
     container : String,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+struct AstCreateIndex{
+    name : String,
+    container : String,
+    column : String,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstDeleteIndex{
+    name : String,
+}
+#[derive(Debug, Clone, PartialEq)]
+enum AlterColumn {
+    Add{name : String, col_val : AlbaTypes, nullable : bool},
+    Drop{name : String},
+    Rename{name : String, new_name : String},
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstAlterContainer{
+    container : String,
+    change : AlterColumn,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 enum AlbaContainer {
     Real(String),
@@ -138,8 +219,34 @@ enum AlbaContainer {
 #[derive(Debug, Clone, PartialEq)]
 struct AstSearch{
     container : Vec<AlbaContainer>,
-    conditions : (Vec<(Token,Token,Token)>,Vec<(usize,char)>),
-    col_nam : Vec<String>,
+    conditions : PrimitiveQueryConditions,
+    joins : Vec<AstJoin>,
+    projection : Vec<Projection>,
+    group_by : Vec<String>,
+    order_by : Vec<(String,bool)>, // (column, descending)
+    limit : Option<usize>,
+    offset : usize,
+}
+#[derive(Debug, Clone, PartialEq)]
+struct AstJoin{
+    container : String,
+    left : String,
+    right : String,
+    outer : bool, // LEFT JOIN, rows without a match are kept with NULL columns
+}
+#[derive(Debug, Clone, PartialEq)]
+enum Projection{
+    All,
+    Column(String),
+    Aggregate(AggregateFunction, Option<String>), // None is COUNT(*)
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AggregateFunction{
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
 }
 #[derive(Debug, Clone, PartialEq)]
 struct AstCommit{
@@ -151,6 +258,11 @@ struct AstRollback{
     container : Option<String>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+struct AstSavepoint{
+    name : String,
+}
+
 fn gerr(msg : &str) -> Error{
     return Error::new(ErrorKind::Other, msg.to_string())
 }
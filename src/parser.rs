@@ -2,7 +2,7 @@ use std::io::{Error, ErrorKind};
 
 use base64::Engine;
 
-use crate::{alba_types::AlbaTypes, gerr, lexer, lexer_functions::{Token, B64ENGINE}, AlbaContainer, AstCommit, AstCreateContainer, AstCreateRow, AstEditRow, AstRollback, AstSearch, AST};
+use crate::{alba_types::{parse_decimal, AlbaTypes, DECIMAL_MAX_PRECISION}, arithmetic::{ArithmeticOperator, Expression, JsonKey}, gerr, lexer, lexer_functions::{Token, B64ENGINE}, query::{ConditionExpression, PrimitiveQueryConditions}, AlbaContainer, AlterColumn, AstAlterContainer, AstCommit, AstCreateContainer, AstCreateIndex, AstDeleteIndex, AstCreateRow, AstEditRow, AstRollback, AstSavepoint, AstSearch, AstJoin, AggregateFunction, Projection, AST};
 
 
 
@@ -36,8 +36,89 @@ fn parser_debugger_extract_group_elstr(output : &mut Vec<String>,list : &Vec<Tok
     }
     None
 }
+/// Parses a column type declaration such as 'INT', 'TEXT NULLABLE' or 'NANO-STRING UNIQUE'.
+/// Columns are NOT NULL unless declared as NULLABLE, the returned bools tell if the column accepts NULL
+/// and if its values must be unique.
+fn parse_column_type(declaration: &str) -> Result<(AlbaTypes, bool, bool), Error> {
+    let upper = declaration.to_uppercase();
+    // the arguments of DECIMAL(p,s) may hold spaces
+    let (name, rest) = match upper.trim_start().split_once(')') {
+        Some((name, rest)) if name.starts_with("DECIMAL") => (format!("{})", name), rest.to_string()),
+        _ => match upper.trim_start().split_once(char::is_whitespace) {
+            Some((name, rest)) => (name.to_string(), rest.to_string()),
+            None => (upper.trim().to_string(), String::new()),
+        },
+    };
+    let words = rest.split_whitespace();
+    let ty = match name.as_str() {
+        "INT" => AlbaTypes::Int(0),
+        "BIGINT" => AlbaTypes::Bigint(0),
+        "FLOAT" => AlbaTypes::Float(0.0),
+        "BOOL" => AlbaTypes::Bool(false),
+        "TEXT" => AlbaTypes::Text(String::new()),
+        "NANO-STRING" => AlbaTypes::NanoString(String::new()),
+        "SMALL-STRING" => AlbaTypes::SmallString(String::new()),
+        "MEDIUM-STRING" => AlbaTypes::MediumString(String::new()),
+        "BIG-STRING" => AlbaTypes::BigString(String::new()),
+        "LARGE-STRING" => AlbaTypes::LargeString(String::new()),
+        "NANO-BYTES" => AlbaTypes::NanoBytes(Vec::new()),
+        "SMALL-BYTES" => AlbaTypes::SmallBytes(Vec::new()),
+        "MEDIUM-BYTES" => AlbaTypes::MediumBytes(Vec::new()),
+        "BIG-BYTES" => AlbaTypes::BigSBytes(Vec::new()),
+        "LARGE-BYTES" => AlbaTypes::LargeBytes(Vec::new()),
+        "TIMESTAMP" => AlbaTypes::Timestamp(0),
+        "DATE" => AlbaTypes::Date(0),
+        "UUID" => AlbaTypes::Uuid(0),
+        "JSON" => AlbaTypes::Json(String::new()),
+        "DECIMAL" => AlbaTypes::Decimal(0, 18, 0),
+        array if array.starts_with("ARRAY<") => parse_array_type(array).ok_or_else(|| gerr(&format!("Expected ARRAY<INT>, ARRAY<BIGINT>, ARRAY<FLOAT> or ARRAY<NANO-STRING>, found {}", declaration)))?,
+        decimal if decimal.starts_with("DECIMAL") => parse_decimal_type(decimal).ok_or_else(|| gerr(&format!("Expected DECIMAL(<precision>, <scale>) with a precision up to {} and a scale up to the precision, found {}", DECIMAL_MAX_PRECISION, declaration)))?,
+        _ => return Err(gerr(&format!("Unknown type: {}", declaration))),
+    };
+    let mut modifier : Vec<&str> = words.collect();
+    let unique = modifier.last() == Some(&"UNIQUE");
+    if unique {
+        modifier.pop();
+    }
+    let nullable = match modifier.as_slice() {
+        [] | ["NOT", "NULL"] => false,
+        ["NULLABLE"] | ["NULL"] => true,
+        _ => return Err(gerr(&format!("Unknown column modifier in type: {}", declaration))),
+    };
+    Ok((ty, nullable, unique))
+}
+
+/// `DECIMAL(p,s)` or `DECIMAL(p)`, which has no digits after the point.
+fn parse_decimal_type(declaration: &str) -> Option<AlbaTypes> {
+    let arguments = declaration.strip_prefix("DECIMAL")?.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let mut numbers = arguments.split(',').map(|n| n.trim().parse::<u8>());
+    let precision = numbers.next()?.ok()?;
+    let scale = match numbers.next() {
+        Some(scale) => scale.ok()?,
+        None => 0,
+    };
+    if numbers.next().is_some() || precision == 0 || precision > DECIMAL_MAX_PRECISION || scale > precision {
+        return None
+    }
+    Some(AlbaTypes::Decimal(0, precision, scale))
+}
+
+/// `ARRAY<INT>`, `ARRAY<BIGINT>`, `ARRAY<FLOAT>` or `ARRAY<NANO-STRING>`.
+fn parse_array_type(declaration: &str) -> Option<AlbaTypes> {
+    let element = match declaration.strip_prefix("ARRAY<")?.strip_suffix('>')?.trim() {
+        "INT" => AlbaTypes::Int(0),
+        "BIGINT" => AlbaTypes::Bigint(0),
+        "FLOAT" => AlbaTypes::Float(0.0),
+        "NANO-STRING" => AlbaTypes::NanoString(String::new()),
+        _ => return None,
+    };
+    Some(AlbaTypes::Array(Vec::new(), element.get_id()))
+}
+
 fn parser_debugger_extract_group_albatype(
     output: &mut Vec<AlbaTypes>,
+    nullable: &mut Vec<bool>,
+    unique: &mut Vec<bool>,
     list: &[Token],
     index: usize
 ) -> Option<Error> {
@@ -47,25 +128,14 @@ fn parser_debugger_extract_group_albatype(
                 for item in g {
                     match item {
                         Token::String(s) => {
-                            let ty = match s.to_uppercase().as_str() {
-                                "INT" => AlbaTypes::Int(0),
-                                "BIGINT" => AlbaTypes::Bigint(0),
-                                "FLOAT" => AlbaTypes::Float(0.0),
-                                "BOOL" => AlbaTypes::Bool(false),
-                                "TEXT" => AlbaTypes::Text(String::new()),
-                                "NANO-STRING" => AlbaTypes::NanoString(String::new()),
-                                "SMALL-STRING" => AlbaTypes::SmallString(String::new()),
-                                "MEDIUM-STRING" => AlbaTypes::MediumString(String::new()),
-                                "BIG-STRING" => AlbaTypes::BigString(String::new()),
-                                "LARGE-STRING" => AlbaTypes::LargeString(String::new()),
-                                "NANO-BYTES" => AlbaTypes::NanoBytes(Vec::new()),
-                                "SMALL-BYTES" => AlbaTypes::SmallBytes(Vec::new()),
-                                "MEDIUM-BYTES" => AlbaTypes::MediumBytes(Vec::new()),
-                                "BIG-BYTES" => AlbaTypes::BigSBytes(Vec::new()),
-                                "LARGE-BYTES" => AlbaTypes::LargeBytes(Vec::new()),
-                                _ => return Some(gerr(&format!("Unknown type: {}", s))),
-                            };
-                            output.push(ty);
+                            match parse_column_type(s) {
+                                Ok((ty, n, u)) => {
+                                    output.push(ty);
+                                    nullable.push(n);
+                                    unique.push(u);
+                                },
+                                Err(e) => return Some(e),
+                            }
                         },
                         _ => return Some(gerr("Expected string for column type")),
                     }
@@ -79,6 +149,57 @@ fn parser_debugger_extract_group_albatype(
     }
 }
 
+fn parser_debugger_extract_group_values(
+    output: &mut Vec<AlbaTypes>,
+    list: &[Token],
+    index: usize
+) -> Option<Error> {
+    if let Some(token) = list.get(index) {
+        match token {
+            Token::Group(g) => {
+                for item in g {
+                    match AlbaTypes::try_from(item.clone()) {
+                        Ok(value) => output.push(value),
+                        Err(e) => return Some(gerr(e)),
+                    }
+                }
+                None
+            },
+            _ => Some(gerr("Missing column values group")),
+        }
+    } else {
+        Some(gerr("Missing token for column values"))
+    }
+}
+
+/// Like `parser_debugger_extract_group_values`, but an element with an operator or in parentheses is an expression computed from the row.
+fn parser_debugger_extract_group_expressions(
+    output: &mut Vec<Expression>,
+    list: &[Token],
+    index: usize
+) -> Option<Error> {
+    if let Some(token) = list.get(index) {
+        match token {
+            Token::Group(g) => {
+                for item in g {
+                    let expression = match item {
+                        Token::SubCommand(inner) => parse_whole_arithmetic(inner),
+                        _ => AlbaTypes::try_from(item.clone()).map(Expression::Value).map_err(gerr),
+                    };
+                    match expression {
+                        Ok(expression) => output.push(expression),
+                        Err(e) => return Some(e),
+                    }
+                }
+                None
+            },
+            _ => Some(gerr("Missing column values group")),
+        }
+    } else {
+        Some(gerr("Missing token for column values"))
+    }
+}
+
 fn debug_create_command(tokens: &Vec<Token>) -> Result<AST,Error>{
     if let Some(instance) = tokens.get(1){
         match instance{
@@ -88,6 +209,8 @@ fn debug_create_command(tokens: &Vec<Token>) -> Result<AST,Error>{
                         let mut cname : String = String::new();
                         let mut col_name : Vec<String> = Vec::with_capacity(5);
                         let mut col_types : Vec<AlbaTypes> = Vec::with_capacity(5);
+                        let mut col_nullable : Vec<bool> = Vec::with_capacity(5);
+                        let mut col_unique : Vec<bool> = Vec::with_capacity(5);
                         if let Some(err) = parser_debugger_extract_string(&mut cname,tokens,2){
                             return Err(err)
                         }
@@ -102,14 +225,14 @@ fn debug_create_command(tokens: &Vec<Token>) -> Result<AST,Error>{
                             col_name_holder.push(i.clone());
                         }
                         drop(col_name_holder);
-                        if let Some(bruh) = parser_debugger_extract_group_albatype(&mut col_types, tokens, 4){
+                        if let Some(bruh) = parser_debugger_extract_group_albatype(&mut col_types, &mut col_nullable, &mut col_unique, tokens, 4){
                             return Err(bruh)
                         }
                         if col_name.len() != col_types.len(){
                             return Err(gerr("All column names and column types are not matching"))
                         }
                         
-                        return Ok(AST::CreateContainer(AstCreateContainer { name: cname, col_nam: col_name, col_val: col_types }))
+                        return Ok(AST::CreateContainer(AstCreateContainer { name: cname, col_nam: col_name, col_val: col_types, nullable: col_nullable, unique: col_unique }))
                     }
                     "ROW" => {
                         let mut col_names : Vec<String> = Vec::with_capacity(5);
@@ -119,7 +242,7 @@ fn debug_create_command(tokens: &Vec<Token>) -> Result<AST,Error>{
                         if let Some(cva) = parser_debugger_extract_group_elstr(&mut col_names, tokens, 2){
                             return Err(cva)
                         }
-                        if let Some(bruh) = parser_debugger_extract_group_albatype(&mut col_values, tokens, 3){
+                        if let Some(bruh) = parser_debugger_extract_group_values(&mut col_values, tokens, 3){
                             return Err(bruh)
                         }
                         if let Some(cn) = tokens.get(5){
@@ -133,6 +256,19 @@ fn debug_create_command(tokens: &Vec<Token>) -> Result<AST,Error>{
                         return Ok(AST::CreateRow(AstCreateRow { col_nam: col_names, col_val: col_values, container: container }))
                         
                     },
+                    "INDEX" => {
+                        let name = expect_string(tokens, 2, "index name")?;
+                        expect_keyword(tokens, 3, "ON")?;
+                        let container = expect_string(tokens, 4, "container name")?;
+                        let column = match tokens.get(5){
+                            Some(Token::SubCommand(t)) | Some(Token::Group(t)) if t.len() == 1 => match &t[0]{
+                                Token::String(s) => s.clone(),
+                                _ => return Err(gerr("Invalid type, the indexed column name must be a string"))
+                            },
+                            _ => return Err(gerr("Expected the indexed column between parentheses at position 5"))
+                        };
+                        return Ok(AST::CreateIndex(AstCreateIndex { name, container, column }))
+                    },
                     _ => {return Err(gerr("Invalid instance type"))}
                 }
             },
@@ -152,14 +288,14 @@ fn debug_edit_command(tokens : &Vec<Token>) -> Result<AST,Error> {
                 match st.as_str(){
                     "ROW" => {
                         let mut ed_col_name : Vec<String> = Vec::with_capacity(20);
-                        let mut ed_col_type : Vec<AlbaTypes> = Vec::with_capacity(20);
+                        let mut ed_col_type : Vec<Expression> = Vec::with_capacity(20);
                         let mut ed_container : String = String::new();
-                        let mut conditions: (Vec<(Token, Token, Token)>, Vec<(usize, char)>) = (Vec::with_capacity(10), Vec::with_capacity(10));   
+                        let mut conditions: PrimitiveQueryConditions = None;
 
                         if let Some(errrrrr) = parser_debugger_extract_group_elstr(&mut ed_col_name, &tokens, 2){
                             return Err(errrrrr)
                         }
-                        if let Some(errrrrr) = parser_debugger_extract_group_albatype(&mut ed_col_type, &tokens, 3){
+                        if let Some(errrrrr) = parser_debugger_extract_group_expressions(&mut ed_col_type, &tokens, 3){
                             return Err(errrrrr)
                         }
                         if let Some(t) = tokens.get(4){
@@ -181,67 +317,7 @@ fn debug_edit_command(tokens : &Vec<Token>) -> Result<AST,Error> {
                                 return Err(gerr(&format!(r#"In EDIT ROW command, expected keyword 'WHERE' at position 6, but found {:?}"#, tok)));
                             }
 
-                            if let Some(iterator_of_tokens) = tokens.get(7..) {
-                                let mut bushes: Vec<Token> = Vec::new();
-
-                                for i in iterator_of_tokens {
-                                    if bushes.len() == 3 {
-                                        conditions.0.push((
-                                            get_from_bushes_with_safety(&bushes, 0)?,
-                                            get_from_bushes_with_safety(&bushes, 1)?,
-                                            get_from_bushes_with_safety(&bushes, 2)?,
-                                        ));
-                                        bushes.clear();
-
-                                        conditions.1.push((
-                                            conditions.0.len() - 1,
-                                            match i {
-                                                Token::Keyword(a) => match a.to_uppercase().as_str() {
-                                                    "OR" => 'o',
-                                                    "AND" => 'a',
-                                                    _ => {
-                                                        return Err(gerr(
-                                                            "Expected logical operator 'AND' or 'OR' after a condition",
-                                                        ))
-                                                    }
-                                                },
-                                                _ => return Err(gerr("Expected logical operator after condition")),
-                                            },
-                                        ));
-                                        continue;
-                                    }
-
-                                    match i {
-                                        Token::String(_) => match bushes.len() {
-                                            0 | 2 => bushes.push(i.clone()),
-                                            _ => return Err(gerr("Unexpected string: operator might be missing")),
-                                        },
-                                        Token::Bool(_) | Token::Int(_) | Token::Float(_) => {
-                                            if bushes.len() == 2 {
-                                                bushes.push(i.clone())
-                                            } else {
-                                                return Err(gerr("Unexpected value: condition must follow 'column OP value' pattern"));
-                                            }
-                                        }
-                                        Token::Operator(_) => {
-                                            if bushes.len() == 1 {
-                                                bushes.push(i.clone());
-                                            } else {
-                                                return Err(gerr("Unexpected operator: check condition structure"));
-                                            }
-                                        }
-                                        _ => return Err(gerr("Unexpected token in WHERE clause")),
-                                    }
-                                }
-
-                                if bushes.len() == 3 {
-                                    conditions.0.push((
-                                        get_from_bushes_with_safety(&bushes, 0)?,
-                                        get_from_bushes_with_safety(&bushes, 1)?,
-                                        get_from_bushes_with_safety(&bushes, 2)?,
-                                    ));
-                                }
-                            }
+                            conditions = parse_conditions(tokens.get(7..).unwrap_or_default())?;
                         }
 
                         return Ok(AST::EditRow(AstEditRow{
@@ -264,8 +340,291 @@ fn debug_edit_command(tokens : &Vec<Token>) -> Result<AST,Error> {
     return Err(gerr("Missing the instance to be editted"));
 }
 
-fn get_from_bushes_with_safety(bushes : &Vec<Token>,index : usize) -> Result<Token,Error>{
-    match bushes.get(index){ Some(a) => Ok(a.clone()), None => return Err(gerr("Missing"))}
+/// Parses a WHERE clause into a tree of conditions: OR binds loosest, then AND, then NOT, and `( ... )` groups conditions.
+/// `column IS NULL` and `column IS NOT NULL` are read as a condition with an "IS NULL"/"IS NOT NULL" operator.
+fn parse_conditions(tokens: &[Token]) -> Result<PrimitiveQueryConditions, Error> {
+    if tokens.is_empty() {
+        return Ok(None)
+    }
+    let mut position = 0;
+    let expression = parse_condition_or(tokens, &mut position)?;
+    if position < tokens.len() {
+        return Err(gerr("Expected logical operator 'AND' or 'OR' after a condition"))
+    }
+    Ok(Some(expression))
+}
+
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    matches!(token, Some(Token::Keyword(k)) if k.eq_ignore_ascii_case(keyword))
+}
+
+fn parse_condition_or(tokens: &[Token], position: &mut usize) -> Result<ConditionExpression, Error> {
+    let mut items = vec![parse_condition_and(tokens, position)?];
+    while is_keyword(tokens.get(*position), "OR") {
+        *position += 1;
+        items.push(parse_condition_and(tokens, position)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { ConditionExpression::Or(items) })
+}
+
+fn parse_condition_and(tokens: &[Token], position: &mut usize) -> Result<ConditionExpression, Error> {
+    let mut items = vec![parse_condition_not(tokens, position)?];
+    while is_keyword(tokens.get(*position), "AND") {
+        *position += 1;
+        items.push(parse_condition_not(tokens, position)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { ConditionExpression::And(items) })
+}
+
+fn parse_condition_not(tokens: &[Token], position: &mut usize) -> Result<ConditionExpression, Error> {
+    if is_keyword(tokens.get(*position), "NOT") {
+        *position += 1;
+        return Ok(ConditionExpression::Not(Box::new(parse_condition_not(tokens, position)?)))
+    }
+    parse_condition_primary(tokens, position)
+}
+
+fn parse_condition_primary(tokens: &[Token], position: &mut usize) -> Result<ConditionExpression, Error> {
+    match tokens.get(*position) {
+        // parentheses followed by an operator hold arithmetic, like ('a' + 1) * 2 > 10
+        Some(Token::SubCommand(inner)) if !matches!(tokens.get(*position + 1), Some(Token::Operator(_))) && !is_negative_number(tokens.get(*position + 1)) => {
+            *position += 1;
+            return match parse_conditions(inner)? {
+                Some(expression) => Ok(expression),
+                None => Err(gerr("Empty parentheses in WHERE clause")),
+            }
+        }
+        Some(Token::String(_) | Token::SubCommand(_) | Token::Int(_) | Token::Float(_) | Token::Decimal(..) | Token::Timestamp(_) | Token::Date(_) | Token::Uuid(_)) => {},
+        Some(Token::Operator(o)) if o == "-" => {},
+        Some(Token::Keyword(k)) if k == "AND" || k == "OR" => return Err(gerr("Expected a condition before and after each logical operator")),
+        Some(_) => return Err(gerr("Unexpected token in WHERE clause")),
+        None => return Err(gerr("Incomplete condition in WHERE clause")),
+    }
+    let column = match parse_arithmetic(tokens, position, 0)? {
+        Expression::Column(column) => Token::String(column),
+        left => {
+            let operator = match tokens.get(*position) {
+                Some(Token::Operator(operator)) => Token::Operator(operator.clone()),
+                _ => return Err(gerr("Expected a comparison operator after an arithmetic expression")),
+            };
+            *position += 1;
+            let right = match tokens.get(*position) {
+                // like <col_nam> OP <value>, a JSON path is compared with plain values
+                Some(value) if matches!(left, Expression::JsonPath(..)) && is_condition_value(value) && !continues_arithmetic(tokens.get(*position + 1)) => {
+                    *position += 1;
+                    Expression::Value(match value {
+                        // ->> yields Text
+                        Token::String(text) => AlbaTypes::Text(text.clone()),
+                        value => AlbaTypes::try_from(value.clone()).map_err(gerr)?,
+                    })
+                }
+                _ => parse_arithmetic(tokens, position, 0)?,
+            };
+            return Ok(ConditionExpression::Compare(left, operator, right))
+        }
+    };
+    // `column NOT IN (...)`, `column NOT BETWEEN ...` and `column NOT CONTAINS ...` negate the whole predicate
+    let negated = is_keyword(tokens.get(*position), "NOT")
+        && (is_keyword(tokens.get(*position + 1), "IN") || is_keyword(tokens.get(*position + 1), "BETWEEN") || is_keyword(tokens.get(*position + 1), "CONTAINS"));
+    if negated {
+        *position += 1;
+    }
+    let expression = match tokens.get(*position) {
+        Some(Token::Keyword(k)) if k == "IN" => {
+            *position += 1;
+            ConditionExpression::In(column, parse_value_list(tokens, position, "IN")?)
+        }
+        // `column CONTAINS ANY (...)` holds at least one of the values
+        Some(Token::Keyword(k)) if k == "CONTAINS" => {
+            *position += 1;
+            let contains = |value| ConditionExpression::Condition(column.clone(), Token::Operator("CONTAINS".to_string()), value);
+            if is_keyword(tokens.get(*position), "ANY") {
+                *position += 1;
+                ConditionExpression::Or(parse_value_list(tokens, position, "CONTAINS ANY")?.into_iter().map(contains).collect())
+            } else {
+                contains(parse_condition_value(tokens, position)?)
+            }
+        }
+        Some(Token::Keyword(k)) if k == "BETWEEN" => {
+            *position += 1;
+            let low = parse_condition_value(tokens, position)?;
+            if !is_keyword(tokens.get(*position), "AND") {
+                return Err(gerr("Expected AND between the bounds of BETWEEN"))
+            }
+            *position += 1;
+            let high = parse_condition_value(tokens, position)?;
+            ConditionExpression::Between(column, low, high)
+        }
+        Some(Token::Keyword(k)) if k == "IS" => {
+            *position += 1;
+            let negated = is_keyword(tokens.get(*position), "NOT");
+            if negated {
+                *position += 1;
+            }
+            if !is_keyword(tokens.get(*position), "NULL") {
+                return Err(gerr("Expected NULL after IS"))
+            }
+            *position += 1;
+            ConditionExpression::Condition(
+                column,
+                Token::Operator(if negated { "IS NOT NULL" } else { "IS NULL" }.to_string()),
+                Token::Keyword("NULL".to_string()),
+            )
+        }
+        Some(Token::Operator(operator)) => {
+            let operator = Token::Operator(operator.clone());
+            *position += 1;
+            let value = match tokens.get(*position) {
+                // kept so the condition builder can point to IS NULL instead of failing here
+                Some(value @ Token::Keyword(k)) if k == "NULL" => {
+                    *position += 1;
+                    value.clone()
+                }
+                Some(value) if is_condition_value(value) && !continues_arithmetic(tokens.get(*position + 1)) => parse_condition_value(tokens, position)?,
+                // the other side is computed, where quoted names are columns
+                _ => {
+                    let left = match column {
+                        Token::String(column) => Expression::Column(column),
+                        _ => unreachable!(),
+                    };
+                    let right = parse_arithmetic(tokens, position, 0)?;
+                    return Ok(wrap_negated(negated, ConditionExpression::Compare(left, operator, right)))
+                }
+            };
+            ConditionExpression::Condition(column, operator, value)
+        }
+        Some(_) => return Err(gerr("Unexpected token: operator might be missing")),
+        None => return Err(gerr("Incomplete condition in WHERE clause")),
+    };
+    Ok(wrap_negated(negated, expression))
+}
+
+/// A list of plain values like (1, 2, 3) or [1, 2, 3].
+fn parse_value_list(tokens: &[Token], position: &mut usize, keyword: &str) -> Result<Vec<Token>, Error> {
+    let values = match tokens.get(*position) {
+        Some(Token::SubCommand(list)) => list
+            .split(|t| matches!(t, Token::Operator(o) if o == ","))
+            .map(|part| match part {
+                [value] => Ok(value.clone()),
+                _ => Err(gerr(&format!("Each value of an {} list must be a single value separated by commas", keyword))),
+            })
+            .collect::<Result<Vec<Token>, Error>>()?,
+        Some(Token::Group(list)) => list.clone(),
+        _ => return Err(gerr(&format!("Expected a list of values after {}, like (1, 2, 3)", keyword))),
+    };
+    if let Some(value) = values.iter().find(|v| !is_condition_value(v)) {
+        return Err(gerr(&format!("Unexpected value in {} list: {:?}", keyword, value)))
+    }
+    *position += 1;
+    Ok(values)
+}
+
+fn wrap_negated(negated: bool, expression: ConditionExpression) -> ConditionExpression {
+    if negated { ConditionExpression::Not(Box::new(expression)) } else { expression }
+}
+
+fn arithmetic_operator(token: Option<&Token>) -> Option<ArithmeticOperator> {
+    match token {
+        Some(Token::Operator(symbol)) => ArithmeticOperator::from_symbol(symbol),
+        _ => None,
+    }
+}
+
+/// A negative number right after an operand, like in 'a' -1, is lexed together with its sign.
+fn is_negative_number(token: Option<&Token>) -> bool {
+    matches!(token, Some(Token::Int(i)) if *i < 0) || matches!(token, Some(Token::Float(f)) if *f < 0.0) || matches!(token, Some(Token::Decimal(d, _)) if *d < 0)
+}
+
+fn continues_arithmetic(token: Option<&Token>) -> bool {
+    arithmetic_operator(token).is_some() || is_negative_number(token)
+}
+
+/// Parses `+ - * / %` over columns, numbers and parentheses, stopping before any operator that binds looser than `min_precedence`.
+fn parse_arithmetic(tokens: &[Token], position: &mut usize, min_precedence: u8) -> Result<Expression, Error> {
+    let mut left = parse_arithmetic_operand(tokens, position)?;
+    loop {
+        let (operator, signed_number) = match arithmetic_operator(tokens.get(*position)) {
+            Some(operator) => (operator, false),
+            None if is_negative_number(tokens.get(*position)) => (ArithmeticOperator::Add, true),
+            None => break,
+        };
+        if operator.precedence() < min_precedence {
+            break
+        }
+        if !signed_number {
+            *position += 1;
+        }
+        let right = parse_arithmetic(tokens, position, operator.precedence() + 1)?;
+        left = Expression::Operation(Box::new(left), operator, Box::new(right));
+    }
+    Ok(left)
+}
+
+fn parse_arithmetic_operand(tokens: &[Token], position: &mut usize) -> Result<Expression, Error> {
+    let operand = match tokens.get(*position) {
+        Some(Token::String(column)) => {
+            *position += 1;
+            return parse_json_path(Expression::Column(column.clone()), tokens, position)
+        }
+        Some(value @ (Token::Int(_) | Token::Float(_) | Token::Decimal(..) | Token::Timestamp(_) | Token::Date(_))) => Expression::Value(AlbaTypes::try_from(value.clone()).map_err(gerr)?),
+        Some(Token::Keyword(k)) if k == "NULL" => Expression::Value(AlbaTypes::NONE),
+        Some(Token::SubCommand(inner)) => parse_whole_arithmetic(inner)?,
+        Some(Token::Operator(o)) if o == "-" => {
+            *position += 1;
+            let operand = parse_arithmetic_operand(tokens, position)?;
+            return Ok(Expression::Operation(Box::new(Expression::Value(AlbaTypes::Int(0))), ArithmeticOperator::Subtract, Box::new(operand)))
+        }
+        Some(token) => return Err(gerr(&format!("Unexpected {:?} in an arithmetic expression", token))),
+        None => return Err(gerr("Incomplete arithmetic expression")),
+    };
+    *position += 1;
+    Ok(operand)
+}
+
+/// The `->` and `->>` steps after a column, each followed by a quoted key or an array position.
+fn parse_json_path(document: Expression, tokens: &[Token], position: &mut usize) -> Result<Expression, Error> {
+    let mut path = Vec::new();
+    let mut plain = false;
+    while let Some(Token::Operator(o)) = tokens.get(*position) && (o == "->" || o == "->>") {
+        if plain {
+            return Err(gerr("->> must be the last step of a JSON path"))
+        }
+        plain = o == "->>";
+        path.push(match tokens.get(*position + 1) {
+            Some(Token::String(field)) => JsonKey::Field(field.clone()),
+            Some(Token::Int(index)) if *index >= 0 => JsonKey::Index(*index as usize),
+            _ => return Err(gerr(&format!("Expected a quoted key or a position after {}", o))),
+        });
+        *position += 2;
+    }
+    if path.is_empty() {
+        return Ok(document)
+    }
+    Ok(Expression::JsonPath(Box::new(document), path, plain))
+}
+
+fn parse_whole_arithmetic(tokens: &[Token]) -> Result<Expression, Error> {
+    let mut position = 0;
+    let expression = parse_arithmetic(tokens, &mut position, 0)?;
+    if let Some(token) = tokens.get(position) {
+        return Err(gerr(&format!("Unexpected {:?} after an arithmetic expression", token)))
+    }
+    Ok(expression)
+}
+
+fn is_condition_value(token: &Token) -> bool {
+    matches!(token, Token::String(_) | Token::Bool(_) | Token::Int(_) | Token::Float(_) | Token::Decimal(..) | Token::Timestamp(_) | Token::Date(_) | Token::Uuid(_))
+}
+
+fn parse_condition_value(tokens: &[Token], position: &mut usize) -> Result<Token, Error> {
+    match tokens.get(*position) {
+        Some(value) if is_condition_value(value) => {
+            *position += 1;
+            Ok(value.clone())
+        }
+        Some(_) => Err(gerr("Unexpected value: condition must follow 'column OP value' pattern")),
+        None => Err(gerr("Incomplete condition in WHERE clause")),
+    }
 }
 
 fn debug_search(tokens: &Vec<Token>) -> Result<AST, Error> {
@@ -284,24 +643,45 @@ fn debug_search(tokens: &Vec<Token>) -> Result<AST, Error> {
                 }
                 containers
             },
+            Token::String(s) => vec![AlbaContainer::Real(s.clone())],
             _ => return Err(gerr("Expected container group as a group at position 3")),
         },
         None => return Err(gerr("Missing container group (expected at position 3)")),
     };
 
-    let mut conditions: (Vec<(Token, Token, Token)>, Vec<(usize, char)>) =
-        (Vec::with_capacity(10), Vec::with_capacity(10));
+    // [LEFT] JOIN <container> ON <column> = <column>
+    let mut joins = Vec::new();
+    let mut next = 4;
+    loop{
+        let outer = matches!(tokens.get(next), Some(Token::Keyword(k)) if k == "LEFT");
+        let at = if outer { next + 1 } else { next };
+        if !matches!(tokens.get(at), Some(Token::Keyword(k)) if k == "JOIN"){
+            if outer{
+                return Err(gerr(&format!("Expected keyword 'JOIN' after 'LEFT' at position {}", at)))
+            }
+            break;
+        }
+        let joined = expect_string(tokens, at + 1, "joined container")?;
+        expect_keyword(tokens, at + 2, "ON")?;
+        let left = expect_string(tokens, at + 3, "join column")?;
+        match tokens.get(at + 4){
+            Some(Token::Operator(o)) if o == "=" => {},
+            t => return Err(gerr(&format!("Expected '=' between the join columns at position {}, but found {:?}", at + 4, t))),
+        }
+        let right = expect_string(tokens, at + 5, "join column")?;
+        joins.push(AstJoin { container: joined, left, right, outer });
+        next = at + 6;
+    }
+    if !joins.is_empty() && !matches!(container.as_slice(), [AlbaContainer::Real(_)]){
+        return Err(gerr("JOIN needs a single container before it"))
+    }
+
+    let mut conditions: PrimitiveQueryConditions = None;
 
-    let columns: Vec<String> = match tokens.get(1) {
+    let projection: Vec<Projection> = match tokens.get(1) {
         Some(a) => match a {
-            Token::Group(g) => {
-                g.iter()
-                    .map(|a| match a {
-                        Token::String(s) => s.to_string(),
-                        _ => "".to_string(),
-                    })
-                    .collect()
-            }
+            Token::Group(g) if !g.is_empty() => g.iter().map(parse_projection).collect::<Result<_, _>>()?,
+            Token::Group(_) => return Err(gerr("The column group of SEARCH is empty, use ['*'] to get every column")),
             _ => return Err(gerr("Expected a group of column names (strings) at position 1")),
         },
         None => return Err(gerr("Missing column group (expected at position 1)")),
@@ -316,84 +696,119 @@ fn debug_search(tokens: &Vec<Token>) -> Result<AST, Error> {
         }
     }
 
-    if let Some(tok) = tokens.get(4) {
+    let clauses = tokens.iter().skip(next)
+        .position(|t| matches!(t, Token::Keyword(k) if k == "GROUP" || k == "ORDER" || k == "LIMIT" || k == "OFFSET"))
+        .map_or(tokens.len(), |p| p + next);
+
+    if clauses > next && let Some(tok) = tokens.get(next) {
         if match tok {
             Token::Keyword(a) if a.to_uppercase() == "WHERE" => false,
             _ => true,
         } {
-            return Err(gerr(r#"Expected keyword "WHERE" at position 4"#));
-        }
-
-        if let Some(iterator_of_tokens) = tokens.get(5..) {
-            let mut bushes: Vec<Token> = Vec::new();
-
-            for i in iterator_of_tokens {
-                if bushes.len() == 3 {
-                    conditions.0.push((
-                        get_from_bushes_with_safety(&bushes, 0)?,
-                        get_from_bushes_with_safety(&bushes, 1)?,
-                        get_from_bushes_with_safety(&bushes, 2)?,
-                    ));
-                    bushes.clear();
-
-                    conditions.1.push((
-                        conditions.0.len() - 1,
-                        match i {
-                            Token::Keyword(a) => match a.to_uppercase().as_str() {
-                                "OR" => 'o',
-                                "AND" => 'a',
-                                _ => {
-                                    return Err(gerr(
-                                        "Expected logical operator 'AND' or 'OR' after a condition",
-                                    ))
-                                }
-                            },
-                            _ => return Err(gerr("Expected logical operator after condition")),
-                        },
-                    ));
-                    continue;
-                }
-
-                match i {
-                    Token::String(_) => match bushes.len() {
-                        0 | 2 => bushes.push(i.clone()),
-                        _ => return Err(gerr("Unexpected string: operator might be missing")),
-                    },
-                    Token::Bool(_) | Token::Int(_) | Token::Float(_) => {
-                        if bushes.len() == 2 {
-                            bushes.push(i.clone())
-                        } else {
-                            return Err(gerr("Unexpected value: condition must follow 'column OP value' pattern"));
-                        }
-                    }
-                    Token::Operator(_) => {
-                        if bushes.len() == 1 {
-                            bushes.push(i.clone());
-                        } else {
-                            return Err(gerr("Unexpected operator: check condition structure"));
-                        }
-                    }
-                    _ => return Err(gerr("Unexpected token in WHERE clause")),
-                }
-            }
-
-            if bushes.len() == 3 {
-                conditions.0.push((
-                    get_from_bushes_with_safety(&bushes, 0)?,
-                    get_from_bushes_with_safety(&bushes, 1)?,
-                    get_from_bushes_with_safety(&bushes, 2)?,
-                ));
-            }
+            return Err(gerr(&format!(r#"Expected keyword "WHERE" at position {}"#, next)));
         }
+
+        conditions = parse_conditions(tokens.get(next + 1..clauses).unwrap_or_default())?;
     }
 
+    let (group_by, order_by, limit, offset) = parse_result_clauses(&tokens[clauses.min(tokens.len())..])?;
+
     Ok(AST::Search(AstSearch {
         container,
         conditions,
-        col_nam: columns,
+        joins,
+        projection,
+        group_by,
+        order_by,
+        limit,
+        offset,
     }))
 }
 
+fn parse_projection(token : &Token) -> Result<Projection, Error>{
+    match token{
+        Token::String(s) if s == "*" => Ok(Projection::All),
+        Token::Operator(o) if o == "*" => Ok(Projection::All),
+        Token::String(s) => Ok(Projection::Column(s.clone())),
+        Token::Function(name, arguments) => {
+            let function = match name.as_str(){
+                "COUNT" => AggregateFunction::Count,
+                "SUM" => AggregateFunction::Sum,
+                "AVG" => AggregateFunction::Avg,
+                "MIN" => AggregateFunction::Min,
+                "MAX" => AggregateFunction::Max,
+                _ => return Err(gerr(&format!("{} is not an aggregate function", name))),
+            };
+            match arguments.as_slice(){
+                [Token::Operator(o)] if o == "*" && function == AggregateFunction::Count => Ok(Projection::Aggregate(function, None)),
+                [Token::String(column)] => Ok(Projection::Aggregate(function, Some(column.clone()))),
+                _ => Err(gerr(&format!("{} expects a single column name", name))),
+            }
+        },
+        t => Err(gerr(&format!("Expected a column name or an aggregate function in the column group, but found {:?}", t))),
+    }
+}
+
+/// Parses the `GROUP BY`, `ORDER BY`, `LIMIT` and `OFFSET` clauses that may follow the conditions of a SEARCH.
+fn parse_result_clauses(tokens : &[Token]) -> Result<(Vec<String>, Vec<(String,bool)>, Option<usize>, usize), Error>{
+    let mut group_by = Vec::new();
+    let mut order_by = Vec::new();
+    let mut limit = None;
+    let mut offset = None;
+    let mut index = 0;
+    let count = |tokens : &[Token], index : usize, clause : &str| -> Result<usize, Error>{
+        match tokens.get(index){
+            Some(Token::Int(n)) if *n >= 0 => Ok(*n as usize),
+            Some(t) => Err(gerr(&format!("Expected a non-negative number after {}, but found {:?}", clause, t))),
+            None => Err(gerr(&format!("Missing the number after {}", clause))),
+        }
+    };
+    while let Some(token) = tokens.get(index){
+        match token{
+            Token::Keyword(k) if k == "GROUP" && group_by.is_empty() && order_by.is_empty() && limit.is_none() && offset.is_none() => {
+                expect_keyword(tokens, index + 1, "BY")?;
+                index += 2;
+                loop{
+                    group_by.push(expect_string(tokens, index, "column name in GROUP BY")?);
+                    index += 1;
+                    match tokens.get(index){
+                        Some(Token::Operator(o)) if o == "," => index += 1,
+                        _ => break,
+                    }
+                }
+            },
+            Token::Keyword(k) if k == "ORDER" && order_by.is_empty() && limit.is_none() && offset.is_none() => {
+                expect_keyword(tokens, index + 1, "BY")?;
+                index += 2;
+                loop{
+                    let column = expect_string(tokens, index, "column name in ORDER BY")?;
+                    index += 1;
+                    let descending = match tokens.get(index){
+                        Some(Token::Keyword(k)) if k == "ASC" => { index += 1; false },
+                        Some(Token::Keyword(k)) if k == "DESC" => { index += 1; true },
+                        _ => false,
+                    };
+                    order_by.push((column, descending));
+                    match tokens.get(index){
+                        Some(Token::Operator(o)) if o == "," => index += 1,
+                        _ => break,
+                    }
+                }
+            },
+            Token::Keyword(k) if k == "LIMIT" && limit.is_none() => {
+                limit = Some(count(tokens, index + 1, "LIMIT")?);
+                index += 2;
+            },
+            Token::Keyword(k) if k == "OFFSET" && offset.is_none() => {
+                offset = Some(count(tokens, index + 1, "OFFSET")?);
+                index += 2;
+            },
+            t => return Err(gerr(&format!("Unexpected token {:?} in SEARCH, expected GROUP BY, ORDER BY, LIMIT or OFFSET", t))),
+        }
+    }
+    Ok((group_by, order_by, limit, offset.unwrap_or(0)))
+}
+
 fn debug_delete(tokens : &Vec<Token>) -> Result<AST,Error>{
     if let Some(t) = tokens.get(0){
         if let Token::Keyword(s) = t{
@@ -406,6 +821,9 @@ fn debug_delete(tokens : &Vec<Token>) -> Result<AST,Error>{
     }else{
         return Err(gerr("Missing tokens"))
     }
+    if let Some(Token::Keyword(s)) = tokens.get(1) && s == "INDEX"{
+        return Ok(AST::DeleteIndex(AstDeleteIndex { name: expect_string(tokens, 2, "index name")? }))
+    }
     let mut path = false;
     if let Some(t) = tokens.get(1){
         if let Token::Keyword(s) = t{
@@ -429,7 +847,7 @@ fn debug_delete(tokens : &Vec<Token>) -> Result<AST,Error>{
             return Err(gerr("Missing container name"))
         }
     }else{
-        if let Some(t) = tokens.get(3){
+        if let Some(t) = tokens.get(2){
             if let Token::Keyword(s) = t{
                 if s.to_lowercase() != "on".to_string(){
                     return Err(gerr("Invalid keyword, expected \"ON\"."))
@@ -441,9 +859,8 @@ fn debug_delete(tokens : &Vec<Token>) -> Result<AST,Error>{
             return Err(gerr("Missing tokens"))
         }
         let mut container : String = String::new();
-        let mut conditions: (Vec<(Token, Token, Token)>, Vec<(usize, char)>) =
-        (Vec::with_capacity(10), Vec::with_capacity(10));
-        if let Some(t) = tokens.get(4){
+        let mut conditions: PrimitiveQueryConditions = None;
+        if let Some(t) = tokens.get(3){
             if let Token::String(s) = t{
                 container = s.to_string();
             }else{
@@ -454,82 +871,67 @@ fn debug_delete(tokens : &Vec<Token>) -> Result<AST,Error>{
             return Err(gerr("Missing container name"))
         }
 
-        if let Some(tok) = tokens.get(5) {
+        if let Some(tok) = tokens.get(4) {
             if match tok {
                 Token::Keyword(a) if a.to_uppercase() == "WHERE" => false,
                 _ => true,
             } {
-                return Err(gerr(r#"Expected keyword "WHERE" at position 5"#));
+                return Err(gerr(r#"Expected keyword "WHERE" at position 4"#));
             }
 
-            if let Some(iterator_of_tokens) = tokens.get(6..) {
-                let mut bushes: Vec<Token> = Vec::new();
-
-                for i in iterator_of_tokens {
-                    if bushes.len() == 3 {
-                        conditions.0.push((
-                            get_from_bushes_with_safety(&bushes, 0)?,
-                            get_from_bushes_with_safety(&bushes, 1)?,
-                            get_from_bushes_with_safety(&bushes, 2)?,
-                        ));
-                        bushes.clear();
-
-                        conditions.1.push((
-                            conditions.0.len() - 1,
-                            match i {
-                                Token::Keyword(a) => match a.to_uppercase().as_str() {
-                                    "OR" => 'o',
-                                    "AND" => 'a',
-                                    _ => {
-                                        return Err(gerr(
-                                            "Expected logical operator 'AND' or 'OR' after a condition",
-                                        ))
-                                    }
-                                },
-                                _ => return Err(gerr("Expected logical operator after condition")),
-                            },
-                        ));
-                        continue;
-                    }
-
-                    match i {
-                        Token::String(_) => match bushes.len() {
-                            0 | 2 => bushes.push(i.clone()),
-                            _ => return Err(gerr("Unexpected string: operator might be missing")),
-                        },
-                        Token::Bool(_) | Token::Int(_) | Token::Float(_) => {
-                            if bushes.len() == 2 {
-                                bushes.push(i.clone())
-                            } else {
-                                return Err(gerr("Unexpected value: condition must follow 'column OP value' pattern"));
-                            }
-                        }
-                        Token::Operator(_) => {
-                            if bushes.len() == 1 {
-                                bushes.push(i.clone());
-                            } else {
-                                return Err(gerr("Unexpected operator: check condition structure"));
-                            }
-                        }
-                        _ => return Err(gerr("Unexpected token in WHERE clause")),
-                    }
-                }
-
-                if bushes.len() == 3 {
-                    conditions.0.push((
-                        get_from_bushes_with_safety(&bushes, 0)?,
-                        get_from_bushes_with_safety(&bushes, 1)?,
-                        get_from_bushes_with_safety(&bushes, 2)?,
-                    ));
-                }
-            }
+            conditions = parse_conditions(tokens.get(5..).unwrap_or_default())?;
         }
 
-        return Ok(AST::DeleteRow(crate::AstDeleteRow { container, conditions: Some(conditions) }))
+        return Ok(AST::DeleteRow(crate::AstDeleteRow { container, conditions }))
     }
     
 }
 
+fn expect_keyword(tokens : &[Token], index : usize, keyword : &str) -> Result<(), Error> {
+    match tokens.get(index) {
+        Some(Token::Keyword(k)) if k == keyword => Ok(()),
+        Some(t) => Err(gerr(&format!("Expected keyword '{}' at position {}, but found {:?}", keyword, index, t))),
+        None => Err(gerr(&format!("Missing keyword '{}' at position {}", keyword, index))),
+    }
+}
+
+fn expect_string(tokens : &[Token], index : usize, what : &str) -> Result<String, Error> {
+    match tokens.get(index) {
+        Some(Token::String(s)) => Ok(s.clone()),
+        Some(t) => Err(gerr(&format!("Expected the {} as a string at position {}, but found {:?}", what, index, t))),
+        None => Err(gerr(&format!("Missing the {} at position {}", what, index))),
+    }
+}
+
+// ALTER CONTAINER <container> ADD COLUMN <name> <type>
+// ALTER CONTAINER <container> DROP COLUMN <name>
+// ALTER CONTAINER <container> RENAME COLUMN <name> TO <new name>
+fn debug_alter(tokens : &Vec<Token>) -> Result<AST, Error> {
+    expect_keyword(tokens, 1, "CONTAINER")?;
+    let container = expect_string(tokens, 2, "container name")?;
+    expect_keyword(tokens, 4, "COLUMN")?;
+    let column = expect_string(tokens, 5, "column name")?;
+    let change = match tokens.get(3) {
+        Some(Token::Keyword(k)) => match k.as_str() {
+            "ADD" => {
+                let (col_val, nullable, unique) = parse_column_type(&expect_string(tokens, 6, "column type")?)?;
+                if unique {
+                    return Err(gerr("UNIQUE columns can only be declared in CREATE CONTAINER"));
+                }
+                AlterColumn::Add { name: column, col_val, nullable }
+            },
+            "DROP" => AlterColumn::Drop { name: column },
+            "RENAME" => {
+                expect_keyword(tokens, 6, "TO")?;
+                AlterColumn::Rename { name: column, new_name: expect_string(tokens, 7, "new column name")? }
+            },
+            _ => return Err(gerr("Invalid ALTER CONTAINER action, expected ADD, DROP or RENAME")),
+        },
+        _ => return Err(gerr("Missing the ALTER CONTAINER action, expected ADD, DROP or RENAME")),
+    };
+    Ok(AST::AlterContainer(AstAlterContainer { container, change }))
+}
+
 pub fn debug_tokens(tokens: &Vec<Token>) -> Result<AST, Error> {
     let first = tokens.first().ok_or_else(|| gerr("Token list is empty"))?;
     if let Token::Keyword(command) = first {
@@ -537,8 +939,9 @@ pub fn debug_tokens(tokens: &Vec<Token>) -> Result<AST, Error> {
             "CREATE" => debug_create_command(tokens),
             "EDIT" => debug_edit_command(tokens),
             "SEARCH" => debug_search(tokens),
-            "COMMIT"|"ROLLBACK" => debug_finishers_command(tokens),
+            "BEGIN"|"COMMIT"|"ROLLBACK"|"SAVEPOINT"|"RELEASE" => debug_finishers_command(tokens),
             "DELETE" => debug_delete(tokens),
+            "ALTER" => debug_alter(tokens),
             _ => Err(gerr("Invalid command keyword")),
         }
     } else {
@@ -551,6 +954,12 @@ fn debug_finishers_command(tokens : &Vec<Token>) -> Result<AST,Error> {
     if let Some(kw) = tokens.get(0){
         if let Token::Keyword(st) = kw {
             match st.to_uppercase().as_str(){
+                "BEGIN" => {
+                    if tokens.len() > 1{
+                        return Err(gerr("BEGIN takes no arguments"));
+                    }
+                    return Ok(AST::Begin)
+                },
                 "COMMIT" => {
                     let con : Option<String> = match tokens.get(1){
                         Some(ttt) => match ttt{
@@ -563,6 +972,21 @@ fn debug_finishers_command(tokens : &Vec<Token>) -> Result<AST,Error> {
                         container:con
                     }))
                 },
+                "SAVEPOINT" => {
+                    return Ok(AST::Savepoint(AstSavepoint{
+                        name:expect_string(tokens, 1, "savepoint name")?
+                    }))
+                },
+                "RELEASE" => {
+                    return Ok(AST::Release(AstSavepoint{
+                        name:expect_string(tokens, 1, "savepoint name")?
+                    }))
+                },
+                "ROLLBACK" if matches!(tokens.get(1), Some(Token::Keyword(k)) if k == "TO") => {
+                    return Ok(AST::RollbackTo(AstSavepoint{
+                        name:expect_string(tokens, 2, "savepoint name")?
+                    }))
+                },
                 "ROLLBACK" => {
                     let con : Option<String> = match tokens.get(1){
                         Some(ttt) => match ttt{
@@ -642,6 +1066,8 @@ fn argument_lexer(input: String) -> Result<Vec<Token>, Error> {
             result.push(Token::Bool(lowercase == "true"));
         } else if let Ok(int_val) = input.parse::<i64>() {
             result.push(Token::Int(int_val));
+        } else if let Some((value, scale)) = input.contains('.').then(|| parse_decimal(&input)).flatten() {
+            result.push(Token::Decimal(value, scale));
         } else if let Ok(float_val) = input.parse::<f64>() {
             result.push(Token::Float(float_val));
         } else {
@@ -666,10 +1092,14 @@ pub fn parse(input: String, arguments_input: Vec<String>) -> Result<AST, Error>
             Token::Bool(a) => arguments.push(Token::Bool(*a)),
             Token::Int(a) => arguments.push(Token::Int(*a)),
             Token::Float(a) => arguments.push(Token::Float(*a)),
+            Token::Decimal(v, s) => arguments.push(Token::Decimal(*v, *s)),
             Token::String(a) => arguments.push(Token::String(a.to_string())),
             Token::Keyword(s) => arguments.push(Token::String(s.to_string())),
             Token::Bytes(s) => arguments.push(Token::Bytes(s.to_owned())),
-            _ => return Err(gerr("Invalid argument type; expected Bool, Int, Float, Bytes or String")),
+            Token::Timestamp(t) => arguments.push(Token::Timestamp(*t)),
+            Token::Date(d) => arguments.push(Token::Date(*d)),
+            Token::Uuid(u) => arguments.push(Token::Uuid(*u)),
+            _ => return Err(gerr("Invalid argument type; expected Bool, Int, Float, Decimal, Timestamp, Date, Uuid, Bytes or String")),
         }
     }
 
@@ -1,12 +1,52 @@
-use std::{collections::{btree_map::Range, BTreeSet, HashMap}, fs::File, hash::{DefaultHasher, Hash, Hasher}, io::Error, ops::RangeInclusive, os::unix::fs::{FileExt, MetadataExt}, sync::Arc, usize, vec};
-use ahash::AHashSet;
+use std::{cmp::Ordering, collections::{btree_map::Range, BTreeSet, BinaryHeap, HashMap}, fs::File, hash::{DefaultHasher, Hash, Hasher}, io::Error, ops::RangeInclusive, os::unix::fs::{FileExt, MetadataExt}, sync::Arc, usize, vec};
 use tokio::sync::Mutex;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{alba_types::AlbaTypes, container::Container, database::generate_secure_code, gerr, lexer_functions::Token, logerr, loginfo, query_conditions::QueryConditions, row::Row};
+use crate::{alba_types::{compare_decimals, decimal_to_float, AlbaTypes, DECIMAL_MAX_PRECISION}, arithmetic::{apply, ArithmeticOperator, Expression}, container::{Container, SessionId, StagedRows}, indexing::GetIndex, AggregateFunction, Projection, database::generate_secure_code, gerr, lexer_functions::Token, logerr, loginfo, query_conditions::QueryConditions, row::Row};
+
+/// WHERE clause as written, the values are only checked against the column types by `QueryConditions`.
+/// AND binds tighter than OR, and NOT tighter than both.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionExpression {
+    /// `column OP value`
+    Condition(Token, Token, Token),
+    /// `column IN (value, ...)`
+    In(Token, Vec<Token>),
+    /// `column BETWEEN low AND high`
+    Between(Token, Token, Token),
+    /// `expression OP expression` where either side is computed from the row
+    Compare(Expression, Token, Expression),
+    Not(Box<ConditionExpression>),
+    And(Vec<ConditionExpression>),
+    Or(Vec<ConditionExpression>),
+}
+
+impl ConditionExpression {
+    /// Calls `f` on the column name of every condition.
+    pub fn columns_mut(&mut self, f : &mut impl FnMut(&mut String) -> Result<(), Error>) -> Result<(), Error> {
+        match self {
+            ConditionExpression::Condition(column, _, _) | ConditionExpression::In(column, _) | ConditionExpression::Between(column, _, _) => match column {
+                Token::String(name) => f(name),
+                _ => Ok(()),
+            },
+            ConditionExpression::Compare(left, _, right) => {
+                left.columns_mut(f)?;
+                right.columns_mut(f)
+            }
+            ConditionExpression::Not(inner) => inner.columns_mut(f),
+            ConditionExpression::And(items) | ConditionExpression::Or(items) => {
+                for item in items.iter_mut() {
+                    item.columns_mut(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
 
-pub type PrimitiveQueryConditions = (Vec<(Token, Token, Token)>, Vec<(usize, char)>);
+/// None when there is no WHERE clause.
+pub type PrimitiveQueryConditions = Option<ConditionExpression>;
 
 type Rows = (Vec<String>, Vec<Vec<AlbaTypes>>);
 
@@ -69,7 +109,10 @@ impl Query {
         if foreign.column_types != self.column_types {
             return;
         }
-        self.rows.0.extend_from_slice(&foreign.rows.0);
+        // the rows of both queries have the same columns, the names of the first one are kept
+        if self.rows.0.is_empty() {
+            self.rows.0 = foreign.rows.0;
+        }
         self.rows.1.extend_from_slice(&foreign.rows.1);
         
         self.trim();
@@ -77,57 +120,443 @@ impl Query {
 
 }
 
+/// ORDER BY, LIMIT and OFFSET of a SEARCH.
+#[derive(Debug, Clone, Default)]
+pub struct ResultOrder {
+    pub columns : Vec<(String,bool)>, // (column, descending)
+    pub limit : Option<usize>,
+    pub offset : usize,
+}
+
+impl ResultOrder {
+    /// Sorts the rows of a finished query and cuts the requested page out of them.
+    pub fn apply(&self, query : &mut Query) -> Result<(), Error> {
+        let mut collector = RowCollector::new(self, &query.rows.0)?;
+        for row in std::mem::take(&mut query.rows.1) {
+            collector.push(row);
+        }
+        query.rows.1 = collector.finish().into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        Ok(())
+    }
+}
+
+/// Orders NULL after every value, values of different types by their index key.
+pub fn compare_values(a : &AlbaTypes, b : &AlbaTypes) -> Ordering {
+    match (a, b) {
+        (AlbaTypes::NONE, AlbaTypes::NONE) => Ordering::Equal,
+        (AlbaTypes::NONE, _) => Ordering::Greater,
+        (_, AlbaTypes::NONE) => Ordering::Less,
+        (AlbaTypes::Int(a), AlbaTypes::Int(b)) => a.cmp(b),
+        (AlbaTypes::Bigint(a), AlbaTypes::Bigint(b)) => a.cmp(b),
+        (AlbaTypes::Float(a), AlbaTypes::Float(b)) => a.total_cmp(b),
+        (AlbaTypes::Bool(a), AlbaTypes::Bool(b)) => a.cmp(b),
+        (AlbaTypes::Char(a), AlbaTypes::Char(b)) => a.cmp(b),
+        (AlbaTypes::Timestamp(_) | AlbaTypes::Date(_), AlbaTypes::Timestamp(_) | AlbaTypes::Date(_)) => a.as_timestamp().cmp(&b.as_timestamp()),
+        (AlbaTypes::Uuid(a), AlbaTypes::Uuid(b)) => a.cmp(b),
+        (AlbaTypes::Json(a), AlbaTypes::Json(b)) => a.cmp(b),
+        (AlbaTypes::Array(a, _), AlbaTypes::Array(b, _)) => a.iter().zip(b).map(|(x, y)| compare_values(x, y)).find(|o| o.is_ne()).unwrap_or(a.len().cmp(&b.len())),
+        (AlbaTypes::Decimal(a, _, s), AlbaTypes::Decimal(b, _, t)) => compare_decimals((*a, *s), (*b, *t)),
+        // numbers of different types compare by value, exactly unless one of them is a float
+        (AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Decimal(..), AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Decimal(..)) => compare_decimals(a.as_decimal().unwrap_or_default(), b.as_decimal().unwrap_or_default()),
+        (AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Float(_) | AlbaTypes::Decimal(..), AlbaTypes::Int(_) | AlbaTypes::Bigint(_) | AlbaTypes::Float(_) | AlbaTypes::Decimal(..)) => {
+            let (x, y) = (a.as_float().unwrap_or_default(), b.as_float().unwrap_or_default());
+            x.partial_cmp(&y).unwrap_or(x.total_cmp(&y))
+        },
+        (AlbaTypes::Text(a) | AlbaTypes::NanoString(a) | AlbaTypes::SmallString(a) | AlbaTypes::MediumString(a) | AlbaTypes::BigString(a) | AlbaTypes::LargeString(a),
+         AlbaTypes::Text(b) | AlbaTypes::NanoString(b) | AlbaTypes::SmallString(b) | AlbaTypes::MediumString(b) | AlbaTypes::BigString(b) | AlbaTypes::LargeString(b)) => a.trim_end().cmp(b.trim_end()),
+        (AlbaTypes::NanoBytes(a) | AlbaTypes::SmallBytes(a) | AlbaTypes::MediumBytes(a) | AlbaTypes::BigSBytes(a) | AlbaTypes::LargeBytes(a),
+         AlbaTypes::NanoBytes(b) | AlbaTypes::SmallBytes(b) | AlbaTypes::MediumBytes(b) | AlbaTypes::BigSBytes(b) | AlbaTypes::LargeBytes(b)) => a.cmp(b),
+        (a, b) => a.get_index().cmp(&b.get_index()),
+    }
+}
+
+struct RankedRow {
+    key : Vec<(AlbaTypes,bool)>,
+    position : u64,
+    row : Vec<AlbaTypes>,
+}
+
+impl Ord for RankedRow {
+    fn cmp(&self, other : &Self) -> Ordering {
+        for ((a, descending), (b, _)) in self.key.iter().zip(other.key.iter()) {
+            let ordering = compare_values(a, b);
+            if ordering != Ordering::Equal {
+                return if *descending { ordering.reverse() } else { ordering }
+            }
+        }
+        // rows that compare equal keep the order they were found in
+        self.position.cmp(&other.position)
+    }
+}
+
+impl PartialOrd for RankedRow {
+    fn partial_cmp(&self, other : &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedRow {
+    fn eq(&self, other : &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedRow {}
+
+/// Collects the rows of a search in the requested order. With a LIMIT only the best
+/// `offset + limit` rows are kept in a bounded max-heap, the worst one is dropped as soon as a better one shows up.
+pub struct RowCollector {
+    columns : Vec<(usize,bool)>,
+    keep : Option<usize>,
+    heap : BinaryHeap<RankedRow>,
+    found : u64,
+}
+
+impl RowCollector {
+    pub fn new(order : &ResultOrder, column_names : &[String]) -> Result<Self, Error> {
+        let mut columns = Vec::with_capacity(order.columns.len());
+        for (name, descending) in order.columns.iter() {
+            match column_names.iter().position(|c| c == name) {
+                Some(i) => columns.push((i, *descending)),
+                None => return Err(gerr(&format!("Failed to sort the result, there is no column named {}", name)))
+            }
+        }
+        Ok(RowCollector { columns, keep: order.limit.map(|l| l.saturating_add(order.offset)), heap: BinaryHeap::new(), found: 0 })
+    }
+
+    pub fn push(&mut self, row : Vec<AlbaTypes>) {
+        let key = self.columns.iter().map(|(i, descending)| (row.get(*i).cloned().unwrap_or(AlbaTypes::NONE), *descending)).collect();
+        self.heap.push(RankedRow { key, position: self.found, row });
+        self.found += 1;
+        if let Some(keep) = self.keep && self.heap.len() > keep {
+            self.heap.pop();
+        }
+    }
+
+    /// Without ORDER BY the first rows found are the result, so the search can stop once it has enough of them.
+    pub fn is_full(&self) -> bool {
+        self.columns.is_empty() && self.keep.is_some_and(|keep| self.heap.len() >= keep)
+    }
+
+    pub fn finish(self) -> Vec<Vec<AlbaTypes>> {
+        self.heap.into_sorted_vec().into_iter().map(|r| r.row).collect()
+    }
+}
+
+impl AggregateFunction {
+    fn name(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "COUNT",
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Avg => "AVG",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+        }
+    }
+
+    /// Type of the value the function yields for a column of the given type.
+    fn result_type(&self, column : &AlbaTypes) -> Result<AlbaTypes, Error> {
+        match (self, column) {
+            (AggregateFunction::Count, _) => Ok(AlbaTypes::Bigint(0)),
+            (AggregateFunction::Sum, AlbaTypes::Int(_) | AlbaTypes::Bigint(_)) => Ok(AlbaTypes::Bigint(0)),
+            (AggregateFunction::Sum | AggregateFunction::Avg, AlbaTypes::Float(_)) | (AggregateFunction::Avg, AlbaTypes::Int(_) | AlbaTypes::Bigint(_)) => Ok(AlbaTypes::Float(0.0)),
+            (AggregateFunction::Sum, AlbaTypes::Decimal(_, _, s)) => Ok(AlbaTypes::Decimal(0, DECIMAL_MAX_PRECISION, *s)),
+            (AggregateFunction::Avg, AlbaTypes::Decimal(_, _, s)) => Ok(AlbaTypes::Decimal(0, DECIMAL_MAX_PRECISION, (*s + 6).min(DECIMAL_MAX_PRECISION))),
+            (AggregateFunction::Sum | AggregateFunction::Avg, _) => Err(gerr(&format!("{} can only be used on INT, BIGINT, FLOAT and DECIMAL columns", self.name()))),
+            (AggregateFunction::Min | AggregateFunction::Max, c) => Ok(c.clone()),
+        }
+    }
+
+    /// Computes the function over the values of one group, NULL values are ignored.
+    fn compute<'a>(&self, values : impl Iterator<Item = &'a AlbaTypes>) -> Result<AlbaTypes, Error> {
+        let mut values = values.filter(|v| **v != AlbaTypes::NONE).peekable();
+        if values.peek().is_none() && *self != AggregateFunction::Count {
+            return Ok(AlbaTypes::NONE)
+        }
+        Ok(match self {
+            AggregateFunction::Count => AlbaTypes::Bigint(values.count() as i64),
+            AggregateFunction::Sum if matches!(values.peek(), Some(AlbaTypes::Float(_))) => AlbaTypes::Float(values.map(as_float).sum()),
+            // decimals are summed exactly, AVG divides the exact sum
+            AggregateFunction::Sum | AggregateFunction::Avg if matches!(values.peek(), Some(AlbaTypes::Decimal(..))) => {
+                let mut sum = AlbaTypes::Bigint(0);
+                let mut count : i64 = 0;
+                for v in values {
+                    sum = apply(ArithmeticOperator::Add, sum, v.clone())?;
+                    count += 1;
+                }
+                if *self == AggregateFunction::Avg { apply(ArithmeticOperator::Divide, sum, AlbaTypes::Bigint(count))? } else { sum }
+            },
+            AggregateFunction::Sum => {
+                let mut sum : i64 = 0;
+                for v in values {
+                    let v = match v { AlbaTypes::Int(i) => *i as i64, AlbaTypes::Bigint(i) => *i, _ => 0 };
+                    sum = sum.checked_add(v).ok_or_else(|| gerr("SUM overflowed the range of BIGINT"))?;
+                }
+                AlbaTypes::Bigint(sum)
+            },
+            AggregateFunction::Avg => {
+                let (sum, count) = values.fold((0.0, 0), |(s, c), v| (s + as_float(v), c + 1));
+                AlbaTypes::Float(sum / count as f64)
+            },
+            AggregateFunction::Min => values.min_by(|a, b| compare_values(a, b)).cloned().unwrap_or(AlbaTypes::NONE),
+            AggregateFunction::Max => values.max_by(|a, b| compare_values(a, b)).cloned().unwrap_or(AlbaTypes::NONE),
+        })
+    }
+}
+
+fn as_float(value : &AlbaTypes) -> f64 {
+    match value {
+        AlbaTypes::Int(i) => *i as f64,
+        AlbaTypes::Bigint(i) => *i as f64,
+        AlbaTypes::Float(f) => *f,
+        AlbaTypes::Decimal(v, _, s) => decimal_to_float(*v, *s),
+        _ => 0.0,
+    }
+}
+
+/// Groups the rows of a query by the GROUP BY columns and yields one row per group with the
+/// values of the projection. Aggregates are named after their call, like `COUNT(*)` or `SUM(age)`.
+pub fn aggregate(query : &Query, projection : &[Projection], group_by : &[String]) -> Result<Query, Error> {
+    let column = |name : &String| query.rows.0.iter().position(|c| c == name)
+        .ok_or_else(|| gerr(&format!("There is no column named {}", name)));
+    let column_type = |i : usize| query.column_types.get(i).cloned().unwrap_or(AlbaTypes::NONE);
+    let groups = group_by.iter().map(column).collect::<Result<Vec<usize>, Error>>()?;
+
+    let mut outputs : Vec<(Option<AggregateFunction>, Option<usize>)> = Vec::with_capacity(projection.len());
+    let mut names = Vec::with_capacity(projection.len());
+    let mut types = Vec::with_capacity(projection.len());
+    for p in projection {
+        match p {
+            Projection::All => return Err(gerr("'*' can't be used together with aggregate functions or GROUP BY")),
+            Projection::Column(name) => {
+                let i = column(name)?;
+                if !groups.contains(&i) {
+                    return Err(gerr(&format!("The column {} must be listed in GROUP BY or used inside an aggregate function", name)))
+                }
+                outputs.push((None, Some(i)));
+                names.push(name.clone());
+                types.push(column_type(i));
+            },
+            Projection::Aggregate(function, None) => {
+                outputs.push((Some(*function), None));
+                names.push(format!("{}(*)", function.name()));
+                types.push(AlbaTypes::Bigint(0));
+            },
+            Projection::Aggregate(function, Some(name)) => {
+                let i = column(name)?;
+                outputs.push((Some(*function), Some(i)));
+                names.push(format!("{}({})", function.name(), name));
+                types.push(function.result_type(&column_type(i))?);
+            },
+        }
+    }
+
+    // sorting by the group columns puts the rows of a group next to each other
+    let rows = &query.rows.1;
+    let mut order : Vec<usize> = (0..rows.len()).collect();
+    let same_group = |a : &Vec<AlbaTypes>, b : &Vec<AlbaTypes>| groups.iter().map(|g| compare_values(&a[*g], &b[*g])).find(|o| *o != Ordering::Equal).unwrap_or(Ordering::Equal);
+    order.sort_by(|a, b| same_group(&rows[*a], &rows[*b]));
+
+    let mut result = Query::new(types);
+    result.rows.0 = names.clone();
+    result.column_names = names;
+    let mut start = 0;
+    // without GROUP BY every row belongs to a single group, which exists even when there are no rows
+    while start < order.len() || (start == 0 && groups.is_empty()) {
+        let mut end = start + 1;
+        while end < order.len() && same_group(&rows[order[start]], &rows[order[end]]) == Ordering::Equal {
+            end += 1;
+        }
+        let end = end.min(order.len());
+        let members = &order[start..end];
+        let mut row = Vec::with_capacity(outputs.len());
+        for (function, column) in outputs.iter() {
+            row.push(match (function, column) {
+                (None, Some(i)) => rows[members[0]][*i].clone(),
+                (Some(f), Some(i)) => f.compute(members.iter().map(|m| &rows[*m][*i]))?,
+                (Some(_), None) => AlbaTypes::Bigint(members.len() as i64),
+                (None, None) => AlbaTypes::NONE,
+            });
+        }
+        result.rows.1.push(row);
+        start = end.max(start + 1);
+    }
+    Ok(result)
+}
+
+/// Resolves a column name against the `container.column` names of a join, the container
+/// may be left out when only one of the joined containers has a column with that name.
+pub fn qualify(name : &str, names : &[String]) -> Result<String, Error> {
+    if names.iter().any(|n| n == name) || name.contains('.') || name == "*" {
+        return Ok(name.to_string())
+    }
+    let suffix = format!(".{}", name);
+    let mut candidates = names.iter().filter(|n| n.ends_with(&suffix));
+    match (candidates.next(), candidates.next()) {
+        (Some(n), None) => Ok(n.clone()),
+        (Some(_), Some(_)) => Err(gerr(&format!("The column {} is ambiguous, name it as container.column", name))),
+        _ => Ok(name.to_string()),
+    }
+}
+
+/// Keeps the columns listed in the projection of a SEARCH, in the order they were listed.
+pub fn project(query : &mut Query, projection : &[Projection]) -> Result<(), Error> {
+    let mut columns = Vec::with_capacity(projection.len());
+    for p in projection {
+        match p {
+            Projection::All => columns.extend(0..query.rows.0.len()),
+            Projection::Column(name) => match query.rows.0.iter().position(|c| c == name) {
+                Some(i) => columns.push(i),
+                None => return Err(gerr(&format!("There is no column named {}", name)))
+            },
+            Projection::Aggregate(..) => return Err(gerr("Aggregate functions can't be projected without aggregating the rows")),
+        }
+    }
+    for row in query.rows.1.iter_mut() {
+        *row = columns.iter().map(|i| row[*i].clone()).collect::<Vec<_>>();
+    }
+    let names : Vec<String> = columns.iter().map(|i| query.rows.0[*i].clone()).collect();
+    query.column_types = columns.iter().map(|i| query.column_types.get(*i).cloned().unwrap_or(AlbaTypes::NONE)).collect();
+    query.rows.0 = names.clone();
+    query.column_names = names;
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct SearchArguments {
     pub element_size : usize,
     pub header_offset : usize,
     pub file : Arc<Mutex<File>>,
     pub container_values : Vec<(String,AlbaTypes)>,
-    pub conditions : QueryConditions
-
+    pub conditions : QueryConditions,
+    pub session : SessionId,
+    pub snapshot : u64,
 }
 const CHUNK_MATRIX : usize = 4096 * 10;
 
-pub async fn search(container: Arc<Mutex<Container>>, args: SearchArguments) -> Result<Query, Error> {
-    let file = args.file.lock().await;
-    let lck = container.lock().await;
-    let graveyard = lck.graveyard.lock().await;
-    let size = file.metadata().unwrap().len() as usize;
-    let columns = lck.column_names();
-    let mut query = Query::new(args.container_values.iter().map(|f| f.1.clone()).collect());
-    query.rows.0 = lck.column_names();
+/// Changes the searching session staged on a container by row index, true when the row is staged for delete.
+/// Every read overlays them on the file, so a client sees its own inserts, edits and deletes before COMMIT
+/// and nothing of what other sessions haven't committed.
+type PendingRows = StagedRows;
+
+async fn pending_rows(container : &Container, session : &SessionId) -> PendingRows {
+    container.staged(session).await
+}
+
+fn pending_row(headers : &[(String, AlbaTypes)], content : &[AlbaTypes]) -> Row {
+    Row { data: headers.iter().map(|h| h.0.clone()).zip(content.iter().cloned()).collect() }
+}
 
-    if size == args.header_offset{
-        return Ok(query)
+/// Row of a stored row, checking its values against the column types.
+fn checked_row(headers : &[(String, AlbaTypes)], row_content : &[AlbaTypes]) -> Result<Row, Error> {
+    let mut data: HashMap<String, AlbaTypes> = HashMap::new();
+    for (index, value) in headers.iter().enumerate() {
+        let column_value = match row_content.get(index) {
+            Some(a) => {
+                let cv = a.to_owned();
+                if cv != AlbaTypes::NONE && std::mem::discriminant(&cv) != std::mem::discriminant(&value.1) {
+                    return Err(gerr("Invalid alba type row order, unmatching stuff"));
+                }
+                cv
+            }
+            None => {
+                return Err(gerr("Invalid alba type row order, missing stuff"));
+            }
+        };
+        data.insert(value.0.clone(), column_value);
     }
+    Ok(Row { data })
+}
+
+/// Row at `index` as the snapshot of the search sees it, from the bytes read from its slot, None for an empty slot.
+/// The versions are looked up after the read, so a commit that changed the slot meanwhile already left there the row it replaced.
+async fn visible_row(container : &Container, snapshot : u64, index : u64, bytes : &[u8]) -> Result<Option<Vec<AlbaTypes>>, Error> {
+    let stored = if bytes.iter().all(|b| *b == 0) {
+        Ok(None)
+    } else {
+        container.deserialize_row(bytes).await.map(Some)
+    };
+    match container.version_at(index, snapshot).await {
+        Some(version) => Ok(version),
+        None => stored,
+    }
+}
+
+/// Offsets to read for an index lookup: the ones found in the index, plus every staged row, since a staged insert
+/// or edit isn't in the index yet, and every row changed after the snapshot, since the index only knows its last version.
+/// Those rows are checked against the conditions like the others.
+async fn overlay_offsets(container : &Container, address : &BTreeSet<u64>, pending : &PendingRows, snapshot : u64) -> BTreeSet<u64> {
+    let (header_offset, element_size) = (container.headers_offset, container.element_size as u64);
+    let mut offsets = address.clone();
+    offsets.extend(pending.iter().filter(|(_, (deleted, _))| !deleted).map(|(index, _)| header_offset + index * element_size));
+    offsets.extend(container.versioned_since(snapshot).await.into_iter().map(|index| header_offset + index * element_size));
+    offsets
+}
+
+// The searches below lock the file and the container per chunk or per row, not for the whole read, and a SEARCH
+// sent by a client runs without the lock of the database (see `Database::begin_read`). Commits go on in between
+// and keep the rows they replace as versions, which the snapshot of the search reads.
+
+pub async fn search(container: Arc<Mutex<Container>>, args: SearchArguments, order: &ResultOrder) -> Result<Query, Error> {
+    let (columns, pending) = {
+        let lck = container.lock().await;
+        (lck.column_names(), pending_rows(&lck, &args.session).await)
+    };
+    let size = args.file.lock().await.metadata()?.len() as usize;
+    let mut query = Query::new(args.container_values.iter().map(|f| f.1.clone()).collect());
+    query.rows.0 = columns.clone();
+    let mut collector = RowCollector::new(order, &columns)?;
+
     loginfo!("size {:?}",size);
     loginfo!("elementsize {:?}",args.element_size);
     loginfo!("headersize {:?}",args.header_offset);
-    let to_read = (size-args.header_offset)/args.element_size;
+    let to_read = size.saturating_sub(args.header_offset)/args.element_size;
     loginfo!("toread: {}",to_read);
 
-    let r = to_read;
+    // staged inserts may go past the end of the file
+    let r = pending.keys().map(|k| *k as usize + 1).max().unwrap_or(0).max(to_read);
     loginfo!("r: {:?}",r);
-    for i in 0..r{
-        let i = (i * args.element_size) + args.header_offset;
-        if i + args.element_size > size {
-            break;
+    let rows_per_iteration = std::cmp::max(1, CHUNK_MATRIX / args.element_size);
+    let mut readen_rows = 0;
+    'scan: while readen_rows < r {
+        let chunk = rows_per_iteration.min(r - readen_rows);
+        let stored = to_read.saturating_sub(readen_rows).min(chunk);
+        let mut buffer = vec![0u8; stored * args.element_size];
+        if stored > 0 {
+            args.file.lock().await.read_exact_at(&mut buffer, (args.header_offset + readen_rows * args.element_size) as u64)?;
         }
-        if graveyard.get(&(i as u64)).is_none(){
-            let mut b = vec![0u8;args.element_size];
-            loginfo!("i:{}",i);
-            if file.read_exact_at(&mut b, i as u64).is_err(){break;};
-            let r = lck.deserialize_row(&mut b).await.unwrap();
+        let lck = container.lock().await;
+        for i in 0..chunk {
+            let row_index = readen_rows + i;
+            let r = match pending.get(&(row_index as u64)){
+                Some((true, _)) => continue,
+                Some((false, staged)) => staged.clone(),
+                None if i < stored => {
+                    match visible_row(&lck, args.snapshot, row_index as u64, &buffer[(i * args.element_size)..((i + 1) * args.element_size)]).await? {
+                        Some(row) => row,
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
             let mut h : HashMap<String,AlbaTypes> = HashMap::new();
             for i in r.iter().zip(columns.iter().cloned()){
                 h.insert(i.1,i.0.to_owned());
             }
             let row = Row{data:h};
-            if args.conditions.row_match(&row).unwrap(){
-                query.rows.1.push(r);
+            if args.conditions.row_match(&row)?{
+                collector.push(r);
+                if collector.is_full(){
+                    break 'scan;
+                }
             }
         }
+        readen_rows += chunk;
     }
+    query.rows.1 = collector.finish();
 
 
     Ok(query)
@@ -139,16 +568,13 @@ pub async fn search_direct(container: Arc<Mutex<Container>>, args: SearchArgumen
     
 
     
-    let file = args.file.lock().await;
-    
-
-    let file_size = file.metadata().unwrap().size() as usize;
-    let total_rows = (file_size - header_offset) / element_size;
+    let file_size = args.file.lock().await.metadata()?.size() as usize;
+    let total_rows = file_size.saturating_sub(header_offset) / element_size;
     let rows_per_iteration = std::cmp::max(1, CHUNK_MATRIX / element_size).min(total_rows);
     
-    let container_book = container.lock().await;
-    let container_headers = {
-        container_book.headers.clone()
+    let (container_headers, pending) = {
+        let container_book = container.lock().await;
+        (container_book.headers.clone(), pending_rows(&container_book, &args.session).await)
     };
     
 
@@ -157,55 +583,39 @@ pub async fn search_direct(container: Arc<Mutex<Container>>, args: SearchArgumen
     while readen_rows < total_rows {
         let to_read = rows_per_iteration.min(total_rows - readen_rows);
         let read_size = to_read * element_size;
-        let offset = readen_rows as u64;
-        
-
-        if offset > file.metadata()?.size() {
-            logerr!("WARNING: Bad offset | offset: {} size: {} index: {}", offset, file.metadata()?.size(), readen_rows);
-            readen_rows += to_read;
-            continue;
-        }
+        let offset = (header_offset + readen_rows * element_size) as u64;
 
         let mut buffer = vec![0u8; read_size];
-        file.read_exact_at(&mut buffer, offset).unwrap();
+        args.file.lock().await.read_exact_at(&mut buffer, offset)?;
         
-
-
+        let container_book = container.lock().await;
         for i in 0..to_read {
             let buff = &buffer[(i * element_size)..((i + 1) * element_size)];
-            let row_address = (readen_rows + i) as u64;
-            
-            let row = match container_book.deserialize_row(buff).await {
-                Ok(row_content) => {
-                    let mut data: HashMap<String, AlbaTypes> = HashMap::new();
-                    for (index, value) in container_headers.iter().enumerate() {
-                        let column_value = match row_content.get(index) {
-                            Some(a) => {
-                                let cv = a.to_owned();
-                                if std::mem::discriminant(&cv) != std::mem::discriminant(&value.1) {
-                                    return Err(gerr("Invalid alba type row order, unmatching stuff"));
-                                }
-                                cv
-                            }
-                            None => {
-                                return Err(gerr("Invalid alba type row order, missing stuff"));
-                            }
-                        };
-                        data.insert(value.0.clone(), column_value);
+            let row_address = offset + (i * element_size) as u64;
+            match pending.get(&((readen_rows + i) as u64)) {
+                Some((true, _)) => continue,
+                Some((false, staged)) => {
+                    if args.conditions.row_match(&pending_row(&container_headers, staged))? {
+                        result.push((staged.clone(), row_address));
                     }
-                    Row { data }
+                    continue;
                 }
+                None => {}
+            }
+            
+            let row_content = match visible_row(&container_book, args.snapshot, (readen_rows + i) as u64, buff).await {
+                Ok(Some(row_content)) => row_content,
+                Ok(None) => continue,
                 Err(e) => {
                     logerr!("Error deserializing row {}: {}", row_address, e);
                     return Err(e);
                 }
             };
+            let row = checked_row(&container_headers, &row_content)?;
             
 
-            if args.conditions.row_match(&row).unwrap() {
-                result.push((row.data.values().map(|f| f.to_owned()).collect(), row_address));
-                
-            } else {
+            if args.conditions.row_match(&row)? {
+                result.push((row_content, row_address));
                 
             }
         }
@@ -213,67 +623,62 @@ pub async fn search_direct(container: Arc<Mutex<Container>>, args: SearchArgumen
         
     }
 
+    let mut staged_inserts: Vec<(&u64, &Vec<AlbaTypes>)> = pending.iter()
+        .filter(|(index, (deleted, _))| !deleted && **index >= total_rows as u64)
+        .map(|(index, (_, row))| (index, row))
+        .collect();
+    staged_inserts.sort_by_key(|(index, _)| **index);
+    for (index, staged) in staged_inserts {
+        if args.conditions.row_match(&pending_row(&container_headers, staged))? {
+            result.push((staged.clone(), header_offset as u64 + index * element_size as u64));
+        }
+    }
     
     Ok(result)
 }
 
 pub async fn indexed_search_direct(container: Arc<Mutex<Container>>, args: SearchArguments, address: &BTreeSet<u64>) -> Result<Vec<(Vec<AlbaTypes>, u64)>, Error> {
     let element_size = args.element_size;
-    let container = container.lock().await;
-    let file = container.file.lock().await;
-    let file_size = file.metadata()?.size();
-    let mut runned : AHashSet<u64> = AHashSet::new();
+    let (headers, pending, offsets) = {
+        let container = container.lock().await;
+        let pending = pending_rows(&container, &args.session).await;
+        let offsets = overlay_offsets(&container, address, &pending, args.snapshot).await;
+        (container.headers.clone(), pending, offsets)
+    };
+    let file_size = args.file.lock().await.metadata()?.size();
     let mut result: Vec<(Vec<AlbaTypes>, u64)> = Vec::new();
-    for (idx, &row_address) in address.iter().enumerate() {
+    for &row_address in offsets.iter() {
         loginfo!("row_address: {}",row_address);
         let mut buffer = vec![0u8; element_size];
-        let offset = row_address as u64;
-        if offset > file_size {
-            logerr!("WARNING: Bad offset | offset: {} size: {} index: {}", offset, file_size, row_address);
-            continue;
+        let offset = row_address;
+        let row_index = (offset - args.header_offset as u64) / element_size as u64;
+        match pending.get(&row_index) {
+            Some((true, _)) => continue,
+            Some((false, staged)) => {
+                if args.conditions.row_match(&pending_row(&headers, staged))? {
+                    result.push((staged.clone(), row_address));
+                }
+                continue;
+            }
+            None => {}
         }
-        if runned.get(&offset).is_some(){
+        if offset + element_size as u64 > file_size {
+            logerr!("WARNING: Bad offset | offset: {} size: {} index: {}", offset, file_size, row_address);
             continue;
         }
-        runned.insert(offset);
-        file.read_exact_at(&mut buffer, offset).unwrap();
-        let row_content = match container.deserialize_row(&buffer).await {
-            Ok(row_content) => {
-                let mut data: HashMap<String, AlbaTypes> = HashMap::new();
-                for (index, value) in container.headers.iter().enumerate() {
-                    let column_value = match row_content.get(index) {
-                        Some(a) => {
-                            let cv = a.to_owned();
-                            if std::mem::discriminant(&cv) != std::mem::discriminant(&value.1) {
-                                return Err(gerr("Invalid alba type row order, unmatching stuff"));
-                            }
-                            cv
-                        }
-                        None => {
-                            return Err(gerr("Invalid alba type row order, missing stuff"));
-                        }
-                    };
-                    data.insert(value.0.clone(), column_value);
-                }
-                let row = Row { data };
-                
-
-                if args.conditions.row_match(&row).unwrap() {
-                    
-                    Some(row_content)
-                } else {
-                    
-                    None
-                }
-            }
+        args.file.lock().await.read_exact_at(&mut buffer, offset)?;
+        let container = container.lock().await;
+        let row_content = match visible_row(&container, args.snapshot, row_index, &buffer).await {
+            Ok(Some(row_content)) => row_content,
+            Ok(None) => continue,
             Err(e) => {
                 logerr!("Error deserializing row at offset {}: {}", offset, e);
                 return Err(e);
             }
         };
-
-        if let Some(content) = row_content {
-            result.push((content, row_address));
+        drop(container);
+        if args.conditions.row_match(&checked_row(&headers, &row_content)?)? {
+            result.push((row_content, row_address));
             
         }
     }
@@ -284,83 +689,70 @@ pub async fn indexed_search_direct(container: Arc<Mutex<Container>>, args: Searc
 
 
 
-pub async fn indexed_search(container: Arc<Mutex<Container>>, args: SearchArguments, address: &BTreeSet<u64>) -> Result<Query, Error> {
+pub async fn indexed_search(container: Arc<Mutex<Container>>, args: SearchArguments, address: &BTreeSet<u64>, order: &ResultOrder) -> Result<Query, Error> {
     let element_size = args.element_size;
     
     
-    let container = container.lock().await;
-    
+    let (headers, pending, offsets) = {
+        let container = container.lock().await;
+        let pending = pending_rows(&container, &args.session).await;
+        let offsets = overlay_offsets(&container, address, &pending, args.snapshot).await;
+        (container.headers.clone(), pending, offsets)
+    };
+    let columns : Vec<String> = headers.iter().map(|h| h.0.clone()).collect();
+    let mut collector = RowCollector::new(order, &columns)?;
     
-    let file = args.file.lock().await;
     
-    let file_size = file.metadata()?.size();
-    let mut runned : AHashSet<u64> = AHashSet::new();
-    let mut rows: Vec<(Row, u64)> = Vec::new();
-    for i in address.iter() {
+    let file_size = args.file.lock().await.metadata()?.size();
+    for i in offsets.iter() {
         loginfo!("row_address: {}",i);
         let mut buffer = vec![0u8; element_size];
         let offset = *i;
-        if offset > file_size {
+        let row_index = (offset - args.header_offset as u64) / element_size as u64;
+        match pending.get(&row_index) {
+            Some((true, _)) => continue,
+            Some((false, staged)) => {
+                if args.conditions.row_match(&pending_row(&headers, staged))? {
+                    collector.push(staged.clone());
+                    if collector.is_full(){
+                        break;
+                    }
+                }
+                continue;
+            }
+            None => {}
+        }
+        if offset + element_size as u64 > file_size {
             logerr!("WARNING: Bad offset | offset: {} size: {} index: {}", offset, file_size, *i);
             continue;
         }
-        if runned.get(&offset).is_some(){continue;}
         
-        file.read_exact_at(&mut buffer, offset).unwrap();
-        runned.insert(offset);
-        let row = match container.deserialize_row(&buffer).await {
-            Ok(row_content) => {
-                let mut data: HashMap<String, AlbaTypes> = HashMap::new();
-                for (index, value) in container.headers.iter().enumerate() {
-                    let column_value = match row_content.get(index) {
-                        Some(a) => {
-                            let cv = a.to_owned();
-                            if std::mem::discriminant(&cv) != std::mem::discriminant(&value.1) {
-                                return Err(gerr("Invalid alba type row order, unmatching stuff"));
-                            }
-                            cv
-                        }
-                        None => {
-                            return Err(gerr("Invalid alba type row order, missing stuff"));
-                        }
-                    };
-                    data.insert(value.0.clone(), column_value);
-                }
-                Row { data }
-            },
+        args.file.lock().await.read_exact_at(&mut buffer, offset)?;
+        let container = container.lock().await;
+        let row_content = match visible_row(&container, args.snapshot, row_index, &buffer).await {
+            Ok(Some(row_content)) => row_content,
+            Ok(None) => continue,
             Err(e) => {
                 logerr!("Error deserializing row at offset {}: {}", offset, e);
                 return Err(e);
             }
         };
-        if args.conditions.row_match(&row).unwrap(){
-            rows.push((row, *i));
+        drop(container);
+        if args.conditions.row_match(&checked_row(&headers, &row_content)?)?{
+            collector.push(row_content);
+            if collector.is_full(){
+                break;
+            }
         }
         
     }
     
-    drop(file);
-    
 
     
     
     let mut query = Query::new(args.container_values.iter().map(|f| f.1.clone()).collect());
-    let headers = container.column_names();
-    let mut header_map = HashMap::new();
-    for i in headers.iter().enumerate(){
-        header_map.insert(i.1,i.0);
-    }
-    let v = container.columns();
-    for i in rows {
-        let mut r = v.clone();
-        for i in i.0.data.iter(){
-            if let Some(a) = header_map.get(i.0){
-                r.insert(*a, i.1.to_owned());
-            }
-        }
-        query.rows.1.push(r);
-        
-    }
+    query.rows.0 = columns;
+    query.rows.1 = collector.finish();
 
     
     Ok(query)
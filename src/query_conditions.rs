@@ -1,10 +1,10 @@
-use std::{collections::HashMap, io::{self, Error, ErrorKind}, mem::discriminant, ops::{Range, RangeInclusive}};
+use std::{cmp::Ordering, collections::{BTreeSet, HashMap}, io::{self, Error, ErrorKind}, mem::discriminant, ops::{Range, RangeInclusive}};
 
 use aes_gcm::aead::consts::U9223372036854775808;
 use ahash::AHashMap;
 use regex::{Regex, Replacer};
 
-use crate::{alba_types::AlbaTypes, gerr, indexing::GetIndex, lexer_functions::Token, loginfo, query::PrimitiveQueryConditions, row::Row};
+use crate::{alba_types::{compare_decimals, decimal_to_float, parse_date, parse_decimal, parse_timestamp, rescale_decimal, AlbaTypes, DECIMAL_MAX_PRECISION, MICROS_PER_DAY}, arithmetic::Expression, gerr, indexing::{GetIndex, Indexing, Search}, lexer_functions::Token, loginfo, query::{ConditionExpression, PrimitiveQueryConditions}, row::Row};
 
 
 fn string_to_char(s: String) -> Result<char, io::Error> {
@@ -16,22 +16,29 @@ fn string_to_char(s: String) -> Result<char, io::Error> {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-enum LogicalGate{
-    And,
-    Or,
-}
-
 #[derive(Clone)]
 pub struct QueryConditionAtom{
     column : String,
     operator : Operator,
     value : AlbaTypes,
+    // index keys of the value rounded down and up to the encoding of the column, they only differ
+    // for a decimal with more digits than the column keeps and for a timestamp compared with a Date column
+    keys : (u64,u64),
+}
+#[derive(Clone)]
+enum ConditionNode{
+    Atom(QueryConditionAtom),
+    /// Both sides are computed from the row before comparing
+    Compare(Expression, Operator, Expression),
+    Not(Box<ConditionNode>),
+    And(Vec<ConditionNode>),
+    Or(Vec<ConditionNode>),
 }
 #[derive(Clone,Default)]
 pub struct QueryConditions{
-    primary_key : Option<String>,
-    chain : Vec<(QueryConditionAtom,Option<LogicalGate>)>
+    /// Columns that have an index, in the order of `Container::indexes`
+    indexed_columns : Vec<String>,
+    root : Option<ConditionNode>
 }
 
 fn gather_regex<'a>(regex_map: &'a mut HashMap<String, Regex>, key: String) -> Result<&'a Regex, Error> {
@@ -51,12 +58,107 @@ pub enum QueryIndexType {
     Strict(u64),
     Range(Range<u64>),
     InclusiveRange(RangeInclusive<u64>), 
+    /// Disjoint key ranges whose offsets are merged, a range of a single key is probed as `Strict`
+    Union(Vec<RangeInclusive<u64>>),
+}
+
+impl QueryIndexType{
+    pub async fn search(self, indexing : &Indexing) -> Result<BTreeSet<u64>,Error>{
+        match self{
+            QueryIndexType::Strict(t) => indexing.search(t).await,
+            QueryIndexType::Range(t) => indexing.search(t).await,
+            QueryIndexType::InclusiveRange(t) => indexing.search(t).await,
+            QueryIndexType::Union(ranges) => {
+                let mut offsets = BTreeSet::new();
+                for range in ranges{
+                    let found = if range.start() == range.end(){
+                        indexing.search(*range.start()).await?
+                    }else{
+                        indexing.search(range).await?
+                    };
+                    offsets.extend(found);
+                }
+                Ok(offsets)
+            },
+        }
+    }
+}
+
+/// Equality of two numbers of different types, like the Int 2 and the Bigint 2 an expression can produce, or of a Date and a Timestamp.
+fn numeric_equal(a : &AlbaTypes, b : &AlbaTypes) -> Option<bool>{
+    match (a, b){
+        (AlbaTypes::Int(_) | AlbaTypes::Bigint(_), AlbaTypes::Int(_) | AlbaTypes::Bigint(_)) => Some(as_bigint(a) == as_bigint(b)),
+        (AlbaTypes::Float(x), AlbaTypes::Int(_) | AlbaTypes::Bigint(_)) => Some(*x == as_bigint(b) as f64),
+        (AlbaTypes::Int(_) | AlbaTypes::Bigint(_), AlbaTypes::Float(y)) => Some(as_bigint(a) as f64 == *y),
+        (AlbaTypes::Timestamp(_) | AlbaTypes::Date(_), AlbaTypes::Timestamp(_) | AlbaTypes::Date(_)) => Some(a.as_timestamp() == b.as_timestamp()),
+        (AlbaTypes::Decimal(..), AlbaTypes::Float(y)) | (AlbaTypes::Float(y), AlbaTypes::Decimal(..)) => Some(a.as_decimal().or(b.as_decimal()).map(|(v, s)| decimal_to_float(v, s)) == Some(*y)),
+        (AlbaTypes::Decimal(..), _) | (_, AlbaTypes::Decimal(..)) => Some(compare_decimals(a.as_decimal()?, b.as_decimal()?) == Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn as_bigint(value : &AlbaTypes) -> i64{
+    match value{
+        AlbaTypes::Int(v) => *v as i64,
+        AlbaTypes::Bigint(v) => *v,
+        _ => 0,
+    }
+}
+
+/// Index keys of the decimal `value` of scale `from` in a column of scale `to`, rounded down and up
+/// when the column keeps fewer digits.
+fn decimal_keys(value : i128, from : u8, to : u8) -> (u64,u64){
+    let key = |v : i128| AlbaTypes::Decimal(v, DECIMAL_MAX_PRECISION, to).get_index();
+    if to >= from{
+        let scaled = rescale_decimal(value, from, to).unwrap_or(if value < 0 { i128::MIN } else { i128::MAX });
+        return (key(scaled), key(scaled))
+    }
+    let divisor = 10i128.pow((from - to) as u32);
+    let floor = value.div_euclid(divisor);
+    (key(floor), key(if value.rem_euclid(divisor) == 0 { floor } else { floor + 1 }))
+}
+
+/// Index keys of the days a timestamp compared with a Date column falls between, the same day when it is a midnight.
+fn date_keys(timestamp : i64) -> (u64,u64){
+    let day = timestamp.div_euclid(MICROS_PER_DAY) as i32;
+    let next = if timestamp.rem_euclid(MICROS_PER_DAY) == 0 { day } else { day.saturating_add(1) };
+    (AlbaTypes::Date(day).get_index(), AlbaTypes::Date(next).get_index())
+}
+
+/// Sorted, disjoint and inclusive ranges of index keys
+type KeyRanges = Vec<(u64,u64)>;
+
+fn union_key_ranges(mut ranges : KeyRanges) -> KeyRanges{
+    ranges.sort_unstable();
+    let mut merged : KeyRanges = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges{
+        match merged.last_mut(){
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn intersect_key_ranges(a : &[(u64,u64)], b : &[(u64,u64)]) -> KeyRanges{
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len(){
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start <= end{
+            result.push((start, end));
+        }
+        if a[i].1 < b[j].1 { i += 1 } else { j += 1 }
+    }
+    result
 }
 
 #[derive(Debug)]
 pub enum QueryType{
     Scan,
-    Indexed(QueryIndexType),
+    /// Position of the index to use in `Container::indexes` and the keys to look for
+    Indexed(usize,QueryIndexType),
 }
 
 #[derive(Clone,Debug)]
@@ -70,613 +172,657 @@ enum Operator{
     Different,
     StringContains,
     StringCaseInsensitiveContains,
-    StringRegularExpression
+    StringRegularExpression,
+    ArrayContains,
+    IsNull,
+    IsNotNull,
 }
 impl Operator{
-    fn get_range(&self,ind : u64) -> LogicCell{
+    /// Inclusive range of index keys that can hold the rows matching `key` through this operator, None when the operator can't use the index.
+    /// Strict comparisons stay inclusive, since different values may share the same key.
+    fn key_bounds(&self,(low, high) : (u64,u64)) -> Option<(u64,u64)>{
         match self{
-            Operator::Equal | Operator::StrictEqual => {((ind,ind),(false,false),true)},
-            Operator::Greater => {((ind,0),(false,true),false)},
-            Operator::Lower => {((0,ind),(true,false),false)},
-            Operator::GreaterEquality => {((ind,0),(false,true),true)},
-            Operator::LowerEquality => {((0,ind),(true,false),true)},
-            Operator::Different => {((0,0),(false,false),false)},
-            Operator::StringContains => {((0,0),(false,false),false)},
-            Operator::StringCaseInsensitiveContains => {((0,0),(false,false),false)},
-            Operator::StringRegularExpression => {((0,0),(false,false),false)},
+            Operator::Equal | Operator::StrictEqual => Some((low,high)),
+            Operator::Greater | Operator::GreaterEquality => Some((low,u64::MAX)),
+            Operator::Lower | Operator::LowerEquality => Some((0,high)),
+            _ => None,
         }
     }
 }
 
-type LogicCell = ((u64,u64),(bool,bool),bool);
-// ranges | infinity<bool> | InclusiveRange
-
-/*  stuff I wanted to ask
-- can cpu be processed in gpu even while with horrible performance?
-*/
 impl QueryConditions{
-    pub fn from_primitive_conditions(primitive_conditions : PrimitiveQueryConditions, column_properties : &HashMap<String,AlbaTypes>,primary_key : String) -> Result<Self,Error>{
-        let mut chain : Vec<(QueryConditionAtom,Option<LogicalGate>)> = Vec::new();
-        let condition_chunk = primitive_conditions.0;
-        let condition_logical_gates_vec = primitive_conditions.1;
-        let mut condition_logical_gates = AHashMap::new();
-        for i in condition_logical_gates_vec{
-            condition_logical_gates.insert(i.0, match i.1{
-                'a'|'A' => LogicalGate::And,
-                'o'|'O' => LogicalGate::Or,
-                _ => return  Err(gerr("Failed to load LogicalGate, invalid token."))
-            });
-        }
-        for (index,value) in condition_chunk.iter().enumerate(){
-            let value = value.to_owned();
-            
-            let column = if let Token::String(name) = value.0{
-                name
-            }else{
-                return Err(gerr("Failed to get QueryConditions, but failed to gather the column_name."))
-            };
-            
-            let operator = if let Token::Operator(operator_name) = value.1{
-                match operator_name.as_str(){
-                    "=" => Operator::Equal,
-                    "==" => Operator::StrictEqual,
-                    ">=" => Operator::GreaterEquality,
-                    "<=" => Operator::LowerEquality,
-                    ">" => Operator::Greater,
-                    "<" => Operator::Lower,
-                    "!=" => Operator::Different,
-                    "&>" => Operator::StringContains,
-                    "&&>" => Operator::StringCaseInsensitiveContains,
-                    "&&&>" => Operator::StringRegularExpression,
-                    _ => {
-                        return Err(gerr("Failed to get operator, invalid token contant."))
+    pub fn from_primitive_conditions(primitive_conditions : PrimitiveQueryConditions, column_properties : &HashMap<String,AlbaTypes>,indexed_columns : Vec<String>) -> Result<Self,Error>{
+        let root = match primitive_conditions{
+            Some(expression) => Some(Self::node(expression, column_properties)?),
+            None => None,
+        };
+        Ok(QueryConditions { root, indexed_columns })
+    }
+    fn node(expression : ConditionExpression, column_properties : &HashMap<String,AlbaTypes>) -> Result<ConditionNode,Error>{
+        Ok(match expression{
+            ConditionExpression::Condition(column, operator, value) => ConditionNode::Atom(Self::atom((column, operator, value), column_properties)?),
+            // IN is a chain of equalities and BETWEEN a pair of inclusive bounds, the planner sees through both
+            ConditionExpression::In(column, values) => ConditionNode::Or(values.into_iter()
+                .map(|v| Ok(ConditionNode::Atom(Self::atom((column.clone(), Token::Operator("=".to_string()), v), column_properties)?)))
+                .collect::<Result<_,Error>>()?),
+            ConditionExpression::Between(column, low, high) => ConditionNode::And(vec![
+                ConditionNode::Atom(Self::atom((column.clone(), Token::Operator(">=".to_string()), low), column_properties)?),
+                ConditionNode::Atom(Self::atom((column, Token::Operator("<=".to_string()), high), column_properties)?),
+            ]),
+            ConditionExpression::Compare(left, operator, right) => {
+                for column in left.columns().into_iter().chain(right.columns()){
+                    if !column_properties.contains_key(column){
+                        return Err(gerr(&format!("Failed to generate QueryConditions, there is no column named {}", column)))
                     }
                 }
-            }else{
-                return Err(gerr("Failed to get operator, invalid token,"))
-            };
+                for column in left.json_documents().into_iter().chain(right.json_documents()){
+                    if !matches!(column_properties.get(column), Some(AlbaTypes::Json(_))){
+                        return Err(gerr(&format!("-> and ->> can only be used on JSON columns, {} is not one", column)))
+                    }
+                }
+                match Self::operator(operator)?{
+                    Operator::IsNull | Operator::IsNotNull => return Err(gerr("IS NULL can't be used with an arithmetic expression")),
+                    operator => ConditionNode::Compare(left, operator, right),
+                }
+            },
+            ConditionExpression::Not(inner) => ConditionNode::Not(Box::new(Self::node(*inner, column_properties)?)),
+            ConditionExpression::And(items) => ConditionNode::And(items.into_iter().map(|i| Self::node(i, column_properties)).collect::<Result<_,_>>()?),
+            ConditionExpression::Or(items) => ConditionNode::Or(items.into_iter().map(|i| Self::node(i, column_properties)).collect::<Result<_,_>>()?),
+        })
+    }
+    fn operator(token : Token) -> Result<Operator,Error>{
+        if let Token::Operator(operator_name) = token{
+            Ok(match operator_name.as_str(){
+                "=" => Operator::Equal,
+                "==" => Operator::StrictEqual,
+                ">=" => Operator::GreaterEquality,
+                "<=" => Operator::LowerEquality,
+                ">" => Operator::Greater,
+                "<" => Operator::Lower,
+                "!=" => Operator::Different,
+                "&>" => Operator::StringContains,
+                "&&>" => Operator::StringCaseInsensitiveContains,
+                "&&&>" => Operator::StringRegularExpression,
+                "CONTAINS" => Operator::ArrayContains,
+                "IS NULL" => Operator::IsNull,
+                "IS NOT NULL" => Operator::IsNotNull,
+                _ => return Err(gerr("Failed to get operator, invalid token contant.")),
+            })
+        }else{
+            Err(gerr("Failed to get operator, invalid token,"))
+        }
+    }
 
-            let column_value = if let Some(column_type) = column_properties.get(&column){
-                match column_type{
-                    AlbaTypes::Text(_) => {
-                        if let Token::String(string) = value.2{
-                            AlbaTypes::Text(string)
-                        }else {
-                            return Err(gerr("No string found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Int(_) => {
-                        if let Token::Int(number) = value.2{
-                            AlbaTypes::Int(number as i32)
-                        }else {
-                            return Err(gerr("No integer found in the ComparisionToken"))
-                        }
-                    },
-                    AlbaTypes::Bigint(_) => {
-                        if let Token::Int(number) = value.2{
-                            AlbaTypes::Bigint(number)
-                        }else {
-                            return Err(gerr("No integer found in the ComparisionToken"))
+    fn atom(value : (Token,Token,Token), column_properties : &HashMap<String,AlbaTypes>) -> Result<QueryConditionAtom,Error>{
+        let column = if let Token::String(name) = value.0{
+            name
+        }else{
+            return Err(gerr("Failed to get QueryConditions, but failed to gather the column_name."))
+        };
+        
+        let operator = Self::operator(value.1)?;
+
+        if let Operator::IsNull | Operator::IsNotNull = operator{
+            if !column_properties.contains_key(&column){
+                return Err(gerr("Failed to generate QueryConditions, that happened because no column_property has been found with the given column-names"))
+            }
+            return Ok(QueryConditionAtom{column,operator,value:AlbaTypes::NONE,keys:(0,0)})
+        }
+        if let Token::Keyword(k) = &value.2 && k == "NULL"{
+            return Err(gerr(&format!("A comparison with NULL never matches, use '{}' IS NULL or '{}' IS NOT NULL instead",column,column)))
+        }
+        if let Operator::ArrayContains = operator && !matches!(column_properties.get(&column), Some(AlbaTypes::Array(..))){
+            return Err(gerr(&format!("CONTAINS can only be used on ARRAY columns, {} is not one",column)))
+        }
+
+        let column_value = if let Some(column_type) = column_properties.get(&column){
+            match column_type{
+                AlbaTypes::Text(_) => {
+                    if let Token::String(string) = value.2{
+                        AlbaTypes::Text(string)
+                    }else {
+                        return Err(gerr("No string found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Int(_) => {
+                    if let Token::Int(number) = value.2{
+                        match i32::try_from(number){
+                            Ok(number) => AlbaTypes::Int(number),
+                            Err(_) if matches!(operator, Operator::Equal | Operator::StrictEqual) => return Err(gerr(&format!("{} is out of the range of the INT column {}", number, column))),
+                            // the rows compare with the literal itself, it shares the key encoding of the INT values
+                            Err(_) => AlbaTypes::Bigint(number),
                         }
+                    }else {
+                        return Err(gerr("No integer found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Bigint(_) => {
+                    if let Token::Int(number) = value.2{
+                        AlbaTypes::Bigint(number)
+                    }else {
+                        return Err(gerr("No integer found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Float(_) => {
+                    if let Token::Decimal(number, scale) = value.2{
+                        AlbaTypes::Float(decimal_to_float(number, scale))
+                    }else if let Token::Float(number) = value.2{
+                        AlbaTypes::Float(number)
+                    }else {
+                        return Err(gerr("No float found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Bool(_) => {
+                    if let Token::Bool(bool) = value.2{
+                        AlbaTypes::Bool(bool)
+                    }else {
+                        return Err(gerr("No bool found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Char(_) => {
+                    if let Token::String(char) = value.2{
+                        AlbaTypes::Char(string_to_char(char)?)
+                    }else {
+                        return Err(gerr("No char found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::NanoString(_) => {
+                    if let Token::String(mut nano_string) = value.2{
+                        nano_string.truncate(10);
+                        AlbaTypes::NanoString(nano_string)
+                    }else {
+                        return Err(gerr("No nano_string found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::SmallString(_) => {
+                    if let Token::String(mut small_string) = value.2{
+                        small_string.truncate(100);
+                        AlbaTypes::SmallString(small_string)
+                    }else {
+                        return Err(gerr("No small_string found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::MediumString(_) => {
+                    if let Token::String(mut medium_string) = value.2{
+                        medium_string.truncate(500);
+                        AlbaTypes::SmallString(medium_string)
+                    }else {
+                        return Err(gerr("No medium_string found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::BigString(_) => {
+                    if let Token::String(mut big_string) = value.2{
+                        big_string.truncate(2000);
+                        AlbaTypes::SmallString(big_string)
+                    }else {
+                        return Err(gerr("No big_string found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::LargeString(_) => {
+                    if let Token::String(mut large_string) = value.2{
+                        large_string.truncate(3000);
+                        AlbaTypes::SmallString(large_string)
+                    }else {
+                        return Err(gerr("No large_string found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::NanoBytes(_) => {
+                    if let Token::Bytes(mut nano_bytes) = value.2{
+                        nano_bytes.truncate(10);
+                        AlbaTypes::NanoBytes(nano_bytes)
+                    }else {
+                        return Err(gerr("No nano_bytes found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::SmallBytes(_) => {
+                    if let Token::Bytes(mut small_bytes) = value.2{
+                        small_bytes.truncate(1000);
+                        AlbaTypes::SmallBytes(small_bytes)
+                    }else {
+                        return Err(gerr("No small_bytes found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::MediumBytes(_) => {
+                    if let Token::Bytes(mut medium_bytes) = value.2{
+                        medium_bytes.truncate(10000);
+                        AlbaTypes::MediumBytes(medium_bytes)
+                    }else {
+                        return Err(gerr("No medium_bytes found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::BigSBytes(_) => {
+                    if let Token::Bytes(mut big_bytes) = value.2{
+                        big_bytes.truncate(100000);
+                        AlbaTypes::BigSBytes(big_bytes)
+                    }else {
+                        return Err(gerr("No big_bytes found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::LargeBytes(_) => {
+                    if let Token::Bytes(mut large_bytes) = value.2{
+                        large_bytes.truncate(1000000);
+                        AlbaTypes::BigSBytes(large_bytes)
+                    }else {
+                        return Err(gerr("No large_bytes found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Timestamp(_) | AlbaTypes::Date(_) => {
+                    let temporal = match value.2{
+                        token @ (Token::Timestamp(_) | Token::Date(_)) => AlbaTypes::try_from(token).map_err(gerr)?,
+                        Token::String(s) => match parse_date(s.trim()){
+                            Some(date) => AlbaTypes::Date(date),
+                            None => AlbaTypes::Timestamp(parse_timestamp(s.trim()).ok_or(gerr(&format!("The value compared with {} is not an ISO-8601 date or timestamp", column)))?),
+                        },
+                        _ => return Err(gerr("No timestamp or date found in the ComparisionToken"))
+                    };
+                    // a Date column compared with a timestamp keeps its time of the day, both sides compare as timestamps
+                    match (column_type, temporal){
+                        (AlbaTypes::Date(_), timestamp @ AlbaTypes::Timestamp(_)) => timestamp,
+                        (_, temporal) => column_type.try_from_existing(temporal)?,
+                    }
+                },
+                // the value keeps its own scale, compare_decimals orders decimals of any scale
+                AlbaTypes::Decimal(..) => {
+                    let decimal = match value.2{
+                        Token::Decimal(v, s) => Some((v, s)),
+                        Token::Int(i) => Some((i as i128, 0)),
+                        Token::Float(f) => parse_decimal(&f.to_string()),
+                        Token::String(s) => parse_decimal(s.trim()),
+                        _ => return Err(gerr("No decimal found in the ComparisionToken"))
+                    };
+                    match decimal{
+                        Some((v, s)) => AlbaTypes::Decimal(v, DECIMAL_MAX_PRECISION, s),
+                        None => return Err(gerr(&format!("The value compared with {} is not a decimal of at most {} digits", column, DECIMAL_MAX_PRECISION)))
+                    }
+                },
+                AlbaTypes::Json(_) => {
+                    match value.2{
+                        // string operations look at the text of the document
+                        Token::String(text) if matches!(operator, Operator::StringContains | Operator::StringCaseInsensitiveContains | Operator::StringRegularExpression) => AlbaTypes::Text(text),
+                        token @ Token::String(_) => column_type.try_from_existing(AlbaTypes::try_from(token).map_err(gerr)?)?,
+                        _ => return Err(gerr("No json found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Uuid(_) => {
+                    match value.2{
+                        token @ (Token::Uuid(_) | Token::String(_)) => column_type.try_from_existing(AlbaTypes::try_from(token).map_err(gerr)?)?,
+                        _ => return Err(gerr("No uuid found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::Array(_, element) => {
+                    if !matches!(operator, Operator::ArrayContains){
+                        return Err(gerr(&format!("An ARRAY column is matched with CONTAINS, like '{}' CONTAINS <value>",column)))
+                    }
+                    match value.2{
+                        token @ (Token::Int(_) | Token::Float(_) | Token::Decimal(..) | Token::String(_)) => AlbaTypes::from_id(*element)?.try_from_existing(AlbaTypes::try_from(token).map_err(gerr)?)?,
+                        _ => return Err(gerr("No array element found in the ComparisionToken"))
+                    }
+                },
+                AlbaTypes::NONE => {
+                    return Err(gerr("Failed to extract the value from the column_properties"))
+                },
+            } 
+        }else{
+            return Err(gerr("Failed to generate QueryConditions, that happened because no column_property has been found with the given column-names"))
+        };
+
+        // comparisons need the value in the same shape as the stored one, fixed size strings and bytes are padded
+        let column_value = match operator{
+            Operator::Equal | Operator::StrictEqual | Operator::Different | Operator::Greater |
+            Operator::GreaterEquality | Operator::Lower | Operator::LowerEquality => match column_properties.get(&column){
+                Some(AlbaTypes::Decimal(..)) | None => column_value,
+                Some(AlbaTypes::Date(_)) if matches!(column_value, AlbaTypes::Timestamp(_)) => column_value,
+                Some(AlbaTypes::Int(_)) if matches!(column_value, AlbaTypes::Bigint(_)) => column_value,
+                Some(column_type) => column_type.try_from_existing(column_value)?,
+            },
+            _ => column_value,
+        };
+        let keys = match (column_properties.get(&column), &column_value){
+            (Some(AlbaTypes::Decimal(_, _, scale)), AlbaTypes::Decimal(v, _, s)) => decimal_keys(*v, *s, *scale),
+            (Some(AlbaTypes::Date(_)), AlbaTypes::Timestamp(t)) => date_keys(*t),
+            _ => (column_value.get_index(), column_value.get_index()),
+        };
+
+        Ok(QueryConditionAtom{column,operator,value:column_value,keys})
+    }
+    pub fn row_match(&self, row: &Row) -> Result<bool, Error> {
+        let root = match &self.root{
+            Some(root) => root,
+            None => return Ok(true),
+        };
+        let mut regex_cache: AHashMap<String, Regex> = AHashMap::new();
+        Ok(Self::evaluate(root, row, &mut regex_cache)? == Some(true))
+    }
+    /// Three valued result of a condition, None when it is unknown because a compared value is NULL.
+    /// NOT of an unknown stays unknown, so `NOT 'a' > 1` doesn't match the rows where a is NULL.
+    fn evaluate(node : &ConditionNode, row : &Row, regex_cache : &mut AHashMap<String, Regex>) -> Result<Option<bool>, Error> {
+        match node{
+            ConditionNode::Atom(atom) => Self::atom_match(atom, row, regex_cache),
+            ConditionNode::Compare(left_expression, operator, right_expression) => {
+                let left = left_expression.evaluate(&row.data)?;
+                let right = right_expression.evaluate(&row.data)?;
+                if right == AlbaTypes::NONE{
+                    return Ok(None)
+                }
+                match Self::compare(operator, &left, &right, regex_cache){
+                    // a value read from a JSON document may have any type, one that can't be compared is unknown
+                    Err(_) if matches!(left_expression, Expression::JsonPath(..)) || matches!(right_expression, Expression::JsonPath(..)) => Ok(None),
+                    result => result,
+                }
+            },
+            ConditionNode::Not(inner) => Ok(Self::evaluate(inner, row, regex_cache)?.map(|b| !b)),
+            ConditionNode::And(items) => {
+                let mut result = Some(true);
+                for item in items{
+                    match Self::evaluate(item, row, regex_cache)?{
+                        Some(false) => return Ok(Some(false)),
+                        None => result = None,
+                        Some(true) => {},
+                    }
+                }
+                Ok(result)
+            },
+            ConditionNode::Or(items) => {
+                let mut result = Some(false);
+                for item in items{
+                    match Self::evaluate(item, row, regex_cache)?{
+                        Some(true) => return Ok(Some(true)),
+                        None => result = None,
+                        Some(false) => {},
+                    }
+                }
+                Ok(result)
+            },
+        }
+    }
+    fn atom_match(query_condition : &QueryConditionAtom, row : &Row, regex_cache : &mut AHashMap<String, Regex>) -> Result<Option<bool>, Error> {
+        let row_value = match row.data.get(&query_condition.column){
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        Self::compare(&query_condition.operator, row_value, &query_condition.value, regex_cache)
+    }
+    fn compare(operator : &Operator, row_value : &AlbaTypes, value : &AlbaTypes, regex_cache : &mut AHashMap<String, Regex>) -> Result<Option<bool>, Error> {
+        // NULL only satisfies IS NULL, every other comparison against it is unknown
+        let check = match (operator, row_value) {
+            (Operator::IsNull, v) => *v == AlbaTypes::NONE,
+            (Operator::IsNotNull, v) => *v != AlbaTypes::NONE,
+            (_, AlbaTypes::NONE) => return Ok(None),
+            _ => match operator {
+            Operator::IsNull | Operator::IsNotNull => unreachable!(),
+            Operator::Equal | Operator::StrictEqual => {
+                numeric_equal(row_value, value).unwrap_or(*value == *row_value)
+            },
+            Operator::Greater | Operator::GreaterEquality | Operator::Lower | Operator::LowerEquality => {
+                
+                
+                let opd = discriminant(operator);
+                let equality = (opd == discriminant(&Operator::GreaterEquality)) || 
+                              (opd == discriminant(&Operator::LowerEquality));
+                let lower = (opd == discriminant(&Operator::Lower)) || 
+                           (opd == discriminant(&Operator::LowerEquality));
+                
+                
+
+                let comparison_result = match (row_value, value) {
+                    (AlbaTypes::Int(x), AlbaTypes::Int(y)) => {
+                        
+                        let result = if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } };
+                        
+                        result
                     },
-                    AlbaTypes::Float(_) => {
-                        if let Token::Float(number) = value.2{
-                            AlbaTypes::Float(number)
-                        }else {
-                            return Err(gerr("No float found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Bigint(x), AlbaTypes::Bigint(y)) => {
+                        
+                        let result = if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } };
+                        result
                     },
-                    AlbaTypes::Bool(_) => {
-                        if let Token::Bool(bool) = value.2{
-                            AlbaTypes::Bool(bool)
-                        }else {
-                            return Err(gerr("No bool found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Float(x), AlbaTypes::Float(y)) => {
+                        
+                        let result = if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } };
+                        
+                        result
                     },
-                    AlbaTypes::Char(_) => {
-                        if let Token::String(char) = value.2{
-                            AlbaTypes::Char(string_to_char(char)?)
-                        }else {
-                            return Err(gerr("No char found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Int(x), AlbaTypes::Bigint(y)) => {
+                        let x_promoted = *x as i64;
+                        
+                        let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
+                        else { if equality { x_promoted >= *y } else { x_promoted > *y } };
+                        
+                        result
                     },
-                    AlbaTypes::NanoString(_) => {
-                        if let Token::String(mut nano_string) = value.2{
-                            nano_string.truncate(10);
-                            AlbaTypes::NanoString(nano_string)
-                        }else {
-                            return Err(gerr("No nano_string found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Bigint(x), AlbaTypes::Int(y)) => {
+                        let y_promoted = *y as i64;
+                        
+                        let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
+                        else { if equality { *x >= y_promoted } else { *x > y_promoted } };
+                        
+                        result
                     },
-                    AlbaTypes::SmallString(_) => {
-                        if let Token::String(mut small_string) = value.2{
-                            small_string.truncate(100);
-                            AlbaTypes::SmallString(small_string)
-                        }else {
-                            return Err(gerr("No small_string found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Int(x), AlbaTypes::Float(y)) => {
+                        let x_promoted = *x as f64;
+                        
+                        let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
+                        else { if equality { x_promoted >= *y } else { x_promoted > *y } };
+                        
+                        result
                     },
-                    AlbaTypes::MediumString(_) => {
-                        if let Token::String(mut medium_string) = value.2{
-                            medium_string.truncate(500);
-                            AlbaTypes::SmallString(medium_string)
-                        }else {
-                            return Err(gerr("No medium_string found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Float(x), AlbaTypes::Int(y)) => {
+                        let y_promoted = *y as f64;
+                        
+                        let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
+                        else { if equality { *x >= y_promoted } else { *x > y_promoted } };
+                        
+                        result
                     },
-                    AlbaTypes::BigString(_) => {
-                        if let Token::String(mut big_string) = value.2{
-                            big_string.truncate(2000);
-                            AlbaTypes::SmallString(big_string)
-                        }else {
-                            return Err(gerr("No big_string found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Bigint(x), AlbaTypes::Float(y)) => {
+                        let x_promoted = *x as f64;
+                        
+                        let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
+                        else { if equality { x_promoted >= *y } else { x_promoted > *y } };
+                        
+                        result
                     },
-                    AlbaTypes::LargeString(_) => {
-                        if let Token::String(mut large_string) = value.2{
-                            large_string.truncate(3000);
-                            AlbaTypes::SmallString(large_string)
-                        }else {
-                            return Err(gerr("No large_string found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Float(x), AlbaTypes::Bigint(y)) => {
+                        let y_promoted = *y as f64;
+                        
+                        let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
+                        else { if equality { *x >= y_promoted } else { *x > y_promoted } };
+                        
+                        result
                     },
-                    AlbaTypes::NanoBytes(_) => {
-                        if let Token::Bytes(mut nano_bytes) = value.2{
-                            nano_bytes.truncate(10);
-                            AlbaTypes::NanoBytes(nano_bytes)
-                        }else {
-                            return Err(gerr("No nano_bytes found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Text(x) | AlbaTypes::NanoString(x) | AlbaTypes::SmallString(x) | AlbaTypes::MediumString(x) | AlbaTypes::BigString(x) | AlbaTypes::LargeString(x),
+                     AlbaTypes::Text(y) | AlbaTypes::NanoString(y) | AlbaTypes::SmallString(y) | AlbaTypes::MediumString(y) | AlbaTypes::BigString(y) | AlbaTypes::LargeString(y)) => {
+                        if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } }
                     },
-                    AlbaTypes::SmallBytes(_) => {
-                        if let Token::Bytes(mut small_bytes) = value.2{
-                            small_bytes.truncate(1000);
-                            AlbaTypes::SmallBytes(small_bytes)
-                        }else {
-                            return Err(gerr("No small_bytes found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Char(x), AlbaTypes::Char(y)) => {
+                        if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } }
                     },
-                    AlbaTypes::MediumBytes(_) => {
-                        if let Token::Bytes(mut medium_bytes) = value.2{
-                            medium_bytes.truncate(10000);
-                            AlbaTypes::MediumBytes(medium_bytes)
-                        }else {
-                            return Err(gerr("No medium_bytes found in the ComparisionToken"))
-                        }
+                    // a Date is the start of its day
+                    (AlbaTypes::Timestamp(_) | AlbaTypes::Date(_), AlbaTypes::Timestamp(_) | AlbaTypes::Date(_)) => {
+                        let (x, y) = (row_value.as_timestamp(), value.as_timestamp());
+                        if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } }
                     },
-                    AlbaTypes::BigSBytes(_) => {
-                        if let Token::Bytes(mut big_bytes) = value.2{
-                            big_bytes.truncate(100000);
-                            AlbaTypes::BigSBytes(big_bytes)
-                        }else {
-                            return Err(gerr("No big_bytes found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Decimal(..), AlbaTypes::Decimal(..) | AlbaTypes::Int(_) | AlbaTypes::Bigint(_)) | (AlbaTypes::Int(_) | AlbaTypes::Bigint(_), AlbaTypes::Decimal(..)) => {
+                        let ordering = compare_decimals(row_value.as_decimal().unwrap_or_default(), value.as_decimal().unwrap_or_default());
+                        if lower { if equality { ordering.is_le() } else { ordering.is_lt() } } 
+                        else { if equality { ordering.is_ge() } else { ordering.is_gt() } }
                     },
-                    AlbaTypes::LargeBytes(_) => {
-                        if let Token::Bytes(mut large_bytes) = value.2{
-                            large_bytes.truncate(1000000);
-                            AlbaTypes::BigSBytes(large_bytes)
-                        }else {
-                            return Err(gerr("No large_bytes found in the ComparisionToken"))
-                        }
+                    (AlbaTypes::Decimal(x, _, s), AlbaTypes::Float(y)) | (AlbaTypes::Float(y), AlbaTypes::Decimal(x, _, s)) => {
+                        // x is the decimal on either side, the values go back to their sides
+                        let (x, y) = if let AlbaTypes::Decimal(..) = row_value { (decimal_to_float(*x, *s), *y) } else { (*y, decimal_to_float(*x, *s)) };
+                        if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } }
                     },
-                    AlbaTypes::NONE => {
-                        return Err(gerr("Failed to extract the value from the column_properties"))
+                    (AlbaTypes::Uuid(x), AlbaTypes::Uuid(y)) => {
+                        if lower { if equality { x <= y } else { x < y } } 
+                        else { if equality { x >= y } else { x > y } }
                     },
-                } 
-            }else{
-                return Err(gerr("Failed to generate QueryConditions, that happened because no column_property has been found with the given column-names"))
-            };
-
-            let gate = condition_logical_gates
-                .get(&index)
-                .map(|a| a.clone());
-
-            chain.push((QueryConditionAtom{column,operator,value:column_value},gate));
-        }
-        return Ok(QueryConditions { chain, primary_key : Some(primary_key)})
-    }
-    pub fn row_match(&self, row: &Row) -> Result<bool, Error> {
-        
-        
-        if self.chain.is_empty() {
-            
-            return Ok(true);
-        }
-        
-        let mut result = false;
-        let mut regex_cache: AHashMap<String, Regex> = AHashMap::new();
-        
-        
-    
-        for (index, (query_condition, logical_gate)) in self.chain.iter().enumerate() {
-            let column = &query_condition.column;
-            let value = &query_condition.value;
-            
-            
-            
-            let row_value = if let Some(val) = row.data.get(column) {
+                    _ => {
+                        
+                        return Err(gerr("Invalid type for numeric comparison"));
+                    }
+                };
                 
-                val
-            } else {
+                comparison_result
+            },
+            Operator::Different => {
+                !numeric_equal(row_value, value).unwrap_or(*value == *row_value)
+            },
+            Operator::StringContains | Operator::StringCaseInsensitiveContains => {
+                let case_insensitive = discriminant(operator) == 
+                                      discriminant(&Operator::StringCaseInsensitiveContains);
                 
-                continue;
-            };
-            
-            let check = match query_condition.operator {
-                Operator::Equal | Operator::StrictEqual => {
-                    
-                    
-                    let result = *value == *row_value;
-                    
-                    result
-                },
-                Operator::Greater | Operator::GreaterEquality | Operator::Lower | Operator::LowerEquality => {
-                    
-                    
-                    let opd = discriminant(&query_condition.operator);
-                    let equality = (opd == discriminant(&Operator::GreaterEquality)) || 
-                                  (opd == discriminant(&Operator::LowerEquality));
-                    let lower = (opd == discriminant(&Operator::Lower)) || 
-                               (opd == discriminant(&Operator::LowerEquality));
-                    
-                    
-    
-                    let comparison_result = match (row_value, value) {
-                        (AlbaTypes::Int(x), AlbaTypes::Int(y)) => {
-                            
-                            let result = if lower { if equality { x <= y } else { x < y } } 
-                            else { if equality { x >= y } else { x > y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Bigint(x), AlbaTypes::Bigint(y)) => {
-                            
-                            let result = if lower { if equality { x <= y } else { x < y } } 
-                            else { if equality { x >= y } else { x > y } };
-                            result
-                        },
-                        (AlbaTypes::Float(x), AlbaTypes::Float(y)) => {
-                            
-                            let result = if lower { if equality { x <= y } else { x < y } } 
-                            else { if equality { x >= y } else { x > y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Int(x), AlbaTypes::Bigint(y)) => {
-                            let x_promoted = *x as i64;
-                            
-                            let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
-                            else { if equality { x_promoted >= *y } else { x_promoted > *y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Bigint(x), AlbaTypes::Int(y)) => {
-                            let y_promoted = *y as i64;
-                            
-                            let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
-                            else { if equality { *x >= y_promoted } else { *x > y_promoted } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Int(x), AlbaTypes::Float(y)) => {
-                            let x_promoted = *x as f64;
-                            
-                            let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
-                            else { if equality { x_promoted >= *y } else { x_promoted > *y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Float(x), AlbaTypes::Int(y)) => {
-                            let y_promoted = *y as f64;
-                            
-                            let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
-                            else { if equality { *x >= y_promoted } else { *x > y_promoted } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Bigint(x), AlbaTypes::Float(y)) => {
-                            let x_promoted = *x as f64;
-                            
-                            let result = if lower { if equality { x_promoted <= *y } else { x_promoted < *y } } 
-                            else { if equality { x_promoted >= *y } else { x_promoted > *y } };
-                            
-                            result
-                        },
-                        (AlbaTypes::Float(x), AlbaTypes::Bigint(y)) => {
-                            let y_promoted = *y as f64;
-                            
-                            let result = if lower { if equality { *x <= y_promoted } else { *x < y_promoted } } 
-                            else { if equality { *x >= y_promoted } else { *x > y_promoted } };
-                            
-                            result
-                        },
-                        _ => {
-                            
-                            return Err(gerr("Invalid type for numeric comparison"));
-                        }
-                    };
-                    
-                    comparison_result
-                },
-                Operator::Different => {
-                    
-                    
-                    let result = *value != *row_value;
-                    
-                    result
-                },
-                Operator::StringContains | Operator::StringCaseInsensitiveContains => {
-                    let case_insensitive = discriminant(&query_condition.operator) == 
-                                          discriminant(&Operator::StringCaseInsensitiveContains);
-                    
-                    
-                    
-                    let row_string = match row_value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-                    
-                    let value_string = match value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-    
-                    let result = if case_insensitive {
+                
+                
+                let row_string = match row_value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) | AlbaTypes::Json(s) => s.to_string(),
+                    _ => {
                         
-                        row_string.to_lowercase().contains(&value_string.to_lowercase())
-                    } else {
+                        return Err(gerr("Invalid, the entered type cannot make string operations"));
+                    }
+                };
+                
+                let value_string = match value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
+                    _ => {
                         
-                        row_string.contains(&value_string)
-                    };
-                    
-                    
-                    result
-                },
-                Operator::StringRegularExpression => {
-                    
-                    
-                    let row_string = match row_value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
+                        return Err(gerr("Invalid, the entered type cannot make string operations"));
+                    }
+                };
+
+                let result = if case_insensitive {
                     
-                    let value_string = match value {
-                        AlbaTypes::Int(i) => i.to_string(),
-                        AlbaTypes::Bigint(i) => i.to_string(),
-                        AlbaTypes::Float(i) => i.to_string(),
-                        AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
-                        AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
-                        _ => {
-                            
-                            return Err(gerr("Invalid, the entered type cannot make string operations"));
-                        }
-                    };
-    
+                    row_string.to_lowercase().contains(&value_string.to_lowercase())
+                } else {
                     
-    
-                    let regex_result = if let Some(cached_regex) = regex_cache.get(&value_string) {
-                        
-                        let match_result = cached_regex.is_match(&row_string);
+                    row_string.contains(&value_string)
+                };
+                
+                
+                result
+            },
+            Operator::StringRegularExpression => {
+                
+                
+                let row_string = match row_value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) | AlbaTypes::Json(s) => s.to_string(),
+                    _ => {
                         
-                        match_result
-                    } else {
+                        return Err(gerr("Invalid, the entered type cannot make string operations"));
+                    }
+                };
+                
+                let value_string = match value {
+                    AlbaTypes::Int(i) => i.to_string(),
+                    AlbaTypes::Bigint(i) => i.to_string(),
+                    AlbaTypes::Float(i) => i.to_string(),
+                    AlbaTypes::Text(s) | AlbaTypes::NanoString(s) | AlbaTypes::SmallString(s) | AlbaTypes::MediumString(s) | 
+                    AlbaTypes::BigString(s) | AlbaTypes::LargeString(s) => s.to_string(),
+                    _ => {
                         
-                        let re = Regex::new(&value_string);
-                        match re {
-                            Ok(compiled_regex) => {
-                                let match_result = compiled_regex.is_match(&row_string);
-                                
-                                regex_cache.insert(value_string, compiled_regex);
-                                match_result
-                            },
-                            Err(e) => {
-                                
-                                return Err(gerr(&e.to_string()));
-                            }
-                        }
-                    };
-                    
-                    regex_result
-                }
-            };
-            
-            
-            
-            if let Some(gate) = logical_gate {
+                        return Err(gerr("Invalid, the entered type cannot make string operations"));
+                    }
+                };
+
                 
-                match gate {
-                    LogicalGate::And => {
-                        if !check {
+
+                let regex_result = if let Some(cached_regex) = regex_cache.get(&value_string) {
+                    
+                    let match_result = cached_regex.is_match(&row_string);
+                    
+                    match_result
+                } else {
+                    
+                    let re = Regex::new(&value_string);
+                    match re {
+                        Ok(compiled_regex) => {
+                            let match_result = compiled_regex.is_match(&row_string);
                             
-                            result = false;
-                            break;
-                        }
-                        
-                    },
-                    LogicalGate::Or => {
-                        if check {
+                            regex_cache.insert(value_string, compiled_regex);
+                            match_result
+                        },
+                        Err(e) => {
                             
-                            result = true;
-                            break;
+                            return Err(gerr(&e.to_string()));
                         }
-                        
                     }
-                }
-            } else {
+                };
                 
-                result = check;
-            }
-        }
-    
-        
-        Ok(result)
+                regex_result
+            },
+            Operator::ArrayContains => match row_value {
+                AlbaTypes::Array(items, _) => items.iter().any(|item| numeric_equal(item, value).unwrap_or(*item == *value)),
+                _ => return Err(gerr("CONTAINS can only be used on ARRAY columns")),
+            },
+            },
+        };
+
+        Ok(Some(check))
     }
-    pub fn query_type(&self) -> Result<QueryType, Error> {
-        loginfo!("Starting query type analysis");
-        
-        // Early return for scan conditions
-        if self.chain.is_empty() || self.primary_key.is_none() {
-            loginfo!("No chain or primary key found, defaulting to Scan");
-            return Ok(QueryType::Scan);
-        }
-        
-        let pk = self.primary_key.as_ref().unwrap();
-        loginfo!("Primary key: {:?}, chain length: {}", pk, self.chain.len());
-        
-        let mut range: LogicCell = ((0, 0), (false, false), false);
-        let l = self.chain.len();
-        let mut logic_cells: Vec<LogicCell> = Vec::with_capacity(l);
-        let mut gates: Vec<bool> = Vec::with_capacity(l);
-        let mut range_mutated = false;
-        
-        let ad = discriminant(&LogicalGate::And);
-        
-        // Process chain into logic cells and gates
-        loginfo!("Processing {} chain elements", self.chain.len());
-        for (idx, i) in self.chain.iter().enumerate() {
-            if i.0.column != *pk {
-                loginfo!("Chain[{}]: Column {:?} != primary key, skipping", idx, i.0.column);
-                logic_cells.push(((0, 0), (false, false), false));
-            } else {
-                let index = i.0.value.get_index();
-                let cell = i.0.operator.get_range(index);
-                loginfo!("Chain[{}]: Primary key match, operator range: {:?}", idx, cell);
-                if !range_mutated{
-                    range = cell.clone();
-                    range_mutated = true;
+    /// Ranges of keys the matching rows can have in an index over `column`, None when that index can't narrow the search.
+    fn key_ranges(&self, column : &str) -> Option<KeyRanges> {
+        self.root.as_ref().and_then(|root| Self::node_ranges(root, column))
+    }
+    fn node_ranges(node : &ConditionNode, column : &str) -> Option<KeyRanges> {
+        match node{
+            ConditionNode::Atom(atom) if atom.column == column => atom.operator.key_bounds(atom.keys).map(|b| vec![b]),
+            ConditionNode::Atom(_) | ConditionNode::Compare(..) | ConditionNode::Not(_) => None,
+            // every conjunct that can use the index narrows the keys down, the others are checked on the rows
+            ConditionNode::And(items) => items.iter()
+                .filter_map(|i| Self::node_ranges(i, column))
+                .reduce(|a, b| intersect_key_ranges(&a, &b)),
+            // a row matching a branch that can't use the index can be anywhere
+            ConditionNode::Or(items) => {
+                let mut ranges = Vec::new();
+                for item in items{
+                    ranges.extend(Self::node_ranges(item, column)?);
                 }
-                logic_cells.push(cell);
-            }
-            
-            if let Some(logic_gate) = i.1 {
-                let is_and = ad == discriminant(&logic_gate);
-                loginfo!("Chain[{}]: Logic gate: {:?} (is_and: {})", idx, logic_gate, is_and);
-                gates.push(is_and);
-            }
+                Some(union_key_ranges(ranges))
+            },
         }
-
-        loginfo!("Combining logic cells with gates");
-        for (idx, (cell, gate)) in logic_cells.iter().zip(gates.iter()).enumerate() {
-            loginfo!("Processing cell[{}]: {:?} with gate: {}", idx, cell, gate);
-            
-            if range == ((0, 0), (false, false), false) {
-                loginfo!("First valid range, setting initial: {:?}", cell);
-                range = *cell;
-                continue;
-            }
-            
-            if *gate { // AND operation
-                loginfo!("AND operation - intersecting ranges");
-                
-                // Handle null flag
-                if range.2 != cell.2 && cell.2 {
-                    loginfo!("Setting null flag from cell");
-                    range.2 = cell.2;
-                }
-                
-                // Intersect upper bound
-                if range.0.1 == 0 && range.1.1 == true && cell.0.1 != 0 && cell.1.1 == false {
-                    loginfo!("Updating upper bound from cell: {} -> {}", range.0.1, cell.0.1);
-                    range.0.1 = cell.0.1;
-                    range.1.1 = false;
-                }
-                
-                // Intersect lower bound
-                if range.0.0 == 0 && range.1.0 == true && cell.0.0 != 0 && cell.1.0 == false {
-                    loginfo!("Updating lower bound from cell: {} -> {}", range.0.0, cell.0.0);
-                    range.0.0 = cell.0.0;
-                    range.1.0 = false;
-                }
-            } else { // OR operation
-                loginfo!("OR operation - union of ranges");
-                
-                // Handle null flag
-                if range.2 || cell.2 {
-                    loginfo!("Setting null flag due to OR");
-                    range.2 = true;
-                }
-                
-                // Expand lower bound (take minimum)
-                if cell.0.0 != 0 && (range.0.0 == 0 || cell.0.0 < range.0.0) {
-                    loginfo!("Expanding lower bound: {} -> {}", range.0.0, cell.0.0);
-                    range.0.0 = cell.0.0;
-                    range.1.0 = cell.1.0;
-                }
-                
-                // Expand upper bound (take maximum)
-                if cell.0.1 != 0 && (range.0.1 == 0 || cell.0.1 > range.0.1) {
-                    loginfo!("Expanding upper bound: {} -> {}", range.0.1, cell.0.1);
-                    range.0.1 = cell.0.1;
-                    range.1.1 = cell.1.1;
+    }
+    /// Picks the index to search with, exact keys are preferred over ranges and the primary key over the other indexes.
+    pub fn query_type(&self) -> Result<QueryType, Error> {
+        let mut chosen : Option<(usize,KeyRanges)> = None;
+        let exact = |ranges : &KeyRanges| ranges.iter().all(|r| r.0 == r.1);
+        for (position, column) in self.indexed_columns.iter().enumerate(){
+            if let Some(ranges) = self.key_ranges(column){
+                let better = match &chosen{
+                    None => true,
+                    Some((_, current)) => !exact(current) && exact(&ranges),
+                };
+                if better{
+                    chosen = Some((position, ranges));
                 }
             }
-            
-            loginfo!("Range after processing cell[{}]: {:?}", idx, range);
-        }
-        
-        // Normalize range bounds
-        if range.0.0 == 0 && range.1.0 {
-            loginfo!("Normalizing lower bound to 0 (inclusive)");
-            range.0.0 = 0;
-            range.1.0 = false;
-        }
-        if range.0.1 == 0 && range.1.1 {
-            loginfo!("Normalizing upper bound to MAX (exclusive)");
-            range.0.1 = u64::MAX;
-            range.1.1 = false;
-        }
-        
-        loginfo!("Final range: {:?}", range);
-        
-        // Determine query type based on final range
-        if range == ((0, 0), (false, false), false) {
-            loginfo!("Empty range, returning Scan");
-            return Ok(QueryType::Scan);
         }
-        
-        if range.0.0 > range.0.1 {
-            loginfo!("Invalid range (lower > upper), returning Scan");
-            return Ok(QueryType::Scan);
-        }
-        
-        if range.2 && range.0.0 == range.0.1 {
-            loginfo!("Strict index query for value: {}", range.0.0);
-            return Ok(QueryType::Indexed(QueryIndexType::Strict(range.0.0)));
-        }
-        
-        if range.2 && range.0.0 != range.0.1 {
-            loginfo!("Inclusive range query: {}..={}", range.0.0, range.0.1);
-            return Ok(QueryType::Indexed(QueryIndexType::InclusiveRange(range.0.0..=range.0.1)));
-        }
-        
-        if !range.2 && range.0.0 != range.0.1 {
-            loginfo!("Exclusive range query: {}..{}", range.0.0, range.0.1);
-            return Ok(QueryType::Indexed(QueryIndexType::Range(range.0.0..range.0.1)));
-        }
-        
-        loginfo!("Fallback to Scan query type");
-        Ok(QueryType::Scan)
+        loginfo!("Chosen index and key ranges: {:?}", chosen);
+        Ok(match chosen{
+            None => QueryType::Scan,
+            Some((index, ranges)) => QueryType::Indexed(index, match ranges.as_slice(){
+                [] => QueryIndexType::Range(0..0),
+                [(start, end)] if start == end => QueryIndexType::Strict(*start),
+                [(start, end)] => QueryIndexType::InclusiveRange(*start..=*end),
+                _ => QueryIndexType::Union(ranges.into_iter().map(|(start, end)| start..=end).collect()),
+            }),
+        })
     }
 }
\ No newline at end of file
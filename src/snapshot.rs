@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+
+/// Commit timestamps of a database and the snapshots being read at.
+/// Every commit gets the next timestamp, a snapshot sees the commits up to the timestamp it was taken at
+/// and the versions of the rows they replaced, see `Container::version_at`.
+#[derive(Debug, Default)]
+pub struct SnapshotClock{
+    committed : u64,
+    // snapshot timestamp -> count of the statements and transactions reading at it
+    active : BTreeMap<u64, usize>,
+}
+
+impl SnapshotClock{
+    /// Registers a snapshot of the last commit, it must be given back to `release`.
+    pub fn take(&mut self) -> u64{
+        self.hold(self.committed)
+    }
+    /// Registers one more reader of a snapshot already taken, it must be given back to `release` too.
+    pub fn hold(&mut self, snapshot : u64) -> u64{
+        *self.active.entry(snapshot).or_default() += 1;
+        snapshot
+    }
+    pub fn release(&mut self, snapshot : u64){
+        if let Some(count) = self.active.get_mut(&snapshot){
+            *count -= 1;
+            if *count == 0{
+                self.active.remove(&snapshot);
+            }
+        }
+    }
+    /// Timestamp of the next commit, it becomes visible once published.
+    pub fn next(&self) -> u64{
+        self.committed + 1
+    }
+    pub fn publish(&mut self, timestamp : u64){
+        self.committed = timestamp;
+    }
+    /// Oldest timestamp still read at, the versions replaced up to it are needed by no snapshot.
+    pub fn horizon(&self) -> u64{
+        self.active.keys().next().copied().unwrap_or(self.committed)
+    }
+}
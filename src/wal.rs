@@ -0,0 +1,289 @@
+use std::{fs::{File, OpenOptions}, io::{Error, Read, Seek, SeekFrom, Write}};
+use crate::{gerr, logerr};
+
+const WAL_MAGIC : &[u8;4] = b"TWAL";
+pub const WAL_FILE : &str = ".tytodb-wal";
+
+#[derive(Debug, Clone)]
+pub enum WalOperation{
+    /// Raw bytes written at an absolute offset of the container file
+    Write{offset : u64, bytes : Vec<u8>},
+    IndexAdd{key : u64, offset : u64},
+    IndexRemove{key : u64, offset : u64},
+    /// Content of a file in the rf/ reference folder
+    ReferenceWrite{name : String, bytes : Vec<u8>},
+    ReferenceRemove{name : String},
+    /// Entries of the secondary indexes created with CREATE INDEX, addressed by the index name
+    SecondaryIndexAdd{index : String, key : u64, offset : u64},
+    SecondaryIndexRemove{index : String, key : u64, offset : u64},
+}
+
+#[derive(Debug, Clone)]
+pub struct WalEntry{
+    pub container : String,
+    pub operations : Vec<WalOperation>
+}
+
+/// Per-database write-ahead log.
+/// Every commit is appended as a single record ( magic | body length | body | blake3(body) ),
+/// synced to disk and only then applied to the container and index files.
+/// A record that is torn or fails the checksum is discarded on recovery.
+#[derive(Debug)]
+pub struct WriteAheadLog{
+    file : File,
+}
+
+fn take<'a>(body : &'a [u8], cursor : &mut usize, len : usize) -> Result<&'a [u8],Error>{
+    // the length may be garbage read from a torn record
+    if len > body.len().saturating_sub(*cursor){
+        return Err(gerr("Malformed write-ahead log record"))
+    }
+    let slice = &body[*cursor..*cursor+len];
+    *cursor += len;
+    Ok(slice)
+}
+fn take_u64(body : &[u8], cursor : &mut usize) -> Result<u64,Error>{
+    let mut b = [0u8;8];
+    b.copy_from_slice(take(body, cursor, 8)?);
+    Ok(u64::from_be_bytes(b))
+}
+fn take_u16(body : &[u8], cursor : &mut usize) -> Result<u16,Error>{
+    let mut b = [0u8;2];
+    b.copy_from_slice(take(body, cursor, 2)?);
+    Ok(u16::from_be_bytes(b))
+}
+
+fn encode_record(entries : &[WalEntry]) -> Vec<u8>{
+    let mut body : Vec<u8> = Vec::new();
+    body.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for entry in entries{
+        let name = entry.container.as_bytes();
+        body.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        body.extend_from_slice(name);
+        body.extend_from_slice(&(entry.operations.len() as u64).to_be_bytes());
+        for op in entry.operations.iter(){
+            match op{
+                WalOperation::Write { offset, bytes } => {
+                    body.push(0);
+                    body.extend_from_slice(&offset.to_be_bytes());
+                    body.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+                    body.extend_from_slice(bytes);
+                },
+                WalOperation::IndexAdd { key, offset } => {
+                    body.push(1);
+                    body.extend_from_slice(&key.to_be_bytes());
+                    body.extend_from_slice(&offset.to_be_bytes());
+                },
+                WalOperation::IndexRemove { key, offset } => {
+                    body.push(2);
+                    body.extend_from_slice(&key.to_be_bytes());
+                    body.extend_from_slice(&offset.to_be_bytes());
+                },
+                WalOperation::ReferenceWrite { name, bytes } => {
+                    body.push(3);
+                    body.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                    body.extend_from_slice(name.as_bytes());
+                    body.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+                    body.extend_from_slice(bytes);
+                },
+                WalOperation::ReferenceRemove { name } => {
+                    body.push(4);
+                    body.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                    body.extend_from_slice(name.as_bytes());
+                },
+                WalOperation::SecondaryIndexAdd { index, key, offset } | WalOperation::SecondaryIndexRemove { index, key, offset } => {
+                    body.push(if let WalOperation::SecondaryIndexAdd { .. } = op { 5 } else { 6 });
+                    body.extend_from_slice(&(index.len() as u16).to_be_bytes());
+                    body.extend_from_slice(index.as_bytes());
+                    body.extend_from_slice(&key.to_be_bytes());
+                    body.extend_from_slice(&offset.to_be_bytes());
+                },
+            }
+        }
+    }
+    let mut record = Vec::with_capacity(body.len() + 44);
+    record.extend_from_slice(WAL_MAGIC);
+    record.extend_from_slice(&(body.len() as u64).to_be_bytes());
+    record.extend_from_slice(&body);
+    record.extend_from_slice(blake3::hash(&body).as_bytes());
+    record
+}
+
+fn take_string(body : &[u8], cursor : &mut usize) -> Result<String,Error>{
+    let len = take_u16(body, cursor)? as usize;
+    match String::from_utf8(take(body, cursor, len)?.to_vec()){
+        Ok(a) => Ok(a),
+        Err(e) => Err(gerr(&e.to_string()))
+    }
+}
+
+fn decode_body(body : &[u8]) -> Result<Vec<WalEntry>,Error>{
+    let mut cursor = 0;
+    let count = take_u16(body, &mut cursor)?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count{
+        let container = take_string(body, &mut cursor)?;
+        let op_count = take_u64(body, &mut cursor)?;
+        let mut operations = Vec::new();
+        for _ in 0..op_count{
+            let tag = take(body, &mut cursor, 1)?[0];
+            operations.push(match tag{
+                0 => {
+                    let offset = take_u64(body, &mut cursor)?;
+                    let len = take_u64(body, &mut cursor)? as usize;
+                    WalOperation::Write { offset, bytes: take(body, &mut cursor, len)?.to_vec() }
+                },
+                1 => WalOperation::IndexAdd { key: take_u64(body, &mut cursor)?, offset: take_u64(body, &mut cursor)? },
+                2 => WalOperation::IndexRemove { key: take_u64(body, &mut cursor)?, offset: take_u64(body, &mut cursor)? },
+                3 => {
+                    let name = take_string(body, &mut cursor)?;
+                    let len = take_u64(body, &mut cursor)? as usize;
+                    WalOperation::ReferenceWrite { name, bytes: take(body, &mut cursor, len)?.to_vec() }
+                },
+                4 => WalOperation::ReferenceRemove { name: take_string(body, &mut cursor)? },
+                5 => WalOperation::SecondaryIndexAdd { index: take_string(body, &mut cursor)?, key: take_u64(body, &mut cursor)?, offset: take_u64(body, &mut cursor)? },
+                6 => WalOperation::SecondaryIndexRemove { index: take_string(body, &mut cursor)?, key: take_u64(body, &mut cursor)?, offset: take_u64(body, &mut cursor)? },
+                x => return Err(gerr(&format!("Unknown write-ahead log operation: {}",x)))
+            });
+        }
+        entries.push(WalEntry { container, operations });
+    }
+    Ok(entries)
+}
+
+impl WriteAheadLog{
+    pub fn open(location : &str) -> Result<Self,Error>{
+        let path = format!("{}/{}",location,WAL_FILE);
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        Ok(WriteAheadLog { file })
+    }
+
+    /// Durably appends one commit record, it returns only after the record reached the disk.
+    pub fn append(&mut self, entries : &[WalEntry]) -> Result<(),Error>{
+        let record = encode_record(entries);
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&record)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Drops every record, must only be called once all of them were applied and synced.
+    pub fn checkpoint(&mut self) -> Result<(),Error>{
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Returns the complete records of the log in the order they were written.
+    /// Reading stops at the first torn or corrupted record, since it belongs to a commit that never finished.
+    pub fn recover(&mut self) -> Result<Vec<Vec<WalEntry>>,Error>{
+        let mut raw = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut raw)?;
+        let mut records = Vec::new();
+        let mut cursor = 0;
+        let mut valid = 0;
+        while cursor < raw.len(){
+            let magic = match take(&raw, &mut cursor, 4){ Ok(a) => a, Err(_) => break };
+            if magic != WAL_MAGIC{
+                break;
+            }
+            let len = match take_u64(&raw, &mut cursor){ Ok(a) => a as usize, Err(_) => break };
+            let body = match take(&raw, &mut cursor, len){ Ok(a) => a, Err(_) => break };
+            let checksum = match take(&raw, &mut cursor, 32){ Ok(a) => a, Err(_) => break };
+            if blake3::hash(body).as_bytes() != checksum{
+                break;
+            }
+            records.push(decode_body(body)?);
+            valid = cursor;
+        }
+        if valid < raw.len(){
+            logerr!("Discarded {} bytes of an incomplete write-ahead log record",raw.len()-valid);
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn temp_location(name : &str) -> String{
+        let path = std::env::temp_dir().join(format!("tytodb-wal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn entry(container : &str, offset : u64) -> WalEntry{
+        WalEntry{
+            container : container.to_string(),
+            operations : vec![
+                WalOperation::Write { offset, bytes: vec![7u8; 16] },
+                WalOperation::IndexAdd { key: offset, offset },
+                WalOperation::SecondaryIndexAdd { index: "indexes/c/i".to_string(), key: 3, offset },
+            ]
+        }
+    }
+
+    fn offsets(records : &[Vec<WalEntry>]) -> Vec<u64>{
+        records.iter().map(|entries| match entries[0].operations[0]{
+            WalOperation::Write { offset, .. } => offset,
+            _ => panic!("the first operation is a write"),
+        }).collect()
+    }
+
+    #[test]
+    fn recovers_every_complete_record_in_order(){
+        let location = temp_location("complete");
+        let mut wal = WriteAheadLog::open(&location).unwrap();
+        wal.append(&[entry("c", 10)]).unwrap();
+        wal.append(&[entry("c", 20), entry("d", 30)]).unwrap();
+        let records = WriteAheadLog::open(&location).unwrap().recover().unwrap();
+        assert_eq!(offsets(&records), vec![10, 20]);
+        assert_eq!(records[1][1].container, "d");
+        wal.checkpoint().unwrap();
+        assert!(WriteAheadLog::open(&location).unwrap().recover().unwrap().is_empty());
+    }
+
+    #[test]
+    fn discards_a_truncated_last_record(){
+        let location = temp_location("truncated");
+        let mut wal = WriteAheadLog::open(&location).unwrap();
+        wal.append(&[entry("c", 10)]).unwrap();
+        let first = wal.file.metadata().unwrap().len();
+        wal.append(&[entry("c", 20)]).unwrap();
+        let full = wal.file.metadata().unwrap().len();
+        // cut inside the header, the body and the checksum of the second record
+        for len in [first + 2, first + 20, full - 1]{
+            wal.file.set_len(len).unwrap();
+            let records = WriteAheadLog::open(&location).unwrap().recover().unwrap();
+            assert_eq!(offsets(&records), vec![10]);
+        }
+        // a torn header whose length is garbage
+        wal.file.set_len(first).unwrap();
+        wal.file.seek(SeekFrom::End(0)).unwrap();
+        wal.file.write_all(WAL_MAGIC).unwrap();
+        wal.file.write_all(&u64::MAX.to_be_bytes()).unwrap();
+        let records = WriteAheadLog::open(&location).unwrap().recover().unwrap();
+        assert_eq!(offsets(&records), vec![10]);
+    }
+
+    #[test]
+    fn stops_at_a_record_with_a_wrong_checksum(){
+        let location = temp_location("checksum");
+        let mut wal = WriteAheadLog::open(&location).unwrap();
+        wal.append(&[entry("c", 10)]).unwrap();
+        let first = wal.file.metadata().unwrap().len();
+        wal.append(&[entry("c", 20)]).unwrap();
+        wal.append(&[entry("c", 30)]).unwrap();
+        // flips a byte of the body of the second record
+        let mut byte = [0u8];
+        wal.file.seek(SeekFrom::Start(first + 14)).unwrap();
+        wal.file.read_exact(&mut byte).unwrap();
+        wal.file.seek(SeekFrom::Start(first + 14)).unwrap();
+        wal.file.write_all(&[byte[0] ^ 0xff]).unwrap();
+        let records = WriteAheadLog::open(&location).unwrap().recover().unwrap();
+        assert_eq!(offsets(&records), vec![10]);
+    }
+}